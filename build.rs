@@ -1,4 +1,18 @@
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    tonic_build::compile_protos("proto/blind_auth.proto")?;
+    // without the net feature there is no tonic, only the messages are built
+    let net = std::env::var_os("CARGO_FEATURE_NET").is_some();
+    // the JSON gateway reads and writes the BlindAuth messages with serde,
+    // fields left out of a request take their proto3 defaults
+    tonic_build::configure()
+        .build_server(net)
+        .build_client(net)
+        .type_attribute(
+            ".blind_auth",
+            "#[derive(serde::Serialize, serde::Deserialize)] #[serde(default)]",
+        )
+        .compile(&["proto/blind_auth.proto"], &["proto"])?;
+    if net {
+        tonic_build::compile_protos("proto/envoy/service/auth/v3/external_auth.proto")?;
+    }
     Ok(())
 }
@@ -0,0 +1,145 @@
+use std::fmt;
+use std::time::Duration;
+use tonic::Status;
+
+use crate::store::models::{User, UserStatus};
+
+// Rules for who can log in and how long keys stay valid
+#[derive(Clone, Debug)]
+pub struct AccountPolicy {
+    // failed proofs in a row before the account is locked, 0 never locks
+    pub max_failed_logins: u32,
+    pub lockout: Duration,
+    // logins with an older key succeed but ask the prover to rotate it
+    pub key_rotation_age: Option<Duration>,
+    // keys registered from now on stop working after this long
+    pub key_lifetime: Option<Duration>,
+    // new users start out pending until an admin enables them
+    pub require_approval: bool,
+}
+
+impl Default for AccountPolicy {
+    fn default() -> Self {
+        Self {
+            max_failed_logins: 5,
+            lockout: Duration::from_secs(15 * 60),
+            key_rotation_age: None,
+            key_lifetime: None,
+            require_approval: false,
+        }
+    }
+}
+
+// Why a registered user can not log in
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AccountError {
+    Disabled,
+    // unix seconds the lock ends at
+    Locked(u64),
+    Pending,
+    KeyExpired,
+}
+
+impl fmt::Display for AccountError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AccountError::Disabled => write!(f, "user is disabled"),
+            AccountError::Locked(until) => write!(f, "user is locked until {}", until),
+            AccountError::Pending => write!(f, "user is pending approval"),
+            AccountError::KeyExpired => write!(
+                f,
+                "key has expired, rotate it on a live session or ask an admin to delete the user"
+            ),
+        }
+    }
+}
+
+impl From<AccountError> for Status {
+    fn from(err: AccountError) -> Self {
+        match err {
+            AccountError::Disabled | AccountError::Locked(_) => {
+                Status::permission_denied(err.to_string())
+            }
+            AccountError::Pending | AccountError::KeyExpired => {
+                Status::failed_precondition(err.to_string())
+            }
+        }
+    }
+}
+
+impl AccountPolicy {
+    // Status and key lifetime of a freshly registered user
+    pub fn new_user(&self, user: &mut User, now: u64) {
+        user.status = match self.require_approval {
+            true => UserStatus::Pending,
+            false => UserStatus::Active,
+        };
+        user.created_at = now;
+        self.new_key(user, now);
+    }
+
+    // Starts the lifetime of a newly registered key
+    pub fn new_key(&self, user: &mut User, now: u64) {
+        user.key_created_at = now;
+        user.key_expires_at = self.key_lifetime.map(|lifetime| now + lifetime.as_secs());
+    }
+
+    // Counts a failed proof, returns true if it locked the account. A lock
+    // that ran out is lifted first, so the count starts over and the next
+    // max_failed_logins failures lock the account again.
+    pub fn record_failure(&self, user: &mut User, now: u64) -> bool {
+        if user.status == UserStatus::Locked && check_status(user, now).is_ok() {
+            user.status = UserStatus::Active;
+            user.locked_until = None;
+            user.failed_logins = 0;
+        }
+        user.failed_logins += 1;
+        if self.max_failed_logins == 0
+            || user.failed_logins < self.max_failed_logins
+            || user.status != UserStatus::Active
+        {
+            return false;
+        }
+        user.status = UserStatus::Locked;
+        user.locked_until = Some(now + self.lockout.as_secs());
+        true
+    }
+
+    pub fn key_rotation_due(&self, user: &User, now: u64) -> bool {
+        self.key_rotation_age
+            .is_some_and(|age| now >= user.key_created_at + age.as_secs())
+    }
+}
+
+// Whether the user's status lets it log in. Locks that ran out count as
+// lifted, the next successful login clears them.
+pub fn check_status(user: &User, now: u64) -> Result<(), AccountError> {
+    match user.status {
+        UserStatus::Active => Ok(()),
+        UserStatus::Disabled => Err(AccountError::Disabled),
+        UserStatus::Pending => Err(AccountError::Pending),
+        UserStatus::Locked => match user.locked_until {
+            Some(until) if now >= until => Ok(()),
+            until => Err(AccountError::Locked(until.unwrap_or(u64::MAX))),
+        },
+    }
+}
+
+// check_status plus the key expiry
+pub fn check_login(user: &User, now: u64) -> Result<(), AccountError> {
+    check_status(user, now)?;
+    match user.key_expires_at {
+        Some(expires_at) if now >= expires_at => Err(AccountError::KeyExpired),
+        _ => Ok(()),
+    }
+}
+
+// Records a successful login, which also ends an expired lock
+pub fn record_login(user: &mut User, now: u64) {
+    user.last_login_at = Some(now);
+    user.failed_logins = 0;
+    if user.status == UserStatus::Locked {
+        user.status = UserStatus::Active;
+        user.locked_until = None;
+    }
+}
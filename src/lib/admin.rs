@@ -0,0 +1,396 @@
+use log::info;
+use sha2::{Digest, Sha256};
+use tonic::service::Interceptor;
+use tonic::{Request, Response, Status};
+
+use crate::blind_auth_api::blind_auth_admin_server::BlindAuthAdmin;
+use crate::blind_auth_api::{
+    AdminSession, AdminUser, AdminUserRequest, DeleteUserResponse, ExportUsersRequest,
+    ExportUsersResponse, ExportedUser, ImportUsersRequest, ImportUsersResponse,
+    ListSessionsResponse, ListUsersRequest, ListUsersResponse, RevokeSessionRequest,
+    RevokeSessionsResponse, SetUserAttributesRequest, SetUserRolesRequest, SetUserScopesRequest,
+};
+use crate::clock::unix_seconds;
+use crate::store::file::UserRecord;
+use crate::store::models::{User, UserStatus};
+use crate::store::store::DataStore;
+use crate::validation::{
+    validate_attributes, validate_roles, validate_scopes, validate_session_id, validate_user_id,
+};
+
+pub const DEFAULT_PAGE_SIZE: usize = 100;
+pub const MAX_PAGE_SIZE: usize = 1000;
+
+// Operator access to the users and sessions in the store
+pub struct AdminServer {
+    pub store: DataStore,
+}
+
+impl AdminServer {
+    pub fn new(store: DataStore) -> Self {
+        Self { store }
+    }
+
+    fn user(&self, request: &AdminUserRequest) -> Result<String, Status> {
+        let user_id = validate_user_id(&request.user)?;
+        match self.store.get_user(&user_id) {
+            Some(_) => Ok(user_id),
+            None => Err(Status::not_found("user not found")),
+        }
+    }
+
+    // Admin changes are on disk before they are answered
+    async fn update<F>(&self, user: &str, update: F) -> Result<Response<AdminUser>, Status>
+    where
+        F: FnOnce(&mut User),
+    {
+        let user_id = validate_user_id(user)?;
+        let user = self
+            .store
+            .update_user(&user_id, update)
+            .ok_or_else(|| Status::not_found("user not found"))?;
+        self.store.persist_users().await;
+        Ok(Response::new(admin_user(&user)))
+    }
+
+    async fn set_status(
+        &self,
+        request: &AdminUserRequest,
+        status: UserStatus,
+    ) -> Result<AdminUser, Status> {
+        let user_id = self.user(request)?;
+        let user = self
+            .store
+            .update_user(&user_id, |user| {
+                user.status = status;
+                user.failed_logins = 0;
+                user.locked_until = None;
+            })
+            .ok_or_else(|| Status::not_found("user not found"))?;
+        self.store.persist_users().await;
+        Ok(admin_user(&user))
+    }
+}
+
+#[tonic::async_trait]
+impl BlindAuthAdmin for AdminServer {
+    async fn list_users(
+        &self,
+        request: Request<ListUsersRequest>,
+    ) -> Result<Response<ListUsersResponse>, Status> {
+        let request = request.into_inner();
+        let page_size = match request.page_size as usize {
+            0 => DEFAULT_PAGE_SIZE,
+            size => size.min(MAX_PAGE_SIZE),
+        };
+        let users = self.store.list_users(&request.page_token, page_size);
+        // a full page may be followed by more
+        let next_page_token = match users.last() {
+            Some(last) if users.len() == page_size => last.id.clone(),
+            _ => String::new(),
+        };
+        Ok(Response::new(ListUsersResponse {
+            users: users.iter().map(admin_user).collect(),
+            next_page_token,
+        }))
+    }
+
+    async fn get_user(
+        &self,
+        request: Request<AdminUserRequest>,
+    ) -> Result<Response<AdminUser>, Status> {
+        let user_id = validate_user_id(&request.get_ref().user)?;
+        let user = self
+            .store
+            .get_user(&user_id)
+            .ok_or_else(|| Status::not_found("user not found"))?;
+        Ok(Response::new(admin_user(&user)))
+    }
+
+    async fn disable_user(
+        &self,
+        request: Request<AdminUserRequest>,
+    ) -> Result<Response<AdminUser>, Status> {
+        info!("disable_user req: {:?}", request.get_ref());
+        let user = self
+            .set_status(request.get_ref(), UserStatus::Disabled)
+            .await?;
+        let revoked = self.store.remove_user_sessions(&user.id);
+        info!("disabled {} and revoked {} sessions", user.id, revoked);
+        Ok(Response::new(user))
+    }
+
+    async fn enable_user(
+        &self,
+        request: Request<AdminUserRequest>,
+    ) -> Result<Response<AdminUser>, Status> {
+        info!("enable_user req: {:?}", request.get_ref());
+        Ok(Response::new(
+            self.set_status(request.get_ref(), UserStatus::Active)
+                .await?,
+        ))
+    }
+
+    async fn delete_user(
+        &self,
+        request: Request<AdminUserRequest>,
+    ) -> Result<Response<DeleteUserResponse>, Status> {
+        info!("delete_user req: {:?}", request.get_ref());
+        let user_id = self.user(request.get_ref())?;
+        self.store.remove_user(&user_id);
+        self.store.persist_users().await;
+        let revoked = self.store.remove_user_sessions(&user_id);
+        Ok(Response::new(DeleteUserResponse {
+            revoked_sessions: revoked as u32,
+        }))
+    }
+
+    async fn set_user_roles(
+        &self,
+        request: Request<SetUserRolesRequest>,
+    ) -> Result<Response<AdminUser>, Status> {
+        info!("set_user_roles req: {:?}", request.get_ref());
+        let roles = validate_roles(&request.get_ref().roles)?;
+        self.update(&request.get_ref().user, |user| user.roles = roles)
+            .await
+    }
+
+    async fn set_user_scopes(
+        &self,
+        request: Request<SetUserScopesRequest>,
+    ) -> Result<Response<AdminUser>, Status> {
+        info!("set_user_scopes req: {:?}", request.get_ref());
+        let scopes = validate_scopes(&request.get_ref().scopes)?;
+        self.update(&request.get_ref().user, |user| user.scopes = scopes)
+            .await
+    }
+
+    async fn set_user_attributes(
+        &self,
+        request: Request<SetUserAttributesRequest>,
+    ) -> Result<Response<AdminUser>, Status> {
+        info!("set_user_attributes req: {:?}", request.get_ref());
+        let attributes = validate_attributes(&request.get_ref().attributes)?;
+        self.update(&request.get_ref().user, |user| user.attributes = attributes)
+            .await
+    }
+
+    async fn list_sessions(
+        &self,
+        request: Request<AdminUserRequest>,
+    ) -> Result<Response<ListSessionsResponse>, Status> {
+        let user_id = validate_user_id(&request.get_ref().user)?;
+        let mut sessions: Vec<AdminSession> = self
+            .store
+            .user_sessions(&user_id)
+            .into_iter()
+            .map(|(session, expires_at)| AdminSession {
+                id: session.id,
+                user: session.user_id,
+                audience: session.audience,
+                scopes: session.scopes,
+                key_bound: session.public_key.is_some() || session.key.is_some(),
+                channel_bound: session.channel_binding.is_some(),
+                expires_at: unix_seconds(expires_at),
+            })
+            .collect();
+        sessions.sort_by_key(|session| session.expires_at);
+        Ok(Response::new(ListSessionsResponse { sessions }))
+    }
+
+    async fn revoke_session(
+        &self,
+        request: Request<RevokeSessionRequest>,
+    ) -> Result<Response<RevokeSessionsResponse>, Status> {
+        info!("revoke_session req: {:?}", request.get_ref());
+        let session_id = validate_session_id(&request.get_ref().session_id)?;
+        let revoked = self.store.remove_session(&session_id);
+        Ok(Response::new(RevokeSessionsResponse {
+            revoked: revoked as u32,
+        }))
+    }
+
+    async fn revoke_all_sessions(
+        &self,
+        request: Request<AdminUserRequest>,
+    ) -> Result<Response<RevokeSessionsResponse>, Status> {
+        info!("revoke_all_sessions req: {:?}", request.get_ref());
+        let user_id = validate_user_id(&request.get_ref().user)?;
+        let revoked = self.store.remove_user_sessions(&user_id);
+        Ok(Response::new(RevokeSessionsResponse {
+            revoked: revoked as u32,
+        }))
+    }
+
+    async fn export_users(
+        &self,
+        _request: Request<ExportUsersRequest>,
+    ) -> Result<Response<ExportUsersResponse>, Status> {
+        info!("export_users req");
+        let users = self.store.list_users("", usize::MAX);
+        Ok(Response::new(ExportUsersResponse {
+            users: users
+                .iter()
+                .map(|user| ExportedUser::from(UserRecord::from(user)))
+                .collect(),
+        }))
+    }
+
+    async fn import_users(
+        &self,
+        request: Request<ImportUsersRequest>,
+    ) -> Result<Response<ImportUsersResponse>, Status> {
+        let request = request.into_inner();
+        info!("import_users req: {} users", request.users.len());
+        let mut users = Vec::with_capacity(request.users.len());
+        for exported in request.users {
+            let record = UserRecord::try_from(exported).map_err(Status::invalid_argument)?;
+            let user =
+                User::try_from(&record).map_err(|err| Status::invalid_argument(err.to_string()))?;
+            users.push(user);
+        }
+
+        // sessions were proven with the old keys, they go once the new keys
+        // are in place so no login can slip in between
+        let rekeyed: Vec<String> = users
+            .iter()
+            .filter(|user| {
+                self.store
+                    .get_user(&user.id)
+                    .is_some_and(|old| old.y1 != user.y1 || old.y2 != user.y2)
+            })
+            .map(|user| user.id.clone())
+            .collect();
+        let imported = users.len();
+        self.store.insert_users(users);
+        self.store.persist_users().await;
+        let revoked: usize = rekeyed
+            .iter()
+            .map(|id| self.store.remove_user_sessions(id))
+            .sum();
+        Ok(Response::new(ImportUsersResponse {
+            imported: imported as u32,
+            revoked_sessions: revoked as u32,
+        }))
+    }
+}
+
+impl From<UserRecord> for ExportedUser {
+    fn from(record: UserRecord) -> Self {
+        ExportedUser {
+            status: record.status.to_string(),
+            id: record.id,
+            y1: record.y1,
+            y2: record.y2,
+            created_at: record.created_at,
+            last_login_at: record.last_login_at.unwrap_or_default(),
+            key_created_at: record.key_created_at,
+            key_expires_at: record.key_expires_at.unwrap_or_default(),
+            locked_until: record.locked_until.unwrap_or_default(),
+            roles: record.roles,
+            scopes: record.scopes,
+            attributes: record.attributes.into_iter().collect(),
+        }
+    }
+}
+
+impl TryFrom<ExportedUser> for UserRecord {
+    type Error = String;
+
+    fn try_from(exported: ExportedUser) -> Result<Self, Self::Error> {
+        Ok(UserRecord {
+            status: exported.status.parse()?,
+            id: exported.id,
+            y1: exported.y1,
+            y2: exported.y2,
+            created_at: exported.created_at,
+            last_login_at: non_zero(exported.last_login_at),
+            key_created_at: exported.key_created_at,
+            key_expires_at: non_zero(exported.key_expires_at),
+            locked_until: non_zero(exported.locked_until),
+            roles: exported.roles,
+            scopes: exported.scopes,
+            attributes: exported.attributes.into_iter().collect(),
+        })
+    }
+}
+
+fn non_zero(timestamp: u64) -> Option<u64> {
+    (timestamp != 0).then_some(timestamp)
+}
+
+fn admin_user(user: &User) -> AdminUser {
+    AdminUser {
+        id: user.id.clone(),
+        status: user.status.to_string(),
+        created_at: user.created_at,
+        last_login_at: user.last_login_at.unwrap_or_default(),
+        key_created_at: user.key_created_at,
+        key_expires_at: user.key_expires_at.unwrap_or_default(),
+        failed_logins: user.failed_logins,
+        locked_until: user.locked_until.unwrap_or_default(),
+        roles: user.roles.clone(),
+        scopes: user.scopes.clone(),
+        attributes: user
+            .attributes
+            .iter()
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect(),
+    }
+}
+
+// Shortest admin token accepted, e.g. `openssl rand -hex 16`
+pub const MIN_ADMIN_TOKEN_LEN: usize = 32;
+
+// Gate for the admin service. Either requests carry the admin token as
+// "authorization: Bearer <token>", or the listener only accepts clients with
+// a certificate from the admin CA and every connection is trusted.
+#[derive(Clone)]
+pub struct AdminAuth {
+    token_hash: Option<[u8; 32]>,
+}
+
+impl AdminAuth {
+    // Refuses empty and short tokens, an empty token file would otherwise
+    // let "Bearer " in
+    pub fn token(token: &str) -> Result<Self, String> {
+        if token.is_empty() {
+            return Err("admin token is empty".to_string());
+        }
+        if token.len() < MIN_ADMIN_TOKEN_LEN {
+            return Err(format!(
+                "admin token is shorter than {} characters",
+                MIN_ADMIN_TOKEN_LEN
+            ));
+        }
+        Ok(Self {
+            token_hash: Some(Sha256::digest(token.as_bytes()).into()),
+        })
+    }
+
+    // Only for listeners that require client certificates
+    pub fn client_certificate() -> Self {
+        Self { token_hash: None }
+    }
+}
+
+impl Interceptor for AdminAuth {
+    fn call(&mut self, request: Request<()>) -> Result<Request<()>, Status> {
+        let expected = match &self.token_hash {
+            Some(expected) => expected,
+            None => return Ok(request),
+        };
+        let token = request
+            .metadata()
+            .get("authorization")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .ok_or_else(|| Status::unauthenticated("missing admin token"))?;
+        // comparing hashes keeps the comparison from leaking the token
+        let hash: [u8; 32] = Sha256::digest(token.as_bytes()).into();
+        if &hash != expected {
+            return Err(Status::unauthenticated("invalid admin token"));
+        }
+        Ok(request)
+    }
+}
@@ -1,33 +1,96 @@
+// handler helpers return tonic::Status, which is large but what callers need
+#![allow(clippy::result_large_err)]
+
 use crate::public_params::PublicParams;
 use log::info;
-use num_bigint::BigInt;
-use rand::Rng;
+use num_bigint::{BigInt, Sign};
+use rand::RngCore;
 
+#[cfg(feature = "net")]
+pub mod account;
+#[cfg(feature = "net")]
+pub mod admin;
+pub mod clock;
+#[cfg(feature = "constant-time")]
+pub mod ct;
+pub mod events;
+#[cfg(feature = "net")]
+pub mod ext_authz;
+#[cfg(feature = "net")]
+pub mod forward_auth;
+#[cfg(feature = "net")]
+pub mod gateway;
+pub mod group;
+#[cfg(feature = "net")]
+pub mod middleware;
+#[cfg(feature = "net")]
+pub mod pool;
+#[cfg(feature = "net")]
+pub mod pop;
 pub mod prover;
 pub mod public_params;
+#[cfg(feature = "net")]
+pub mod puzzle;
+pub mod rng;
+pub mod schnorr;
+pub mod session_key;
+#[cfg(feature = "net")]
+pub mod session_token;
+#[cfg(feature = "net")]
 pub mod store;
+#[cfg(feature = "net")]
+pub mod tls;
+pub mod transcript;
+pub mod validation;
+#[cfg(feature = "net")]
 pub mod verifier;
+#[cfg(feature = "wasm")]
+pub mod wasm;
 
-// bundles grpc package for proto
+// bundles grpc package for proto, only the messages without the net feature
 pub mod blind_auth_api {
-    tonic::include_proto!("blind_auth");
+    include!(concat!(env!("OUT_DIR"), "/blind_auth.rs"));
 }
 
-// Generates a random BigInt between min and max
-pub fn generate_randomness(min: &BigInt, max: &BigInt) -> BigInt {
-    let mut rng = rand::thread_rng();
-    let range: BigInt = max - min;
+// the subset of Envoy's ext_authz API the verifier implements
+#[cfg(feature = "net")]
+pub mod envoy_auth_api {
+    tonic::include_proto!("envoy.service.auth.v3");
+}
 
-    let random_bytes: Vec<u8> = (0..range.bits() / 8 + 1).map(|_| rng.gen()).collect();
-    let random_bigint = BigInt::from_bytes_le(num_bigint::Sign::Plus, &random_bytes);
+// Samples a uniformly random BigInt in [min, max) by rejection sampling
+pub fn random_in_range<R: RngCore + ?Sized>(rng: &mut R, min: &BigInt, max: &BigInt) -> BigInt {
+    assert!(min < max, "empty range");
+    let range: BigInt = max - min;
+    let bits = range.bits();
+    let mut bytes = vec![0u8; bits.div_ceil(8) as usize];
+    // mask off the excess bits of the leading byte so each draw succeeds with p > 1/2
+    let mask = 0xffu8 >> (bytes.len() as u64 * 8 - bits);
 
-    min + random_bigint % &range
+    loop {
+        rng.fill_bytes(&mut bytes);
+        bytes[0] &= mask;
+        let candidate = BigInt::from_bytes_be(Sign::Plus, &bytes);
+        if candidate < range {
+            return min + candidate;
+        }
+    }
 }
 
-// Generates a param pair p1, p2 where p1=(g^exponent)%p, p2=(h^exponent)%p
+// Generates a param pair p1, p2 where p1=(g^exponent)%p, p2=(h^exponent)%p.
+// The exponent is a secret (x or k), so this runs in constant time when the
+// constant-time feature is enabled.
 pub fn gen_params(exponent: &BigInt) -> (String, String) {
-    let p1 = PublicParams::g().modpow(exponent, &PublicParams::p());
-    let p2 = PublicParams::h().modpow(exponent, &PublicParams::p());
+    #[cfg(feature = "constant-time")]
+    let (p1, p2) = (
+        ct::pow_mod_p(PublicParams::g(), exponent),
+        ct::pow_mod_p(PublicParams::h(), exponent),
+    );
+    #[cfg(not(feature = "constant-time"))]
+    let (p1, p2) = (
+        PublicParams::g().modpow(exponent, PublicParams::p()),
+        PublicParams::h().modpow(exponent, PublicParams::p()),
+    );
 
     info!("generating p1 = {}, p2 = {}", p1, p2);
 
@@ -0,0 +1,81 @@
+use crypto_bigint::modular::constant_mod::{Residue, ResidueParams};
+use crypto_bigint::subtle::{ConstantTimeEq, ConstantTimeLess};
+use crypto_bigint::{Encoding, U2048};
+use num_bigint::{BigInt, BigUint, Sign};
+
+use crate::public_params::{P_PRIME, Q_SUBPRIME};
+
+// Constant time arithmetic on crypto-bigint's Montgomery residues. Used for
+// every operation that touches the secret x or the nonce k, num-bigint stays
+// in use for public values where it is faster.
+//
+// Inputs are converted through fixed size buffers and must be non negative
+// and below 2^2048.
+
+// The Montgomery constants are computed at compile time, on wasm32's 32 bit
+// limbs that runs past the const eval budget but finishes
+#[allow(long_running_const_eval)]
+mod modulus {
+    use super::{P_PRIME, Q_SUBPRIME};
+    use crypto_bigint::{impl_modulus, U2048};
+
+    impl_modulus!(ModP, U2048, P_PRIME);
+    impl_modulus!(ModQ, U2048, Q_SUBPRIME);
+}
+use modulus::{ModP, ModQ};
+
+type ResidueP = Residue<ModP, { ModP::LIMBS }>;
+type ResidueQ = Residue<ModQ, { ModQ::LIMBS }>;
+
+// base^exponent mod p
+pub fn pow_mod_p(base: &BigInt, exponent: &BigInt) -> BigInt {
+    let base = ResidueP::new(&to_uint(base));
+    from_uint(&base.pow(&to_uint(exponent)).retrieve())
+}
+
+// k - c * x mod q
+pub fn auth_secret(c: &BigInt, k: &BigInt, x: &BigInt) -> BigInt {
+    let c = ResidueQ::new(&to_uint(c));
+    let k = ResidueQ::new(&to_uint(k));
+    let x = ResidueQ::new(&to_uint(x));
+    from_uint(&(k - c * x).retrieve())
+}
+
+// v mod q
+pub fn reduce_mod_q(v: &BigInt) -> BigInt {
+    from_uint(&ResidueQ::new(&to_uint(v)).retrieve())
+}
+
+// v mod q as the rlen = 256 big endian octets of RFC 6979 int2octets
+pub fn int_to_octets_mod_q(v: &BigInt) -> [u8; U2048::BYTES] {
+    ResidueQ::new(&to_uint(v)).retrieve().to_be_bytes()
+}
+
+// RFC 6979 bits2int of the 256 octets t, the leftmost qlen bits, when it is
+// in [1, q). Only whether the candidate is accepted shows in the timing.
+pub fn bits2int_mod_q(t: &[u8]) -> Option<BigInt> {
+    let shift = U2048::BITS - ModQ::MODULUS.bits_vartime();
+    let candidate = U2048::from_be_slice(t).shr_vartime(shift);
+    let in_range = candidate.ct_lt(&ModQ::MODULUS) & !candidate.ct_eq(&U2048::ZERO);
+    bool::from(in_range).then(|| from_uint(&candidate))
+}
+
+// Writes all 32 words of v whatever its magnitude
+fn to_uint(v: &BigInt) -> U2048 {
+    assert!(v.sign() != Sign::Minus, "negative input");
+    assert!(
+        v.bits() <= U2048::BITS as u64,
+        "input is wider than 2048 bits"
+    );
+    let words = v.magnitude().to_u64_digits();
+    let mut padded = [0u8; U2048::BYTES];
+    for (i, chunk) in padded.rchunks_exact_mut(8).enumerate() {
+        let word = words.get(i).copied().unwrap_or(0);
+        chunk.copy_from_slice(&word.to_be_bytes());
+    }
+    U2048::from_be_bytes(padded)
+}
+
+fn from_uint(v: &U2048) -> BigInt {
+    BigInt::from_biguint(Sign::Plus, BigUint::from_bytes_be(&v.to_be_bytes()))
+}
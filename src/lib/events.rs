@@ -0,0 +1,49 @@
+use log::warn;
+use std::fmt;
+
+// Security relevant events, logged under the `security` target so they can be
+// routed separately from regular request logs.
+#[derive(Debug)]
+pub enum SecurityEvent<'a> {
+    // A prover sent a commitment (r1, r2) it already used for an earlier
+    // challenge. Answering two challenges with the same k leaks x.
+    CommitmentReuse {
+        user_id: &'a str,
+        commitment: &'a str,
+    },
+    // A user failed too many proofs in a row and can not log in until the
+    // lock ends or an admin lifts it
+    AccountLocked {
+        user_id: &'a str,
+        failed_logins: u32,
+        until: u64,
+    },
+}
+
+impl fmt::Display for SecurityEvent<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SecurityEvent::CommitmentReuse {
+                user_id,
+                commitment,
+            } => write!(
+                f,
+                "commitment reuse detected: user={} commitment={}",
+                user_id, commitment
+            ),
+            SecurityEvent::AccountLocked {
+                user_id,
+                failed_logins,
+                until,
+            } => write!(
+                f,
+                "account locked: user={} failed_logins={} until={}",
+                user_id, failed_logins, until
+            ),
+        }
+    }
+}
+
+pub fn emit(event: SecurityEvent) {
+    warn!(target: "security", "{}", event);
+}
@@ -0,0 +1,130 @@
+use log::debug;
+use std::collections::HashMap;
+use tonic::{Code, Request, Response, Status};
+
+use crate::envoy_auth_api::authorization_server::Authorization;
+use crate::envoy_auth_api::header_value_option::HeaderAppendAction;
+use crate::envoy_auth_api::{
+    self, check_response, CheckRequest, CheckResponse, DeniedHttpResponse, HeaderValue,
+    HeaderValueOption, HttpStatus, OkHttpResponse,
+};
+use crate::middleware::{
+    bearer, http_status, require_route, session_headers, AuthenticatedUser, SessionValidator,
+};
+
+// Envoy external authorization. Envoy calls Check with the headers of every
+// request to a protected route and only forwards the request when it carries
+// a valid session id or session token, adding the session to its headers.
+
+// Route settings read from the filter's context_extensions: the audience
+// sessions must have been granted, and comma separated scopes they need
+pub const AUDIENCE_EXTENSION: &str = "audience";
+pub const SCOPES_EXTENSION: &str = "scopes";
+
+pub struct ExtAuthzServer {
+    validator: SessionValidator,
+}
+
+impl ExtAuthzServer {
+    pub fn new(validator: SessionValidator) -> Self {
+        Self { validator }
+    }
+
+    async fn authorize(
+        &self,
+        headers: &HashMap<String, String>,
+        extensions: &HashMap<String, String>,
+    ) -> Result<AuthenticatedUser, Status> {
+        let credential = bearer(headers.get("authorization").map(String::as_str))?;
+        let user = self.validator.validate(credential).await?;
+        require_route(
+            &user,
+            extensions.get(AUDIENCE_EXTENSION).map(String::as_str),
+            extensions.get(SCOPES_EXTENSION).map(String::as_str),
+        )?;
+        Ok(user)
+    }
+}
+
+#[tonic::async_trait]
+impl Authorization for ExtAuthzServer {
+    // Denials are answers, not errors, Envoy treats errors as the
+    // authorization service being down
+    async fn check(
+        &self,
+        request: Request<CheckRequest>,
+    ) -> Result<Response<CheckResponse>, Status> {
+        let attributes = request.into_inner().attributes.unwrap_or_default();
+        let http = attributes
+            .request
+            .and_then(|request| request.http)
+            .unwrap_or_default();
+        let response = match self
+            .authorize(&http.headers, &attributes.context_extensions)
+            .await
+        {
+            Ok(user) => allowed(&user),
+            Err(status) => {
+                debug!("ext_authz denied {} {}: {}", http.method, http.path, status);
+                denied(&status)
+            }
+        };
+        Ok(Response::new(response))
+    }
+}
+
+// Sets the session headers, replacing any the client sent, and removes the
+// ones the session has no value for
+fn allowed(user: &AuthenticatedUser) -> CheckResponse {
+    let mut headers = vec![];
+    let mut headers_to_remove = vec![];
+    for (name, value) in session_headers(user) {
+        match value.is_empty() {
+            true => headers_to_remove.push(name.to_string()),
+            false => headers.push(header(name, &value)),
+        }
+    }
+    CheckResponse {
+        status: Some(envoy_auth_api::Status {
+            code: Code::Ok as i32,
+            message: String::new(),
+        }),
+        http_response: Some(check_response::HttpResponse::OkResponse(OkHttpResponse {
+            headers,
+            headers_to_remove,
+        })),
+    }
+}
+
+fn denied(status: &Status) -> CheckResponse {
+    let code = http_status(status.code());
+    let mut headers = vec![];
+    if status.code() == Code::Unauthenticated {
+        headers.push(header("www-authenticate", "Bearer"));
+    }
+    CheckResponse {
+        status: Some(envoy_auth_api::Status {
+            code: status.code() as i32,
+            message: status.message().to_string(),
+        }),
+        http_response: Some(check_response::HttpResponse::DeniedResponse(
+            DeniedHttpResponse {
+                status: Some(HttpStatus {
+                    code: code.as_u16().into(),
+                }),
+                headers,
+                body: status.message().to_string(),
+            },
+        )),
+    }
+}
+
+fn header(key: &str, value: &str) -> HeaderValueOption {
+    HeaderValueOption {
+        header: Some(HeaderValue {
+            key: key.to_string(),
+            value: value.to_string(),
+        }),
+        append_action: HeaderAppendAction::OverwriteIfExistsOrAdd as i32,
+    }
+}
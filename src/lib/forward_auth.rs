@@ -0,0 +1,98 @@
+use axum::extract::{Query, State};
+use axum::http::header::{AUTHORIZATION, COOKIE, WWW_AUTHENTICATE};
+use axum::http::{HeaderMap, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::any;
+use axum::Router;
+use serde::Deserialize;
+use tonic::{Code, Status};
+
+use crate::middleware::{
+    bearer, http_status, require_route, session_headers, AuthenticatedUser, SessionValidator,
+};
+
+// Forward auth for nginx auth_request and Traefik forwardAuth. The proxy
+// asks GET /auth with the headers of every request to a protected location,
+// and forwards the request when the answer is 200, copying the session
+// headers of the answer into it.
+
+// Cookie browsers can carry the session id or session token in
+pub const SESSION_COOKIE: &str = "blindauth_session";
+
+// Audience and comma separated scopes a location asks for, as query
+// parameters of the auth URL, e.g. /auth?audience=billing&scopes=invoices:read
+#[derive(Debug, Default, Deserialize)]
+pub struct RouteQuery {
+    pub audience: Option<String>,
+    pub scopes: Option<String>,
+}
+
+// nginx subrequests keep the method of the original request, so /auth
+// answers any method
+pub fn router(validator: SessionValidator) -> Router {
+    Router::new()
+        .route("/auth", any(auth))
+        .with_state(validator)
+}
+
+async fn auth(
+    State(validator): State<SessionValidator>,
+    Query(query): Query<RouteQuery>,
+    headers: HeaderMap,
+) -> Response {
+    match authorize(&validator, &query, &headers).await {
+        Ok(user) => allowed(&user),
+        Err(status) => denied(&status),
+    }
+}
+
+async fn authorize(
+    validator: &SessionValidator,
+    query: &RouteQuery,
+    headers: &HeaderMap,
+) -> Result<AuthenticatedUser, Status> {
+    let credential = credential(headers)?;
+    let user = validator.validate(credential).await?;
+    require_route(&user, query.audience.as_deref(), query.scopes.as_deref())?;
+    Ok(user)
+}
+
+// The bearer value of the authorization header, or the session cookie
+fn credential(headers: &HeaderMap) -> Result<&str, Status> {
+    if let Some(authorization) = headers.get(AUTHORIZATION) {
+        return bearer(authorization.to_str().ok());
+    }
+    headers
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|cookies| cookies.split(';'))
+        .filter_map(|cookie| cookie.trim().split_once('='))
+        .find(|(name, _)| *name == SESSION_COOKIE)
+        .map(|(_, value)| value)
+        .ok_or_else(|| Status::unauthenticated("missing bearer credential or session cookie"))
+}
+
+// Every session header is set, empty when the session has no value for it,
+// so that proxies copying them replace any the client sent
+fn allowed(user: &AuthenticatedUser) -> Response {
+    let mut response = StatusCode::OK.into_response();
+    for (name, value) in session_headers(user) {
+        // user ids, audiences, scopes and roles are validated to be
+        // printable ASCII
+        if let Ok(value) = HeaderValue::from_str(&value) {
+            response.headers_mut().insert(name, value);
+        }
+    }
+    response
+}
+
+fn denied(status: &Status) -> Response {
+    let mut response = (http_status(status.code()), status.message().to_string()).into_response();
+    if status.code() == Code::Unauthenticated {
+        response
+            .headers_mut()
+            .insert(WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+    }
+    response
+}
@@ -0,0 +1,183 @@
+use axum::extract::rejection::JsonRejection;
+use axum::extract::{ConnectInfo, Path, State};
+use axum::http::header::CONTENT_TYPE;
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use serde::Serialize;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tonic::{Request, Status};
+
+use crate::blind_auth_api::blind_auth_server::BlindAuth;
+use crate::blind_auth_api::{
+    AuthAnswerBatchRequest, AuthAnswerRequest, AuthChallengeRequest, CancelChallengeRequest,
+    ChallengeCommitRequest, ChallengeStatusRequest, PublicParamsRequest, RegisterRequest,
+    RotateKeyRequest, ValidateSessionRequest,
+};
+use crate::middleware::http_status;
+use crate::verifier::{AuthServer, PeerAddr};
+
+// JSON over HTTP for clients that can't use gRPC. Every route calls the
+// BlindAuth handler of the same AuthServer the gRPC service uses. Messages
+// are the proto messages with their snake_case field names, and errors are
+// {"code": <gRPC code>, "message": ...} with the matching HTTP status.
+
+// OpenAPI 3 description of the routes below
+pub const OPENAPI: &str = include_str!("../../proto/blind_auth.openapi.yaml");
+
+#[derive(Serialize)]
+struct ErrorBody {
+    code: i32,
+    message: String,
+}
+
+type Body<T> = Result<Json<T>, JsonRejection>;
+
+pub fn router(auth_server: Arc<AuthServer>) -> Router {
+    Router::new()
+        .route("/v1/public-params", get(public_params))
+        .route("/v1/register", post(register))
+        .route("/v1/keys/rotate", post(rotate_key))
+        .route("/v1/challenges", post(create_challenge))
+        .route("/v1/challenges/commit", post(commit_challenge))
+        .route(
+            "/v1/challenges/:auth_id",
+            get(challenge_status).delete(cancel_challenge),
+        )
+        .route("/v1/answers", post(verify_answer))
+        .route("/v1/answers/batch", post(verify_answers))
+        .route("/v1/sessions/validate", post(validate_session))
+        .route("/v1/openapi.yaml", get(openapi))
+        .with_state(auth_server)
+}
+
+async fn public_params(State(server): State<Arc<AuthServer>>) -> Response {
+    let request = Request::new(PublicParamsRequest {});
+    reply(server.get_public_params(request).await)
+}
+
+async fn register(State(server): State<Arc<AuthServer>>, body: Body<RegisterRequest>) -> Response {
+    handle(
+        body,
+        |request| async move { server.register(request).await },
+    )
+    .await
+}
+
+async fn rotate_key(
+    State(server): State<Arc<AuthServer>>,
+    body: Body<RotateKeyRequest>,
+) -> Response {
+    handle(
+        body,
+        |request| async move { server.rotate_key(request).await },
+    )
+    .await
+}
+
+async fn commit_challenge(
+    State(server): State<Arc<AuthServer>>,
+    body: Body<ChallengeCommitRequest>,
+) -> Response {
+    handle(body, |request| async move {
+        server.commit_challenge(request).await
+    })
+    .await
+}
+
+// The client address counts towards the puzzle rates like on gRPC
+async fn create_challenge(
+    State(server): State<Arc<AuthServer>>,
+    peer: Option<ConnectInfo<SocketAddr>>,
+    body: Body<AuthChallengeRequest>,
+) -> Response {
+    handle(body, |mut request| async move {
+        if let Some(ConnectInfo(addr)) = peer {
+            request.extensions_mut().insert(PeerAddr(addr));
+        }
+        server.create_authentication_challenge(request).await
+    })
+    .await
+}
+
+async fn challenge_status(
+    State(server): State<Arc<AuthServer>>,
+    Path(auth_id): Path<String>,
+) -> Response {
+    let request = Request::new(ChallengeStatusRequest { auth_id });
+    reply(server.get_challenge_status(request).await)
+}
+
+async fn cancel_challenge(
+    State(server): State<Arc<AuthServer>>,
+    Path(auth_id): Path<String>,
+) -> Response {
+    let request = Request::new(CancelChallengeRequest { auth_id });
+    reply(server.cancel_challenge(request).await)
+}
+
+async fn verify_answer(
+    State(server): State<Arc<AuthServer>>,
+    body: Body<AuthAnswerRequest>,
+) -> Response {
+    handle(body, |request| async move {
+        server.verify_authentication(request).await
+    })
+    .await
+}
+
+async fn verify_answers(
+    State(server): State<Arc<AuthServer>>,
+    body: Body<AuthAnswerBatchRequest>,
+) -> Response {
+    handle(body, |request| async move {
+        server.verify_authentication_batch(request).await
+    })
+    .await
+}
+
+// POST so the session id stays out of URLs and access logs
+async fn validate_session(
+    State(server): State<Arc<AuthServer>>,
+    body: Body<ValidateSessionRequest>,
+) -> Response {
+    handle(body, |request| async move {
+        server.validate_session(request).await
+    })
+    .await
+}
+
+async fn openapi() -> Response {
+    ([(CONTENT_TYPE, "application/yaml")], OPENAPI).into_response()
+}
+
+// Runs handler on a request body that parsed, answers INVALID_ARGUMENT for
+// one that did not
+async fn handle<T, R, F, Fut>(body: Body<T>, handler: F) -> Response
+where
+    F: FnOnce(Request<T>) -> Fut,
+    Fut: Future<Output = Result<tonic::Response<R>, Status>>,
+    R: Serialize,
+{
+    match body {
+        Ok(Json(message)) => reply(handler(Request::new(message)).await),
+        Err(rejection) => error(&Status::invalid_argument(rejection.body_text())),
+    }
+}
+
+fn reply<R: Serialize>(result: Result<tonic::Response<R>, Status>) -> Response {
+    match result {
+        Ok(response) => Json(response.into_inner()).into_response(),
+        Err(status) => error(&status),
+    }
+}
+
+fn error(status: &Status) -> Response {
+    let body = ErrorBody {
+        code: status.code() as i32,
+        message: status.message().to_string(),
+    };
+    (http_status(status.code()), Json(body)).into_response()
+}
@@ -0,0 +1,297 @@
+use num_bigint::{BigInt, BigUint, Sign};
+use num_traits::Zero;
+
+// Arithmetic modulo an odd p in Montgomery form (R = 2^(64 * limbs)).
+//
+// Values are kept as little endian u64 limbs so that the hot loops in the
+// fixed-base and multi-exponentiation code below avoid allocating and dividing
+// on every multiplication. None of this is constant time, it is meant for
+// verification where every exponent is public.
+pub struct Montgomery {
+    modulus: Vec<u64>,
+    // -modulus^-1 mod 2^64
+    m_inv: u64,
+    // R^2 mod modulus, used to move values into Montgomery form
+    r2: Vec<u64>,
+    // R mod modulus, the Montgomery form of one
+    one: Vec<u64>,
+    p: BigUint,
+}
+
+impl Montgomery {
+    pub fn new(p: &BigInt) -> Self {
+        let p = p.magnitude().clone();
+        assert!(p.bit(0), "montgomery form needs an odd modulus");
+        let modulus = p.to_u64_digits();
+        let limbs = modulus.len();
+
+        // Newton iteration for modulus^-1 mod 2^64
+        let mut inv: u64 = 1;
+        for _ in 0..6 {
+            inv = inv.wrapping_mul(2u64.wrapping_sub(modulus[0].wrapping_mul(inv)));
+        }
+
+        let r = BigUint::from(1u8) << (64 * limbs);
+        Self {
+            m_inv: inv.wrapping_neg(),
+            r2: to_limbs(&((&r * &r) % &p), limbs),
+            one: to_limbs(&(&r % &p), limbs),
+            modulus,
+            p,
+        }
+    }
+
+    pub fn to_mont(&self, v: &BigInt) -> Vec<u64> {
+        let v = reduce(v, &self.p);
+        self.mul(&to_limbs(&v, self.modulus.len()), &self.r2)
+    }
+
+    pub fn from_mont(&self, v: &[u64]) -> BigInt {
+        let mut one = vec![0u64; self.modulus.len()];
+        one[0] = 1;
+        let v = self.mul(v, &one);
+        BigInt::from_biguint(Sign::Plus, from_limbs(&v))
+    }
+
+    pub fn one(&self) -> Vec<u64> {
+        self.one.clone()
+    }
+
+    // Montgomery product a * b * R^-1 mod p (CIOS)
+    pub fn mul(&self, a: &[u64], b: &[u64]) -> Vec<u64> {
+        let n = &self.modulus[..];
+        let limbs = n.len();
+        let a = &a[..limbs];
+        let mut t = vec![0u64; limbs + 1];
+
+        for &b_i in &b[..limbs] {
+            // t += a * b_i
+            let mut carry: u64 = 0;
+            for (t_j, &a_j) in t[..limbs].iter_mut().zip(a) {
+                let sum = *t_j as u128 + a_j as u128 * b_i as u128 + carry as u128;
+                *t_j = sum as u64;
+                carry = (sum >> 64) as u64;
+            }
+            let sum = t[limbs] as u128 + carry as u128;
+            t[limbs] = sum as u64;
+            let top = (sum >> 64) as u64;
+
+            // t = (t + m * n) / 2^64
+            let m = t[0].wrapping_mul(self.m_inv);
+            let mut carry = ((t[0] as u128 + m as u128 * n[0] as u128) >> 64) as u64;
+            for j in 1..limbs {
+                let sum = t[j] as u128 + m as u128 * n[j] as u128 + carry as u128;
+                t[j - 1] = sum as u64;
+                carry = (sum >> 64) as u64;
+            }
+            let sum = t[limbs] as u128 + carry as u128;
+            t[limbs - 1] = sum as u64;
+            t[limbs] = top + (sum >> 64) as u64;
+        }
+
+        if t[limbs] != 0 || !less_than(&t[..limbs], n) {
+            sub_in_place(&mut t, n);
+        }
+        t.truncate(limbs);
+        t
+    }
+
+    // Sliding window exponentiation of a value already in Montgomery form
+    pub fn pow(&self, base: &[u64], exponent: &BigUint) -> Vec<u64> {
+        let table = self.odd_powers(base, window_for(exponent.bits()));
+        let mut acc = self.one();
+        let mut i = exponent.bits() as i64 - 1;
+        while i >= 0 {
+            if !exponent.bit(i as u64) {
+                acc = self.mul(&acc, &acc);
+                i -= 1;
+                continue;
+            }
+            // longest window ending in a set bit
+            let mut start = (i - table.window as i64 + 1).max(0);
+            while !exponent.bit(start as u64) {
+                start += 1;
+            }
+            let mut digit = 0usize;
+            for b in (start..=i).rev() {
+                acc = self.mul(&acc, &acc);
+                digit = (digit << 1) | exponent.bit(b as u64) as usize;
+            }
+            acc = self.mul(&acc, &table.powers[digit >> 1]);
+            i = start - 1;
+        }
+        acc
+    }
+
+    // base^1, base^3, ..., base^(2^window - 1)
+    fn odd_powers(&self, base: &[u64], window: usize) -> OddPowers {
+        let square = self.mul(base, base);
+        let mut powers = vec![base.to_vec()];
+        for k in 1..(1 << (window - 1)) {
+            let next = self.mul(&powers[k - 1], &square);
+            powers.push(next);
+        }
+        OddPowers { window, powers }
+    }
+}
+
+struct OddPowers {
+    window: usize,
+    powers: Vec<Vec<u64>>,
+}
+
+fn window_for(bits: u64) -> usize {
+    match bits {
+        0..=64 => 3,
+        65..=512 => 4,
+        _ => 5,
+    }
+}
+
+// Precomputed powers base^(j * 2^(window * i)) for a base that never changes,
+// so base^e costs one multiplication per window of e and no squarings.
+pub struct FixedBaseTable {
+    window: usize,
+    // rows[i][j - 1] = base^(j * 2^(window * i)) in Montgomery form
+    rows: Vec<Vec<Vec<u64>>>,
+}
+
+impl FixedBaseTable {
+    pub fn new(mont: &Montgomery, base: &BigInt, max_bits: u64, window: usize) -> Self {
+        let mut rows = Vec::new();
+        let mut row_base = mont.to_mont(base);
+        for _ in 0..max_bits.div_ceil(window as u64) {
+            let mut row = vec![row_base.clone()];
+            for j in 1..(1 << window) - 1 {
+                let next = mont.mul(&row[j - 1], &row_base);
+                row.push(next);
+            }
+            // base^(2^(window * (i + 1))) = base^((2^window - 1) * 2^(window * i)) * row_base
+            row_base = mont.mul(&row[row.len() - 1], &row_base);
+            rows.push(row);
+        }
+        Self { window, rows }
+    }
+
+    // base^exponent in Montgomery form, exponent must fit in max_bits
+    pub fn pow(&self, mont: &Montgomery, exponent: &BigUint) -> Vec<u64> {
+        assert!(
+            exponent.bits() <= (self.rows.len() * self.window) as u64,
+            "exponent is larger than the table"
+        );
+        let mut acc = mont.one();
+        for (i, row) in self.rows.iter().enumerate() {
+            let digit = window_digit(exponent, i * self.window, self.window);
+            if digit != 0 {
+                acc = mont.mul(&acc, &row[digit - 1]);
+            }
+        }
+        acc
+    }
+}
+
+// Computes prod(table_i.base ^ e_i) * prod(base_j ^ exponent_j) mod p.
+// Exponents must be non negative.
+//
+// Two or more terms share one chain of squarings through Shamir's trick
+// (Straus' interleaving), with one multiplication per non zero window of each
+// exponent. A single term has nothing to share the chain with and is a plain
+// sliding window Montgomery::pow, which needs fewer multiplications than the
+// fixed window loop. Fixed bases never join the chain: their table entries are
+// already the right power of two, so each costs one multiplication per window
+// and no squarings, multiplied in at the end.
+pub fn multi_exp(
+    mont: &Montgomery,
+    fixed: &[(&FixedBaseTable, &BigInt)],
+    terms: &[(&BigInt, &BigInt)],
+) -> BigInt {
+    let mut acc = mont.one();
+
+    // g^s * y^c for one proof comes down to this and the table lookups, about
+    // 10% faster than going through the interleaved loop with w = 4
+    if let [(base, exponent)] = terms {
+        acc = mont.pow(&mont.to_mont(base), exponent.magnitude());
+    } else if !terms.is_empty() {
+        let exponents: Vec<BigUint> = terms.iter().map(|(_, e)| e.magnitude().clone()).collect();
+        let bits = exponents.iter().map(|e| e.bits()).max().unwrap_or(0);
+        let window = window_for(bits).min(4);
+        let tables: Vec<Vec<Vec<u64>>> = terms
+            .iter()
+            .map(|(base, _)| {
+                let base = mont.to_mont(base);
+                let mut powers = vec![base.clone()];
+                for j in 1..(1 << window) - 1 {
+                    let next = mont.mul(&powers[j - 1], &base);
+                    powers.push(next);
+                }
+                powers
+            })
+            .collect();
+
+        let windows = bits.div_ceil(window as u64) as usize;
+        for w in (0..windows).rev() {
+            for _ in 0..window {
+                acc = mont.mul(&acc, &acc);
+            }
+            for (exponent, powers) in exponents.iter().zip(tables.iter()) {
+                let digit = window_digit(exponent, w * window, window);
+                if digit != 0 {
+                    acc = mont.mul(&acc, &powers[digit - 1]);
+                }
+            }
+        }
+    }
+
+    for (table, exponent) in fixed {
+        acc = mont.mul(&acc, &table.pow(mont, exponent.magnitude()));
+    }
+
+    mont.from_mont(&acc)
+}
+
+fn window_digit(v: &BigUint, offset: usize, window: usize) -> usize {
+    (0..window).fold(0, |digit, b| {
+        digit | ((v.bit((offset + b) as u64) as usize) << b)
+    })
+}
+
+fn reduce(v: &BigInt, p: &BigUint) -> BigUint {
+    let r = v.magnitude() % p;
+    if v.sign() == Sign::Minus && !r.is_zero() {
+        p - r
+    } else {
+        r
+    }
+}
+
+fn to_limbs(v: &BigUint, limbs: usize) -> Vec<u64> {
+    let mut digits = v.to_u64_digits();
+    digits.resize(limbs, 0);
+    digits
+}
+
+fn from_limbs(v: &[u64]) -> BigUint {
+    let bytes: Vec<u8> = v.iter().flat_map(|limb| limb.to_le_bytes()).collect();
+    BigUint::from_bytes_le(&bytes)
+}
+
+fn less_than(a: &[u64], b: &[u64]) -> bool {
+    for (x, y) in a.iter().rev().zip(b.iter().rev()) {
+        if x != y {
+            return x < y;
+        }
+    }
+    false
+}
+
+// a -= b, where a may carry one extra high limb
+fn sub_in_place(a: &mut [u64], b: &[u64]) {
+    let mut borrow = false;
+    for (i, a_i) in a.iter_mut().enumerate() {
+        let b_i = b.get(i).copied().unwrap_or(0);
+        let (d1, o1) = a_i.overflowing_sub(b_i);
+        let (d2, o2) = d1.overflowing_sub(borrow as u64);
+        *a_i = d2;
+        borrow = o1 || o2;
+    }
+}
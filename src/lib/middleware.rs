@@ -0,0 +1,457 @@
+use moka::sync::Cache;
+use num_bigint::BigInt;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, SystemTime};
+use tonic::codegen::http::{self, header, HeaderValue, StatusCode};
+use tonic::transport::Channel;
+use tonic::{Code, Request, Status};
+use tower::{Layer, Service};
+
+use crate::blind_auth_api::blind_auth_client::BlindAuthClient;
+use crate::blind_auth_api::ValidateSessionRequest;
+use crate::clock::{unix_seconds, Clock, SystemClock};
+use crate::pool::CryptoPool;
+use crate::session_token::{self, SessionClaims};
+use crate::store::models::Session;
+use crate::store::store::DataStore;
+use crate::validation::{is_group_element, validate_session_id, ValidationError};
+
+// Authentication for services that accept blind-auth sessions. Requests carry
+// "authorization: Bearer <credential>" where the credential is either a
+// session token, checked against the verifier key, or a session id, checked
+// with ValidateSession on the verifier. Requests that pass get an
+// AuthenticatedUser extension.
+
+// How long a session id checked with the verifier is trusted without asking
+// again, a revoked session can be used for up to this long
+pub const DEFAULT_CACHE_TTL: Duration = Duration::from_secs(30);
+
+// Session ids remembered at most, the oldest are dropped first
+const CACHE_CAPACITY: u64 = 100_000;
+
+// Headers proxy integrations pass the session upstream in, scopes and roles
+// comma separated
+pub const USER_HEADER: &str = "x-blindauth-user";
+pub const AUDIENCE_HEADER: &str = "x-blindauth-audience";
+pub const SCOPES_HEADER: &str = "x-blindauth-scopes";
+pub const ROLES_HEADER: &str = "x-blindauth-roles";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AuthenticatedUser {
+    pub session_id: String,
+    pub user: String,
+    pub audience: String,
+    pub scopes: Vec<String>,
+    pub roles: Vec<String>,
+    pub attributes: BTreeMap<String, String>,
+    // unix seconds
+    pub expires_at: u64,
+}
+
+impl AuthenticatedUser {
+    pub fn has_scope(&self, scope: &str) -> bool {
+        self.scopes.iter().any(|granted| granted == scope)
+    }
+
+    pub fn has_role(&self, role: &str) -> bool {
+        self.roles.iter().any(|granted| granted == role)
+    }
+
+    pub fn require_audience(&self, audience: &str) -> Result<(), Status> {
+        match self.audience == audience {
+            true => Ok(()),
+            false => Err(Status::permission_denied(
+                "session was not granted for this audience",
+            )),
+        }
+    }
+
+    // PERMISSION_DENIED naming the first scope the session was not granted
+    pub fn require_scopes(&self, scopes: &[&str]) -> Result<(), Status> {
+        match scopes.iter().find(|scope| !self.has_scope(scope)) {
+            Some(scope) => Err(Status::permission_denied(format!(
+                "session is missing scope {}",
+                scope
+            ))),
+            None => Ok(()),
+        }
+    }
+}
+
+impl From<SessionClaims> for AuthenticatedUser {
+    fn from(claims: SessionClaims) -> Self {
+        AuthenticatedUser {
+            session_id: claims.session_id,
+            user: claims.user,
+            audience: claims.audience,
+            scopes: claims.scopes,
+            roles: claims.roles,
+            attributes: claims.attributes,
+            expires_at: claims.expires_at,
+        }
+    }
+}
+
+// Values of the session headers, empty when the session has none
+pub fn session_headers(user: &AuthenticatedUser) -> [(&'static str, String); 4] {
+    [
+        (USER_HEADER, user.user.clone()),
+        (AUDIENCE_HEADER, user.audience.clone()),
+        (SCOPES_HEADER, user.scopes.join(",")),
+        (ROLES_HEADER, user.roles.join(",")),
+    ]
+}
+
+// Checks a session against the audience and comma separated scopes a proxied
+// route asks for
+pub fn require_route(
+    user: &AuthenticatedUser,
+    audience: Option<&str>,
+    scopes: Option<&str>,
+) -> Result<(), Status> {
+    if let Some(audience) = audience {
+        user.require_audience(audience)?;
+    }
+    if let Some(scopes) = scopes {
+        let scopes: Vec<&str> = scopes.split(',').map(str::trim).collect();
+        user.require_scopes(&scopes)?;
+    }
+    Ok(())
+}
+
+// User of a request that passed SessionLayer
+pub fn authenticated_user<T>(request: &Request<T>) -> Result<&AuthenticatedUser, Status> {
+    request
+        .extensions()
+        .get::<AuthenticatedUser>()
+        .ok_or_else(|| Status::unauthenticated("request is not authenticated"))
+}
+
+// authenticated_user for handlers that need every scope in scopes
+pub fn require_scopes<'a, T>(
+    request: &'a Request<T>,
+    scopes: &[&str],
+) -> Result<&'a AuthenticatedUser, Status> {
+    let user = authenticated_user(request)?;
+    user.require_scopes(scopes)?;
+    Ok(user)
+}
+
+// Checks credentials. Session tokens need with_verifier_key and session ids
+// need with_verifier or with_store, credentials of a kind that is not
+// configured are refused.
+#[derive(Clone)]
+pub struct SessionValidator {
+    verifier_key: Option<BigInt>,
+    verifier: Option<BlindAuthClient<Channel>>,
+    // sessions of the verifier this runs in
+    store: Option<DataStore>,
+    audience: Option<String>,
+    cache: Cache<String, AuthenticatedUser>,
+    clock: Arc<dyn Clock>,
+    // token signatures are checked here rather than on the runtime
+    pool: CryptoPool,
+}
+
+impl Default for SessionValidator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SessionValidator {
+    pub fn new() -> Self {
+        Self {
+            verifier_key: None,
+            verifier: None,
+            store: None,
+            audience: None,
+            cache: session_cache(DEFAULT_CACHE_TTL),
+            clock: Arc::new(SystemClock),
+            pool: CryptoPool::default(),
+        }
+    }
+
+    // Accepts session tokens signed with key, the verifier_key of
+    // GetPublicParams. Fails unless key is in the group.
+    pub fn with_verifier_key(mut self, key: BigInt) -> Result<Self, ValidationError> {
+        if !is_group_element(&key) {
+            return Err(ValidationError::NotInGroup("verifier_key"));
+        }
+        self.verifier_key = Some(key);
+        Ok(self)
+    }
+
+    // Accepts session ids the verifier behind client knows
+    pub fn with_verifier(mut self, client: BlindAuthClient<Channel>) -> Self {
+        self.verifier = Some(client);
+        self
+    }
+
+    // Accepts session ids in store, for integrations served by the verifier
+    // itself. Uses the store's clock.
+    pub fn with_store(mut self, store: DataStore) -> Self {
+        self.clock = store.clock();
+        self.store = Some(store);
+        self
+    }
+
+    // Only accepts sessions granted for audience
+    pub fn with_audience(mut self, audience: &str) -> Self {
+        self.audience = Some(audience.to_string());
+        self
+    }
+
+    pub fn with_cache_ttl(mut self, ttl: Duration) -> Self {
+        self.cache = session_cache(ttl);
+        self
+    }
+
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    // Pool session token signatures are checked on, e.g. the one of the
+    // verifier this runs in
+    pub fn with_crypto_pool(mut self, pool: CryptoPool) -> Self {
+        self.pool = pool;
+        self
+    }
+
+    // Checks a session token without a round trip to the verifier. The
+    // signature is checked on the crypto pool, RESOURCE_EXHAUSTED when it is
+    // full.
+    pub async fn validate_token(&self, token: &str) -> Result<AuthenticatedUser, Status> {
+        let key = self
+            .verifier_key
+            .clone()
+            .ok_or_else(|| Status::unauthenticated("session tokens are not accepted"))?;
+        let now = unix_seconds(self.clock.now());
+        let token = token.to_string();
+        let claims = self
+            .pool
+            .run(move || session_token::verify(&token, &key, now))
+            .await??;
+        let user = AuthenticatedUser::from(claims);
+        self.check_audience(&user)?;
+        Ok(user)
+    }
+
+    // Checks a session token or a session id
+    pub async fn validate(&self, credential: &str) -> Result<AuthenticatedUser, Status> {
+        if session_token::is_token(credential) {
+            return self.validate_token(credential).await;
+        }
+        let session_id = validate_session_id(credential)
+            .map_err(|_| Status::unauthenticated("session id is malformed"))?;
+        if let Some(store) = &self.store {
+            let (session, expires_at) = store
+                .get_session_with_expiry(&session_id)
+                .ok_or_else(|| Status::unauthenticated("session not found"))?;
+            let user = session_user(session, expires_at)?;
+            self.check_audience(&user)?;
+            return Ok(user);
+        }
+        let now = unix_seconds(self.clock.now());
+        if let Some(user) = self.cache.get(&session_id) {
+            if now < user.expires_at {
+                return Ok(user);
+            }
+            self.cache.invalidate(&session_id);
+        }
+
+        let mut client = self
+            .verifier
+            .clone()
+            .ok_or_else(|| Status::unauthenticated("session ids are not accepted"))?;
+        let session = client
+            .validate_session(ValidateSessionRequest {
+                session_id: session_id.clone(),
+            })
+            .await
+            .map_err(|status| match status.code() {
+                Code::NotFound => Status::unauthenticated("session not found"),
+                _ => {
+                    Status::unavailable(format!("could not validate session: {}", status.message()))
+                }
+            })?
+            .into_inner();
+        // a bound session needs proofs a bearer of its id can't give
+        if !session.session_public_key.is_empty() || session.channel_bound || session.key_exchange {
+            return Err(Status::unauthenticated(
+                "session is bound to a key or channel",
+            ));
+        }
+        let user = AuthenticatedUser {
+            session_id: session_id.clone(),
+            user: session.user,
+            audience: session.audience,
+            scopes: session.scopes,
+            roles: session.roles,
+            attributes: session.attributes.into_iter().collect(),
+            expires_at: session.expires_at,
+        };
+        self.check_audience(&user)?;
+        self.cache.insert(session_id, user.clone());
+        Ok(user)
+    }
+
+    fn check_audience(&self, user: &AuthenticatedUser) -> Result<(), Status> {
+        match &self.audience {
+            Some(audience) => user.require_audience(audience),
+            None => Ok(()),
+        }
+    }
+}
+
+// Key exchange sessions are authenticated with a MAC under the session key,
+// signed ones with the bound key, neither is valid on its id alone
+fn session_user(session: Session, expires_at: SystemTime) -> Result<AuthenticatedUser, Status> {
+    if session.key.is_some() || session.public_key.is_some() || session.channel_binding.is_some() {
+        return Err(Status::unauthenticated(
+            "session is bound to a key or channel",
+        ));
+    }
+    Ok(AuthenticatedUser {
+        session_id: session.id,
+        user: session.user_id,
+        audience: session.audience,
+        scopes: session.scopes,
+        roles: session.roles,
+        attributes: session.attributes,
+        expires_at: unix_seconds(expires_at),
+    })
+}
+
+fn session_cache(ttl: Duration) -> Cache<String, AuthenticatedUser> {
+    Cache::builder()
+        .max_capacity(CACHE_CAPACITY)
+        .time_to_live(ttl)
+        .build()
+}
+
+// Authenticates HTTP and gRPC requests with a session token or session id in
+// front of any tower service, e.g. Server::builder().layer(..) in tonic.
+// Rejected gRPC requests get a gRPC status, others an HTTP status code.
+#[derive(Clone)]
+pub struct SessionLayer {
+    validator: SessionValidator,
+}
+
+impl SessionLayer {
+    pub fn new(validator: SessionValidator) -> Self {
+        Self { validator }
+    }
+}
+
+impl<S> Layer<S> for SessionLayer {
+    type Service = SessionService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        SessionService {
+            inner,
+            validator: self.validator.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct SessionService<S> {
+    inner: S,
+    validator: SessionValidator,
+}
+
+impl<S, B, ResBody> Service<http::Request<B>> for SessionService<S>
+where
+    S: Service<http::Request<B>, Response = http::Response<ResBody>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    B: Send + 'static,
+    ResBody: Default,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: http::Request<B>) -> Self::Future {
+        // the clone may not be ready, keep the service poll_ready was called on
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let validator = self.validator.clone();
+        Box::pin(async move {
+            let grpc = is_grpc(&request);
+            let authorization = request
+                .headers()
+                .get(header::AUTHORIZATION)
+                .and_then(|value| value.to_str().ok());
+            let result = match bearer(authorization) {
+                Ok(credential) => validator.validate(credential).await,
+                Err(status) => Err(status),
+            };
+            match result {
+                Ok(user) => {
+                    request.extensions_mut().insert(user);
+                    inner.call(request).await
+                }
+                Err(status) => Ok(rejection(status, grpc)),
+            }
+        })
+    }
+}
+
+pub(crate) fn bearer(authorization: Option<&str>) -> Result<&str, Status> {
+    authorization
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .ok_or_else(|| Status::unauthenticated("missing bearer credential"))
+}
+
+fn is_grpc<B>(request: &http::Request<B>) -> bool {
+    request
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("application/grpc"))
+}
+
+fn rejection<B: Default>(status: Status, grpc: bool) -> http::Response<B> {
+    if grpc {
+        let (parts, _) = status.to_http().into_parts();
+        return http::Response::from_parts(parts, B::default());
+    }
+    let mut response = http::Response::new(B::default());
+    *response.status_mut() = http_status(status.code());
+    if status.code() == Code::Unauthenticated {
+        response
+            .headers_mut()
+            .insert(header::WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+    }
+    response
+}
+
+// HTTP status code for a gRPC status, as grpc-gateway maps them
+pub(crate) fn http_status(code: Code) -> StatusCode {
+    match code {
+        Code::Ok => StatusCode::OK,
+        Code::InvalidArgument | Code::FailedPrecondition | Code::OutOfRange => {
+            StatusCode::BAD_REQUEST
+        }
+        Code::Unauthenticated => StatusCode::UNAUTHORIZED,
+        Code::PermissionDenied => StatusCode::FORBIDDEN,
+        Code::NotFound => StatusCode::NOT_FOUND,
+        Code::AlreadyExists | Code::Aborted => StatusCode::CONFLICT,
+        Code::ResourceExhausted => StatusCode::TOO_MANY_REQUESTS,
+        Code::Cancelled => StatusCode::from_u16(499).expect("valid status code"),
+        Code::Unimplemented => StatusCode::NOT_IMPLEMENTED,
+        Code::Unavailable => StatusCode::SERVICE_UNAVAILABLE,
+        Code::DeadlineExceeded => StatusCode::GATEWAY_TIMEOUT,
+        Code::Unknown | Code::Internal | Code::DataLoss => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
@@ -0,0 +1,93 @@
+use std::fmt;
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+use tonic::Status;
+
+// Default number of requests allowed to wait for a worker
+pub const DEFAULT_QUEUE_DEPTH: usize = 256;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PoolError {
+    // All workers are busy and the queue is full
+    Exhausted,
+    // The job panicked or was cancelled
+    Failed(String),
+}
+
+impl fmt::Display for PoolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PoolError::Exhausted => write!(f, "crypto worker pool is exhausted"),
+            PoolError::Failed(err) => write!(f, "crypto job failed: {}", err),
+        }
+    }
+}
+
+impl From<PoolError> for Status {
+    fn from(err: PoolError) -> Self {
+        match err {
+            PoolError::Exhausted => Status::resource_exhausted(err.to_string()),
+            PoolError::Failed(_) => Status::internal(err.to_string()),
+        }
+    }
+}
+
+// Runs CPU heavy work (modexps) on tokio's blocking threads so it does not
+// stall the reactor. At most `parallelism` jobs run at once and at most
+// `queue_depth` more may wait; anything beyond that is rejected straight away
+// instead of letting latency grow without bound.
+#[derive(Clone)]
+pub struct CryptoPool {
+    // parallelism + queue_depth permits, taken without waiting
+    admission: Arc<Semaphore>,
+    // parallelism permits, waited for by admitted jobs
+    workers: Arc<Semaphore>,
+}
+
+// One worker per core
+pub fn default_parallelism() -> usize {
+    std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1)
+}
+
+impl Default for CryptoPool {
+    fn default() -> Self {
+        Self::new(default_parallelism(), DEFAULT_QUEUE_DEPTH)
+    }
+}
+
+impl CryptoPool {
+    pub fn new(parallelism: usize, queue_depth: usize) -> Self {
+        assert!(parallelism > 0, "crypto pool needs at least one worker");
+        Self {
+            admission: Arc::new(Semaphore::new(parallelism + queue_depth)),
+            workers: Arc::new(Semaphore::new(parallelism)),
+        }
+    }
+
+    pub async fn run<F, T>(&self, job: F) -> Result<T, PoolError>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let _admitted = self
+            .admission
+            .clone()
+            .try_acquire_owned()
+            .map_err(|_| PoolError::Exhausted)?;
+        let worker = self
+            .workers
+            .clone()
+            .acquire_owned()
+            .await
+            .map_err(|err| PoolError::Failed(err.to_string()))?;
+
+        tokio::task::spawn_blocking(move || {
+            let _worker = worker;
+            job()
+        })
+        .await
+        .map_err(|err| PoolError::Failed(err.to_string()))
+    }
+}
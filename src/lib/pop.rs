@@ -0,0 +1,322 @@
+use prost::Message;
+use sha2::{Digest, Sha256};
+use std::time::{Duration, SystemTime};
+use tonic::metadata::errors::InvalidMetadataValue;
+use tonic::metadata::MetadataMap;
+use tonic::service::Interceptor;
+use tonic::{Request, Status};
+
+use crate::blind_auth_api;
+use crate::clock::unix_seconds;
+use crate::pool::CryptoPool;
+use crate::rng::RngProvider;
+use crate::schnorr::{self, KeyPair, Signature};
+use crate::session_key::SessionKey;
+use crate::store::models::Session;
+use crate::store::store::DataStore;
+use crate::tls;
+use crate::transcript::Transcript;
+
+// Proof of possession for sessions bound to a key at login. Every request
+// carries a signature by the session key over the method, a timestamp, a
+// nonce and the hash of the message, so a session id on its own is useless.
+// Sessions from a key exchange carry an HMAC under the shared session key
+// over the same fields instead.
+pub const SESSION_HEADER: &str = "x-blind-auth-session";
+pub const METHOD_HEADER: &str = "x-blind-auth-method";
+pub const TIMESTAMP_HEADER: &str = "x-blind-auth-timestamp";
+pub const NONCE_HEADER: &str = "x-blind-auth-nonce";
+pub const BODY_HASH_HEADER: &str = "x-blind-auth-body-sha256";
+pub const SIGNATURE_HEADER: &str = "x-blind-auth-signature";
+pub const MAC_HEADER: &str = "x-blind-auth-mac";
+
+// How far a request timestamp may be from the verifier clock, either way
+pub const MAX_CLOCK_SKEW: Duration = Duration::from_secs(60);
+
+// Signs request for method (e.g. "/pkg.Service/Method") on the session with
+// its key and adds the proof to the request metadata
+pub fn sign_request<T: Message>(
+    request: &mut Request<T>,
+    session_id: &str,
+    method: &str,
+    key: &KeyPair,
+    rng: &dyn RngProvider,
+    now: SystemTime,
+) -> Result<(), InvalidMetadataValue> {
+    let (timestamp, nonce, body_hash) = add_request_fields(request, session_id, method, rng, now)?;
+    let message = request_transcript(session_id, method, timestamp, &nonce, &body_hash);
+    let signature = blind_auth_api::Signature::from(key.sign(&message, rng));
+    request.metadata_mut().insert(
+        SIGNATURE_HEADER,
+        format!("{}.{}", signature.e, signature.s).parse()?,
+    );
+    Ok(())
+}
+
+// sign_request for a session from a key exchange, authenticates the request
+// with a MAC under the shared session key
+pub fn mac_request<T: Message>(
+    request: &mut Request<T>,
+    session_id: &str,
+    method: &str,
+    key: &SessionKey,
+    rng: &dyn RngProvider,
+    now: SystemTime,
+) -> Result<(), InvalidMetadataValue> {
+    let (timestamp, nonce, body_hash) = add_request_fields(request, session_id, method, rng, now)?;
+    let mac = key.mac(method, timestamp, &nonce, &body_hash);
+    request
+        .metadata_mut()
+        .insert(MAC_HEADER, hex(&mac).parse()?);
+    Ok(())
+}
+
+// Adds the fields both proofs cover to the metadata, returns the timestamp,
+// the fresh nonce and the body hash
+fn add_request_fields<T: Message>(
+    request: &mut Request<T>,
+    session_id: &str,
+    method: &str,
+    rng: &dyn RngProvider,
+    now: SystemTime,
+) -> Result<(u64, String, [u8; 32]), InvalidMetadataValue> {
+    let timestamp = unix_seconds(now);
+    let mut nonce = [0u8; 16];
+    rng.fill_bytes(&mut nonce);
+    let nonce = hex(&nonce);
+    let body_hash = body_hash(request.get_ref());
+
+    let metadata = request.metadata_mut();
+    metadata.insert(SESSION_HEADER, session_id.parse()?);
+    metadata.insert(METHOD_HEADER, method.parse()?);
+    metadata.insert(TIMESTAMP_HEADER, timestamp.to_string().parse()?);
+    metadata.insert(NONCE_HEADER, nonce.parse()?);
+    metadata.insert(BODY_HASH_HEADER, hex(&body_hash).parse()?);
+    Ok((timestamp, nonce, body_hash))
+}
+
+// Proof carried by a request, a signature by the bound key or a MAC under
+// the key exchanged at login
+#[derive(Clone)]
+enum Proof {
+    Signature(Signature),
+    Mac(Vec<u8>),
+}
+
+// Request on a live session whose proof is still to be checked, added to the
+// request extensions by SessionInterceptor
+#[derive(Clone)]
+pub struct SignedRequest {
+    session: Session,
+    method: String,
+    timestamp: u64,
+    nonce: String,
+    body_hash: [u8; 32],
+    proof: Proof,
+    store: DataStore,
+    pool: CryptoPool,
+}
+
+// Checks the metadata of signed requests against the sessions in the store.
+// Interceptors can't wait, and a 2048 bit signature check is too slow to run
+// on the runtime for every request, so handlers must call authorize, which
+// checks the proof on the crypto pool along with the method and message.
+#[derive(Clone)]
+pub struct SessionInterceptor {
+    store: DataStore,
+    pool: CryptoPool,
+}
+
+impl SessionInterceptor {
+    pub fn new(store: DataStore) -> Self {
+        Self {
+            store,
+            pool: CryptoPool::default(),
+        }
+    }
+
+    // Pool the signatures are checked on, e.g. the one of the verifier
+    pub fn with_crypto_pool(mut self, pool: CryptoPool) -> Self {
+        self.pool = pool;
+        self
+    }
+
+    fn check(&self, request: &Request<()>) -> Result<SignedRequest, Status> {
+        let metadata = request.metadata();
+        let session_id = header(metadata, SESSION_HEADER)?;
+        let method = header(metadata, METHOD_HEADER)?;
+        let nonce = header(metadata, NONCE_HEADER)?;
+        let timestamp: u64 = header(metadata, TIMESTAMP_HEADER)?
+            .parse()
+            .map_err(|_| Status::unauthenticated("request timestamp is not a number"))?;
+        let body_hash = unhex(header(metadata, BODY_HASH_HEADER)?)
+            .and_then(|hash| <[u8; 32]>::try_from(hash).ok())
+            .ok_or_else(|| Status::unauthenticated("request body hash is malformed"))?;
+
+        let session = self
+            .store
+            .get_session(&session_id.to_string())
+            .ok_or_else(|| Status::unauthenticated("session not found"))?;
+        let proof = match (&session.key, &session.public_key) {
+            (Some(_), _) => Proof::Mac(
+                unhex(header(metadata, MAC_HEADER)?)
+                    .ok_or_else(|| Status::unauthenticated("request mac is malformed"))?,
+            ),
+            (None, Some(_)) => Proof::Signature(
+                parse_signature(header(metadata, SIGNATURE_HEADER)?)
+                    .ok_or_else(|| Status::unauthenticated("request signature is malformed"))?,
+            ),
+            (None, None) => return Err(Status::unauthenticated("session is not bound to a key")),
+        };
+        if session.channel_binding.is_some()
+            && session.channel_binding.as_ref() != tls::channel_binding(request)
+        {
+            return Err(Status::unauthenticated(
+                "session is bound to another TLS channel",
+            ));
+        }
+
+        let now = unix_seconds(self.store.now());
+        if now.abs_diff(timestamp) > MAX_CLOCK_SKEW.as_secs() {
+            return Err(Status::unauthenticated(
+                "request timestamp is outside the accepted window",
+            ));
+        }
+
+        Ok(SignedRequest {
+            session,
+            method: method.to_string(),
+            timestamp,
+            nonce: nonce.to_string(),
+            body_hash,
+            proof,
+            store: self.store.clone(),
+            pool: self.pool.clone(),
+        })
+    }
+}
+
+impl Interceptor for SessionInterceptor {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        let signed = self.check(&request)?;
+        request.extensions_mut().insert(signed);
+        Ok(request)
+    }
+}
+
+// Returns the session of a request that passed SessionInterceptor, after
+// checking its proof covers method and this exact message. Signatures are
+// checked on the crypto pool, RESOURCE_EXHAUSTED when it is full.
+pub async fn authorize<'a, T: Message>(
+    request: &'a Request<T>,
+    method: &str,
+) -> Result<&'a Session, Status> {
+    let signed = request
+        .extensions()
+        .get::<SignedRequest>()
+        .ok_or_else(|| Status::unauthenticated("request is not signed"))?;
+    if signed.method != method {
+        return Err(Status::unauthenticated(
+            "request was signed for another method",
+        ));
+    }
+    // prost encodes a message the same way on both sides
+    if signed.body_hash != body_hash(request.get_ref()) {
+        return Err(Status::unauthenticated(
+            "request was signed for another body",
+        ));
+    }
+
+    let session = &signed.session;
+    match (&signed.proof, &session.key, &session.public_key) {
+        (Proof::Mac(mac), Some(key), _) => {
+            if !key.verify(
+                method,
+                signed.timestamp,
+                &signed.nonce,
+                &signed.body_hash,
+                mac,
+            ) {
+                return Err(Status::unauthenticated("request mac is invalid"));
+            }
+        }
+        (Proof::Signature(signature), None, Some(public_key)) => {
+            let message = request_transcript(
+                &session.id,
+                method,
+                signed.timestamp,
+                &signed.nonce,
+                &signed.body_hash,
+            );
+            let (public_key, signature) = (public_key.clone(), signature.clone());
+            let valid = signed
+                .pool
+                .run(move || schnorr::verify(&public_key, &message, &signature))
+                .await?;
+            if !valid {
+                return Err(Status::unauthenticated("request signature is invalid"));
+            }
+        }
+        _ => return Err(Status::unauthenticated("session is not bound to a key")),
+    }
+    // only valid requests use up a nonce
+    if !signed
+        .store
+        .insert_request_nonce(&session.id, &signed.nonce)
+    {
+        return Err(Status::unauthenticated("request nonce was already used"));
+    }
+    Ok(session)
+}
+
+fn request_transcript(
+    session_id: &str,
+    method: &str,
+    timestamp: u64,
+    nonce: &str,
+    body_hash: &[u8; 32],
+) -> [u8; 32] {
+    let mut transcript = Transcript::new("blind-auth/request/v1");
+    transcript
+        .append("session_id", session_id.as_bytes())
+        .append("method", method.as_bytes())
+        .append("timestamp", &timestamp.to_be_bytes())
+        .append("nonce", nonce.as_bytes())
+        .append("body_hash", body_hash);
+    transcript.finalize()
+}
+
+fn body_hash<T: Message>(message: &T) -> [u8; 32] {
+    Sha256::digest(message.encode_to_vec()).into()
+}
+
+fn header<'a>(metadata: &'a MetadataMap, name: &'static str) -> Result<&'a str, Status> {
+    metadata
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .ok_or_else(|| Status::unauthenticated(format!("missing {} header", name)))
+}
+
+// "e.s" in base16
+fn parse_signature(value: &str) -> Option<Signature> {
+    let (e, s) = value.split_once('.')?;
+    let signature = blind_auth_api::Signature {
+        e: e.to_string(),
+        s: s.to_string(),
+    };
+    Signature::try_from(&signature).ok()
+}
+
+pub(crate) fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub(crate) fn unhex(value: &str) -> Option<Vec<u8>> {
+    if !value.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..value.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(value.get(i..i + 2)?, 16).ok())
+        .collect()
+}
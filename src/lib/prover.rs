@@ -1,7 +1,28 @@
+use hmac::{Hmac, Mac};
 use num_bigint::BigInt;
+#[cfg(not(feature = "constant-time"))]
+use num_bigint::Sign;
+use num_traits::Num;
+#[cfg(not(feature = "constant-time"))]
 use num_traits::Zero;
+use sha2::Sha256;
+use std::fmt;
+use std::fs;
+use std::io::{self, Write};
+use std::path::PathBuf;
 
+use crate::blind_auth_api::{AuthChallengeResponse, ChallengeCommitResponse};
+#[cfg(feature = "constant-time")]
+use crate::ct;
 use crate::public_params::PublicParams;
+use crate::schnorr;
+use crate::session_key::{self, SessionKey};
+use crate::transcript::{
+    ChallengeContext, ChallengeTranscript, ChannelBinding, CommittedChallengeTranscript,
+};
+use crate::validation::{parse_group_element, ValidationError};
+
+type HmacSha256 = Hmac<Sha256>;
 
 // This is TOO secure. We need to be reading cli or even better encrypted file and user supplied pin.
 pub fn read_secret() -> BigInt {
@@ -9,10 +30,351 @@ pub fn read_secret() -> BigInt {
 }
 
 // Computes auth_secret = k - c * x (mod q)
+#[cfg(feature = "constant-time")]
+pub fn compute_auth_secret(c: BigInt, k: BigInt, x: BigInt) -> BigInt {
+    // c comes from the verifier and is public, reduce it before the constant
+    // time part which only takes values below 2^2048
+    let q = PublicParams::q();
+    let c = ((c % q) + q) % q;
+    ct::auth_secret(&c, &k, &x)
+}
+
+// Computes auth_secret = k - c * x (mod q)
+#[cfg(not(feature = "constant-time"))]
 pub fn compute_auth_secret(c: BigInt, k: BigInt, x: BigInt) -> BigInt {
     let mut auth_secret = (k - (c * x)) % PublicParams::q();
     if auth_secret < BigInt::zero() {
-        auth_secret = auth_secret + PublicParams::q();
+        auth_secret += PublicParams::q();
     }
     auth_secret
 }
+
+// Derives the nonce k in [1, q) following RFC 6979 section 3.2, with the hashed
+// message replaced by user || context || entropy (see section 3.6). k stays
+// unpredictable as long as either x or the fresh entropy is, so a weak RNG
+// alone can no longer leak x. Candidates outside [1, q) are rejected rather
+// than reduced mod q to avoid biasing k.
+pub fn derive_nonce(x: &BigInt, user: &str, context: &str, entropy: &[u8]) -> BigInt {
+    let q = PublicParams::q();
+    let qlen = q.bits() as usize;
+    let rlen = qlen.div_ceil(8);
+
+    #[cfg(feature = "constant-time")]
+    let x_octets = ct::int_to_octets_mod_q(x);
+    #[cfg(not(feature = "constant-time"))]
+    let x_octets = int_to_octets(&(x % q), rlen);
+    let mut data = Vec::new();
+    for field in [user.as_bytes(), context.as_bytes(), entropy] {
+        data.extend_from_slice(&(field.len() as u32).to_be_bytes());
+        data.extend_from_slice(field);
+    }
+
+    let mut v = [0x01u8; 32];
+    let mut k = [0x00u8; 32];
+    for marker in [0x00u8, 0x01] {
+        k = hmac(&k, &[&v, &[marker], &x_octets, &data]);
+        v = hmac(&k, &[&v]);
+    }
+
+    loop {
+        let mut t = Vec::with_capacity(rlen);
+        while t.len() < rlen {
+            v = hmac(&k, &[&v]);
+            t.extend_from_slice(&v);
+        }
+        if let Some(candidate) = bits2int_in_range(&t[..rlen], qlen) {
+            return candidate;
+        }
+        k = hmac(&k, &[&v, &[0x00]]);
+        v = hmac(&k, &[&v]);
+    }
+}
+
+// bits2int of t when it is in [1, q)
+#[cfg(feature = "constant-time")]
+fn bits2int_in_range(t: &[u8], _qlen: usize) -> Option<BigInt> {
+    ct::bits2int_mod_q(t)
+}
+
+// bits2int of t when it is in [1, q)
+#[cfg(not(feature = "constant-time"))]
+fn bits2int_in_range(t: &[u8], qlen: usize) -> Option<BigInt> {
+    // keep the leftmost qlen bits
+    let candidate = BigInt::from_bytes_be(Sign::Plus, t) >> (t.len() * 8 - qlen);
+    (candidate > BigInt::zero() && candidate < *PublicParams::q()).then_some(candidate)
+}
+
+// PBKDF2 iterations when deriving x from a password, the OWASP figure for
+// PBKDF2-HMAC-SHA256
+pub const PASSWORD_ITERATIONS: u32 = 600_000;
+
+// Derives x in [1, q) from a password. PBKDF2-HMAC-SHA256 stretches the
+// password, salted with the user id so that equal passwords give unrelated
+// keys, and the result is expanded to q's length with HMAC, rejecting
+// candidates outside [1, q) as in derive_nonce. The same password and user
+// always give the same x, so nothing has to be stored to log in again.
+pub fn derive_secret_from_password(password: &str, user: &str, iterations: u32) -> BigInt {
+    let qlen = PublicParams::q().bits() as usize;
+    let rlen = qlen.div_ceil(8);
+
+    let mut salt = b"cp-zkp password v1".to_vec();
+    salt.extend_from_slice(&(user.len() as u32).to_be_bytes());
+    salt.extend_from_slice(user.as_bytes());
+    let key = pbkdf2_block(password.as_bytes(), &salt, iterations.max(1));
+
+    for attempt in 0u32.. {
+        let mut t = Vec::with_capacity(rlen);
+        for block in 0u32.. {
+            if t.len() >= rlen {
+                break;
+            }
+            t.extend_from_slice(&hmac(&key, &[&attempt.to_be_bytes(), &block.to_be_bytes()]));
+        }
+        if let Some(candidate) = bits2int_in_range(&t[..rlen], qlen) {
+            return candidate;
+        }
+    }
+    unreachable!("a candidate below q turns up long before the counter wraps")
+}
+
+// First block of PBKDF2-HMAC-SHA256 (RFC 8018), 32 bytes are all we need
+fn pbkdf2_block(password: &[u8], salt: &[u8], iterations: u32) -> [u8; 32] {
+    let prf = HmacSha256::new_from_slice(password).expect("hmac accepts any key length");
+    let mut u: [u8; 32] = prf
+        .clone()
+        .chain_update(salt)
+        .chain_update(1u32.to_be_bytes())
+        .finalize()
+        .into_bytes()
+        .into();
+    let mut block = u;
+    for _ in 1..iterations {
+        u = prf.clone().chain_update(u).finalize().into_bytes().into();
+        block.iter_mut().zip(u).for_each(|(b, u)| *b ^= u);
+    }
+    block
+}
+
+fn hmac(key: &[u8], parts: &[&[u8]]) -> [u8; 32] {
+    let mut mac = HmacSha256::new_from_slice(key).expect("hmac accepts any key length");
+    for part in parts {
+        mac.update(part);
+    }
+    mac.finalize().into_bytes().into()
+}
+
+// Big endian encoding left padded to len bytes
+#[cfg(not(feature = "constant-time"))]
+fn int_to_octets(v: &BigInt, len: usize) -> Vec<u8> {
+    let (_, bytes) = v.to_bytes_be();
+    let mut octets = vec![0u8; len.saturating_sub(bytes.len())];
+    octets.extend_from_slice(&bytes);
+    octets
+}
+
+#[derive(Debug)]
+pub enum PinError {
+    // The server presented a different key than the one pinned for it
+    Mismatch { server: String, pinned: String },
+    Io(io::Error),
+}
+
+impl fmt::Display for PinError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PinError::Mismatch { server, pinned } => write!(
+                f,
+                "verifier key for {} does not match the pinned key {}",
+                server, pinned
+            ),
+            PinError::Io(err) => write!(f, "known verifiers file: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for PinError {}
+
+impl From<io::Error> for PinError {
+    fn from(err: io::Error) -> Self {
+        PinError::Io(err)
+    }
+}
+
+// Verifier keys pinned on first use, one `server key` pair per line
+pub struct KnownVerifiers {
+    path: PathBuf,
+}
+
+impl KnownVerifiers {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    // ~/.blind-auth/known_verifiers
+    pub fn default_path() -> PathBuf {
+        let home = std::env::var_os("HOME").unwrap_or_else(|| ".".into());
+        PathBuf::from(home)
+            .join(".blind-auth")
+            .join("known_verifiers")
+    }
+
+    pub fn get(&self, server: &str) -> Result<Option<BigInt>, PinError> {
+        let contents = match fs::read_to_string(&self.path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+        Ok(contents.lines().find_map(|line| {
+            let (pinned_server, key) = line.split_once(' ')?;
+            if pinned_server != server {
+                return None;
+            }
+            BigInt::from_str_radix(key.trim(), 16).ok()
+        }))
+    }
+
+    // Pins key for server if it has none yet, otherwise checks it matches.
+    // Returns true if the key was newly pinned.
+    pub fn check_or_pin(&self, server: &str, key: &BigInt) -> Result<bool, PinError> {
+        match self.get(server)? {
+            Some(pinned) if pinned == *key => Ok(false),
+            Some(pinned) => Err(PinError::Mismatch {
+                server: server.to_string(),
+                pinned: pinned.to_str_radix(16),
+            }),
+            None => {
+                if let Some(dir) = self.path.parent() {
+                    fs::create_dir_all(dir)?;
+                }
+                let mut file = fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&self.path)?;
+                writeln!(file, "{} {}", server, key.to_str_radix(16))?;
+                Ok(true)
+            }
+        }
+    }
+}
+
+// What the prover sent in AuthChallengeRequest, needed to check and answer
+// the response
+pub struct ChallengeCommitment<'a> {
+    pub user: &'a str,
+    pub r1: &'a BigInt,
+    pub r2: &'a BigInt,
+    pub session_public_key: Option<&'a BigInt>,
+    // binding of the TLS connection the request was sent on, when asked for
+    pub channel_binding: Option<&'a ChannelBinding>,
+    pub audience: &'a str,
+    pub scopes: &'a [String],
+}
+
+impl ChallengeCommitment<'_> {
+    // Exponent to answer with s = k - c * x, the sent c or H(c, context) when
+    // the login asked for an audience, scopes or channel binding
+    pub fn challenge(&self, response: &AuthChallengeResponse) -> Result<BigInt, ValidationError> {
+        let c = parse_challenge(response)?;
+        Ok(self.context(response).challenge(&c))
+    }
+
+    // Context the prover asked for, with the validity period the verifier
+    // set in response
+    fn context(&self, response: &AuthChallengeResponse) -> ChallengeContext<'_> {
+        ChallengeContext {
+            audience: self.audience,
+            scopes: self.scopes,
+            issued_at: response.issued_at,
+            expires_at: response.expires_at,
+            channel_binding: self.channel_binding,
+        }
+    }
+
+    // Hash of the transcript the verifier signed for response, along with the
+    // ephemeral key it sent if any
+    fn transcript(
+        &self,
+        response: &AuthChallengeResponse,
+    ) -> Result<([u8; 32], Option<BigInt>), ValidationError> {
+        let c = parse_challenge(response)?;
+        let ephemeral = match response.ephemeral.as_str() {
+            "" => None,
+            ephemeral => Some(parse_group_element("ephemeral", ephemeral)?),
+        };
+        let transcript = ChallengeTranscript {
+            user: self.user,
+            auth_id: &response.auth_id,
+            r1: self.r1,
+            r2: self.r2,
+            c: &c,
+            ephemeral: ephemeral.as_ref(),
+            session_public_key: self.session_public_key,
+            context: self.context(response),
+        }
+        .hash();
+        Ok((transcript, ephemeral))
+    }
+}
+
+// Checks the verifier's signature over the challenge transcript before the
+// prover answers a challenge
+pub fn verify_challenge_signature(
+    verifier_key: &BigInt,
+    commitment: &ChallengeCommitment,
+    response: &AuthChallengeResponse,
+) -> bool {
+    let signature = match response
+        .verifier_signature
+        .as_ref()
+        .map(schnorr::Signature::try_from)
+    {
+        Some(Ok(signature)) => signature,
+        _ => return false,
+    };
+    match commitment.transcript(response) {
+        Ok((transcript, _)) => schnorr::verify(verifier_key, &transcript, &signature),
+        Err(_) => false,
+    }
+}
+
+// Checks in strict mode that the c in response opens the commitment from
+// CommitChallenge, so the verifier fixed c before it saw r1 and r2
+pub fn verify_challenge_opening(
+    user: &str,
+    committed: &ChallengeCommitResponse,
+    response: &AuthChallengeResponse,
+) -> bool {
+    let c = match parse_challenge(response) {
+        Ok(c) => c,
+        Err(_) => return false,
+    };
+    let commitment = CommittedChallengeTranscript {
+        user,
+        commit_id: &committed.commit_id,
+        c: &c,
+        opening: &response.opening,
+        issued_at: response.issued_at,
+        expires_at: response.expires_at,
+    }
+    .hash();
+    let commitment: String = commitment.iter().map(|b| format!("{:02x}", b)).collect();
+    commitment == committed.commitment
+}
+
+// Derives the session key from B^k when the verifier sent an ephemeral key B.
+// The response must already have passed verify_challenge_signature.
+pub fn derive_session_key(
+    k: &BigInt,
+    commitment: &ChallengeCommitment,
+    response: &AuthChallengeResponse,
+) -> Result<Option<SessionKey>, ValidationError> {
+    let (transcript, ephemeral) = commitment.transcript(response)?;
+    Ok(ephemeral.map(|ephemeral| {
+        let shared = session_key::shared_secret(&ephemeral, k);
+        SessionKey::derive(&shared, &transcript)
+    }))
+}
+
+fn parse_challenge(response: &AuthChallengeResponse) -> Result<BigInt, ValidationError> {
+    BigInt::from_str_radix(&response.c, 16).map_err(|_| ValidationError::NotBase16("c"))
+}
@@ -1,25 +1,59 @@
 use num_bigint::BigInt;
+use std::sync::OnceLock;
+
+use crate::group::{FixedBaseTable, Montgomery};
 
 pub struct PublicParams;
 
+// Window size of the fixed base tables for g and h, ~3MB each
+const TABLE_WINDOW: usize = 5;
+
+static G: OnceLock<BigInt> = OnceLock::new();
+static H: OnceLock<BigInt> = OnceLock::new();
+static P: OnceLock<BigInt> = OnceLock::new();
+static Q: OnceLock<BigInt> = OnceLock::new();
+static MONTGOMERY: OnceLock<Montgomery> = OnceLock::new();
+static G_TABLE: OnceLock<FixedBaseTable> = OnceLock::new();
+static H_TABLE: OnceLock<FixedBaseTable> = OnceLock::new();
+
 // https://datatracker.ietf.org/doc/html/rfc3526#section-3
-const P_PRIME: &str = "ffffffffffffffffc90fdaa22168c234c4c6628b80dc1cd129024e088a67cc74020bbea63b139b22514a08798e3404ddef9519b3cd3a431b302b0a6df25f14374fe1356d6d51c245e485b576625e7ec6f44c42e9a637ed6b0bff5cb6f406b7edee386bfb5a899fa5ae9f24117c4b1fe649286651ece45b3dc2007cb8a163bf0598da48361c55d39a69163fa8fd24cf5f83655d23dca3ad961c62f356208552bb9ed529077096966d670c354e4abc9804f1746c08ca18217c32905e462e36ce3be39e772c180e86039b2783a2ec07a28fb5c55df06f4c52c9de2bcbf6955817183995497cea956ae515d2261898fa051015728e5a8aacaa68ffffffffffffffff";
-const Q_SUBPRIME: &str = "7fffffffffffffffe487ed5110b4611a62633145c06e0e68948127044533e63a0105df531d89cd9128a5043cc71a026ef7ca8cd9e69d218d98158536f92f8a1ba7f09ab6b6a8e122f242dabb312f3f637a262174d31bf6b585ffae5b7a035bf6f71c35fdad44cfd2d74f9208be258ff324943328f6722d9ee1003e5c50b1df82cc6d241b0e2ae9cd348b1fd47e9267afc1b2ae91ee51d6cb0e3179ab1042a95dcf6a9483b84b4b36b3861aa7255e4c0278ba3604650c10be19482f23171b671df1cf3b960c074301cd93c1d17603d147dae2aef837a62964ef15e5fb4aac0b8c1ccaa4be754ab5728ae9130c4c7d02880ab9472d455655347fffffffffffffff";
+pub(crate) const P_PRIME: &str = "ffffffffffffffffc90fdaa22168c234c4c6628b80dc1cd129024e088a67cc74020bbea63b139b22514a08798e3404ddef9519b3cd3a431b302b0a6df25f14374fe1356d6d51c245e485b576625e7ec6f44c42e9a637ed6b0bff5cb6f406b7edee386bfb5a899fa5ae9f24117c4b1fe649286651ece45b3dc2007cb8a163bf0598da48361c55d39a69163fa8fd24cf5f83655d23dca3ad961c62f356208552bb9ed529077096966d670c354e4abc9804f1746c08ca18217c32905e462e36ce3be39e772c180e86039b2783a2ec07a28fb5c55df06f4c52c9de2bcbf6955817183995497cea956ae515d2261898fa051015728e5a8aacaa68ffffffffffffffff";
+pub(crate) const Q_SUBPRIME: &str = "7fffffffffffffffe487ed5110b4611a62633145c06e0e68948127044533e63a0105df531d89cd9128a5043cc71a026ef7ca8cd9e69d218d98158536f92f8a1ba7f09ab6b6a8e122f242dabb312f3f637a262174d31bf6b585ffae5b7a035bf6f71c35fdad44cfd2d74f9208be258ff324943328f6722d9ee1003e5c50b1df82cc6d241b0e2ae9cd348b1fd47e9267afc1b2ae91ee51d6cb0e3179ab1042a95dcf6a9483b84b4b36b3861aa7255e4c0278ba3604650c10be19482f23171b671df1cf3b960c074301cd93c1d17603d147dae2aef837a62964ef15e5fb4aac0b8c1ccaa4be754ab5728ae9130c4c7d02880ab9472d455655347fffffffffffffff";
 
+// Parameters are parsed once on first use and shared afterwards
 impl PublicParams {
-    pub fn g() -> BigInt {
-        BigInt::from(4)
+    pub fn g() -> &'static BigInt {
+        G.get_or_init(|| BigInt::from(4))
+    }
+
+    pub fn h() -> &'static BigInt {
+        H.get_or_init(|| BigInt::from(9))
+    }
+
+    pub fn p() -> &'static BigInt {
+        P.get_or_init(|| BigInt::parse_bytes(P_PRIME.as_bytes(), 16).unwrap())
+    }
+
+    pub fn q() -> &'static BigInt {
+        Q.get_or_init(|| BigInt::parse_bytes(Q_SUBPRIME.as_bytes(), 16).unwrap())
+    }
+
+    // Montgomery arithmetic modulo p
+    pub fn montgomery() -> &'static Montgomery {
+        MONTGOMERY.get_or_init(|| Montgomery::new(Self::p()))
     }
 
-    pub fn h() -> BigInt {
-        BigInt::from(9)
+    // Fixed base table for g^e with 0 <= e < q
+    pub fn g_table() -> &'static FixedBaseTable {
+        G_TABLE.get_or_init(|| Self::table(Self::g()))
     }
 
-    pub fn p() -> BigInt {
-        BigInt::parse_bytes(P_PRIME.as_bytes(), 16).unwrap()
+    // Fixed base table for h^e with 0 <= e < q
+    pub fn h_table() -> &'static FixedBaseTable {
+        H_TABLE.get_or_init(|| Self::table(Self::h()))
     }
 
-    pub fn q() -> BigInt {
-        BigInt::parse_bytes(Q_SUBPRIME.as_bytes(), 16).unwrap()
+    fn table(base: &BigInt) -> FixedBaseTable {
+        FixedBaseTable::new(Self::montgomery(), base, Self::q().bits(), TABLE_WINDOW)
     }
 }
@@ -0,0 +1,211 @@
+use hmac::{Hmac, Mac};
+use moka::sync::Cache;
+use sha2::Sha256;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tonic::Status;
+
+use crate::blind_auth_api::Puzzle;
+use crate::clock::unix_seconds;
+use crate::pop::{hex, unhex};
+use crate::rng::RngProvider;
+use crate::transcript::Transcript;
+
+type HmacSha256 = Hmac<Sha256>;
+
+// How long a puzzle can be solved and used for
+pub const PUZZLE_TTL: Duration = Duration::from_secs(60);
+
+// Request counters kept at once, a flood from many addresses evicts the
+// least used ones rather than growing the cache
+const MAX_COUNTERS: u64 = 100_000;
+
+// When challenge requests have to come with a solved puzzle
+#[derive(Clone, Debug)]
+pub struct PuzzleConfig {
+    // requests per window a single peer can make without a puzzle
+    pub peer_rate: u64,
+    // requests per window all peers together can make without a puzzle
+    pub global_rate: u64,
+    pub window: Duration,
+    // leading zero bits asked for just above the rate, one more for every
+    // doubling of the rate
+    pub base_difficulty: u32,
+    pub max_difficulty: u32,
+}
+
+impl Default for PuzzleConfig {
+    fn default() -> Self {
+        Self {
+            peer_rate: 10,
+            global_rate: 1000,
+            window: Duration::from_secs(10),
+            base_difficulty: 16,
+            max_difficulty: 24,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PuzzleError {
+    // the mac or the solution does not check out
+    Invalid,
+    Expired,
+    Reused,
+}
+
+impl fmt::Display for PuzzleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PuzzleError::Invalid => write!(f, "puzzle solution is invalid"),
+            PuzzleError::Expired => write!(f, "puzzle has expired"),
+            PuzzleError::Reused => write!(f, "puzzle was already used"),
+        }
+    }
+}
+
+impl From<PuzzleError> for Status {
+    fn from(err: PuzzleError) -> Self {
+        Status::permission_denied(err.to_string())
+    }
+}
+
+// Counts challenge requests and hands out and checks puzzles. Puzzles are
+// stateless, only solutions that were accepted are remembered.
+pub struct PuzzleGuard {
+    config: PuzzleConfig,
+    key: [u8; 32],
+    // requests per (peer, window), "" counts all peers
+    requests: Cache<(String, u64), Arc<AtomicU64>>,
+    solved: Cache<(String, String), ()>,
+}
+
+impl PuzzleGuard {
+    pub fn new(config: PuzzleConfig, rng: &dyn RngProvider) -> Self {
+        let mut key = [0u8; 32];
+        rng.fill_bytes(&mut key);
+        Self {
+            requests: Cache::builder()
+                .time_to_live(config.window * 2)
+                .max_capacity(MAX_COUNTERS)
+                .build(),
+            solved: Cache::builder().time_to_live(PUZZLE_TTL).build(),
+            config,
+            key,
+        }
+    }
+
+    // Counts a request from peer and returns the difficulty of the puzzle it
+    // has to solve, 0 if it can go ahead without one
+    pub fn record(&self, peer: &str, now: SystemTime) -> u32 {
+        let window = unix_seconds(now) / self.config.window.as_secs().max(1);
+        let peer_count = self.count(peer, window);
+        let global_count = self.count("", window);
+
+        let load = f64::max(
+            peer_count as f64 / self.config.peer_rate.max(1) as f64,
+            global_count as f64 / self.config.global_rate.max(1) as f64,
+        );
+        if load <= 1.0 {
+            return 0;
+        }
+        let difficulty = self.config.base_difficulty + load.log2().floor() as u32;
+        difficulty.min(self.config.max_difficulty)
+    }
+
+    pub fn issue(
+        &self,
+        user: &str,
+        difficulty: u32,
+        now: SystemTime,
+        rng: &dyn RngProvider,
+    ) -> Puzzle {
+        let mut nonce = [0u8; 16];
+        rng.fill_bytes(&mut nonce);
+        let mut puzzle = Puzzle {
+            issued_at: unix_seconds(now),
+            nonce: hex(&nonce),
+            difficulty,
+            mac: String::new(),
+        };
+        puzzle.mac = hex(&self.mac(user, &puzzle).finalize().into_bytes());
+        puzzle
+    }
+
+    // Accepts each solution to one of our puzzles for user once
+    pub fn check(
+        &self,
+        user: &str,
+        puzzle: &Puzzle,
+        solution: u64,
+        now: SystemTime,
+    ) -> Result<(), PuzzleError> {
+        let mac_valid =
+            unhex(&puzzle.mac).is_some_and(|mac| self.mac(user, puzzle).verify_slice(&mac).is_ok());
+        if !mac_valid || !is_solution(user, puzzle, solution) {
+            return Err(PuzzleError::Invalid);
+        }
+        if unix_seconds(now) >= puzzle.issued_at + PUZZLE_TTL.as_secs() {
+            return Err(PuzzleError::Expired);
+        }
+        let fresh = self
+            .solved
+            .entry((user.to_string(), puzzle.nonce.clone()))
+            .or_insert(())
+            .is_fresh();
+        if !fresh {
+            return Err(PuzzleError::Reused);
+        }
+        Ok(())
+    }
+
+    fn count(&self, key: &str, window: u64) -> u64 {
+        self.requests
+            .get_with((key.to_string(), window), Default::default)
+            .fetch_add(1, Ordering::Relaxed)
+            + 1
+    }
+
+    fn mac(&self, user: &str, puzzle: &Puzzle) -> HmacSha256 {
+        let mut transcript = Transcript::new("blind-auth/puzzle-mac/v1");
+        transcript
+            .append("user", user.as_bytes())
+            .append("issued_at", &puzzle.issued_at.to_be_bytes())
+            .append("nonce", puzzle.nonce.as_bytes())
+            .append("difficulty", &puzzle.difficulty.to_be_bytes());
+        HmacSha256::new_from_slice(&self.key)
+            .expect("HMAC accepts any key length")
+            .chain_update(transcript.finalize())
+    }
+}
+
+pub fn is_solution(user: &str, puzzle: &Puzzle, solution: u64) -> bool {
+    let mut transcript = Transcript::new("blind-auth/puzzle/v1");
+    transcript
+        .append("user", user.as_bytes())
+        .append("issued_at", &puzzle.issued_at.to_be_bytes())
+        .append("nonce", puzzle.nonce.as_bytes())
+        .append("difficulty", &puzzle.difficulty.to_be_bytes())
+        .append("solution", &solution.to_be_bytes());
+    leading_zero_bits(&transcript.finalize()) >= puzzle.difficulty
+}
+
+// Finds the smallest solution, takes about 2^difficulty hashes
+pub fn solve(user: &str, puzzle: &Puzzle) -> u64 {
+    (0..)
+        .find(|&solution| is_solution(user, puzzle, solution))
+        .expect("a solution exists below 2^64")
+}
+
+fn leading_zero_bits(hash: &[u8]) -> u32 {
+    let mut bits = 0;
+    for byte in hash {
+        bits += byte.leading_zeros();
+        if *byte != 0 {
+            break;
+        }
+    }
+    bits
+}
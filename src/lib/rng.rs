@@ -0,0 +1,68 @@
+use rand::rngs::{OsRng, StdRng};
+use rand::{RngCore, SeedableRng};
+use std::sync::Mutex;
+
+// Source of randomness, injected so transcripts can be reproduced in tests
+pub trait RngProvider: Send + Sync {
+    fn fill_bytes(&self, dest: &mut [u8]);
+}
+
+impl<'a> dyn RngProvider + 'a {
+    // Borrows the provider as a rand::RngCore
+    pub fn rng(&self) -> ProviderRng<'_> {
+        ProviderRng(self)
+    }
+}
+
+// Operating system randomness
+pub struct OsRngProvider;
+
+impl RngProvider for OsRngProvider {
+    fn fill_bytes(&self, dest: &mut [u8]) {
+        OsRng.fill_bytes(dest)
+    }
+}
+
+// Deterministic randomness from a fixed seed, for tests only
+pub struct SeededRng {
+    rng: Mutex<StdRng>,
+}
+
+impl SeededRng {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            rng: Mutex::new(StdRng::seed_from_u64(seed)),
+        }
+    }
+}
+
+impl RngProvider for SeededRng {
+    fn fill_bytes(&self, dest: &mut [u8]) {
+        self.rng.lock().unwrap().fill_bytes(dest)
+    }
+}
+
+pub struct ProviderRng<'a>(&'a dyn RngProvider);
+
+impl RngCore for ProviderRng<'_> {
+    fn next_u32(&mut self) -> u32 {
+        let mut bytes = [0u8; 4];
+        self.0.fill_bytes(&mut bytes);
+        u32::from_le_bytes(bytes)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        let mut bytes = [0u8; 8];
+        self.0.fill_bytes(&mut bytes);
+        u64::from_le_bytes(bytes)
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.0.fill_bytes(dest)
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.0.fill_bytes(dest);
+        Ok(())
+    }
+}
@@ -0,0 +1,102 @@
+use num_bigint::{BigInt, Sign};
+use num_traits::Zero;
+
+use crate::group::multi_exp;
+use crate::prover::compute_auth_secret;
+use crate::public_params::PublicParams;
+use crate::random_in_range;
+use crate::rng::RngProvider;
+use crate::transcript::Transcript;
+use crate::validation::{parse_scalar, ValidationError};
+
+// Schnorr signatures in the same group as the authentication protocol, i.e.
+// a Fiat-Shamir transformed proof of knowledge of v for V = g^v (mod p).
+#[derive(Clone)]
+pub struct KeyPair {
+    secret: BigInt,
+    pub public: BigInt,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Signature {
+    pub e: BigInt,
+    pub s: BigInt,
+}
+
+impl KeyPair {
+    pub fn generate(rng: &dyn RngProvider) -> Self {
+        let secret = random_in_range(&mut rng.rng(), &BigInt::from(1), PublicParams::q());
+        Self::from_secret(secret)
+    }
+
+    pub fn from_secret(secret: BigInt) -> Self {
+        let public = pow_g(&secret);
+        Self { secret, public }
+    }
+
+    pub fn secret(&self) -> &BigInt {
+        &self.secret
+    }
+
+    // w random, R = g^w, e = H(V, R, msg), s = w - e * v (mod q)
+    pub fn sign(&self, message: &[u8], rng: &dyn RngProvider) -> Signature {
+        let w = random_in_range(&mut rng.rng(), &BigInt::from(1), PublicParams::q());
+        let e = challenge(&self.public, &pow_g(&w), message);
+        let s = compute_auth_secret(e.clone(), w, self.secret.clone());
+        Signature { e, s }
+    }
+}
+
+// Accepts iff e == H(V, g^s * V^e, msg)
+pub fn verify(public: &BigInt, message: &[u8], signature: &Signature) -> bool {
+    let q = PublicParams::q();
+    if signature.e < BigInt::zero()
+        || signature.e >= *q
+        || signature.s < BigInt::zero()
+        || signature.s >= *q
+    {
+        return false;
+    }
+    let r = multi_exp(
+        PublicParams::montgomery(),
+        &[(PublicParams::g_table(), &signature.s)],
+        &[(public, &signature.e)],
+    );
+    challenge(public, &r, message) == signature.e
+}
+
+fn challenge(public: &BigInt, r: &BigInt, message: &[u8]) -> BigInt {
+    let mut transcript = Transcript::new("blind-auth/schnorr/v1");
+    transcript
+        .append_int("public", public)
+        .append_int("r", r)
+        .append("message", message);
+    BigInt::from_bytes_be(Sign::Plus, &transcript.finalize()) % PublicParams::q()
+}
+
+fn pow_g(exponent: &BigInt) -> BigInt {
+    #[cfg(feature = "constant-time")]
+    return crate::ct::pow_mod_p(PublicParams::g(), exponent);
+    #[cfg(not(feature = "constant-time"))]
+    return PublicParams::g().modpow(exponent, PublicParams::p());
+}
+
+impl From<Signature> for crate::blind_auth_api::Signature {
+    fn from(signature: Signature) -> Self {
+        Self {
+            e: signature.e.to_str_radix(16),
+            s: signature.s.to_str_radix(16),
+        }
+    }
+}
+
+impl TryFrom<&crate::blind_auth_api::Signature> for Signature {
+    type Error = ValidationError;
+
+    fn try_from(signature: &crate::blind_auth_api::Signature) -> Result<Self, Self::Error> {
+        Ok(Signature {
+            e: parse_scalar("e", &signature.e)?,
+            s: parse_scalar("s", &signature.s)?,
+        })
+    }
+}
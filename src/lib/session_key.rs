@@ -0,0 +1,86 @@
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
+use num_bigint::BigInt;
+use sha2::Sha256;
+
+type HmacSha256 = Hmac<Sha256>;
+
+// Symmetric key shared by prover and verifier after an authenticated key
+// exchange. It is derived on both sides and never sent over the wire, requests
+// are authenticated with a MAC under it instead of presenting a bearer token.
+#[derive(Clone)]
+pub struct SessionKey {
+    // public identifier both sides derive alongside the key
+    pub id: String,
+    key: [u8; 32],
+}
+
+impl SessionKey {
+    // HKDF-SHA256 over the shared element g^kb, salted with the hash of the
+    // authenticated transcript
+    pub fn derive(shared: &BigInt, transcript: &[u8; 32]) -> Self {
+        let (_, ikm) = shared.to_bytes_be();
+        let hkdf = Hkdf::<Sha256>::new(Some(transcript), &ikm);
+
+        let mut key = [0u8; 32];
+        hkdf.expand(b"blind-auth/session-key/v1", &mut key)
+            .expect("32 bytes is a valid hkdf output length");
+        let mut id = [0u8; 16];
+        hkdf.expand(b"blind-auth/key-id/v1", &mut id)
+            .expect("16 bytes is a valid hkdf output length");
+
+        Self {
+            id: id.iter().map(|b| format!("{:02x}", b)).collect(),
+            key,
+        }
+    }
+
+    // HMAC-SHA256 over (key id, method, timestamp, nonce, body) for a
+    // follow-up request, see pop::mac_request. The verifier rejects stale
+    // timestamps and nonces it has seen, so a MAC can not be replayed.
+    pub fn mac(&self, method: &str, timestamp: u64, nonce: &str, body: &[u8]) -> [u8; 32] {
+        self.request_mac(method, timestamp, nonce, body)
+            .finalize()
+            .into_bytes()
+            .into()
+    }
+
+    // Checks a MAC produced by mac, in constant time
+    pub fn verify(
+        &self,
+        method: &str,
+        timestamp: u64,
+        nonce: &str,
+        body: &[u8],
+        tag: &[u8],
+    ) -> bool {
+        self.request_mac(method, timestamp, nonce, body)
+            .verify_slice(tag)
+            .is_ok()
+    }
+
+    fn request_mac(&self, method: &str, timestamp: u64, nonce: &str, body: &[u8]) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(&self.key).expect("hmac accepts any key length");
+        let timestamp = timestamp.to_be_bytes();
+        for part in [
+            self.id.as_bytes(),
+            method.as_bytes(),
+            &timestamp,
+            nonce.as_bytes(),
+            body,
+        ] {
+            mac.update(&(part.len() as u64).to_be_bytes());
+            mac.update(part);
+        }
+        mac
+    }
+}
+
+// peer^secret (mod p), the Diffie-Hellman shared element. The exponent is
+// secret (b or k), so this runs in constant time when the feature is enabled.
+pub fn shared_secret(peer: &BigInt, secret: &BigInt) -> BigInt {
+    #[cfg(feature = "constant-time")]
+    return crate::ct::pow_mod_p(peer, secret);
+    #[cfg(not(feature = "constant-time"))]
+    return peer.modpow(secret, crate::public_params::PublicParams::p());
+}
@@ -0,0 +1,99 @@
+use num_bigint::BigInt;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use tonic::Status;
+
+use crate::pop::{hex, unhex};
+use crate::rng::RngProvider;
+use crate::schnorr::{self, KeyPair, Signature};
+use crate::transcript::Transcript;
+
+// Session tokens let services check a session without asking the verifier:
+// the session's claims in base16 JSON, signed with the verifier key, as
+// "<claims>.<e>.<s>". They are bearer credentials that stay valid until they
+// expire, even if the session is revoked, so they are kept short-lived.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SessionClaims {
+    pub session_id: String,
+    pub user: String,
+    pub audience: String,
+    pub scopes: Vec<String>,
+    pub roles: Vec<String>,
+    pub attributes: BTreeMap<String, String>,
+    // unix seconds
+    pub issued_at: u64,
+    pub expires_at: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TokenError {
+    Malformed,
+    InvalidSignature,
+    Expired,
+}
+
+impl fmt::Display for TokenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TokenError::Malformed => write!(f, "session token is malformed"),
+            TokenError::InvalidSignature => write!(f, "session token signature is invalid"),
+            TokenError::Expired => write!(f, "session token has expired"),
+        }
+    }
+}
+
+impl std::error::Error for TokenError {}
+
+impl From<TokenError> for Status {
+    fn from(err: TokenError) -> Self {
+        Status::unauthenticated(err.to_string())
+    }
+}
+
+// Signs claims with the verifier key
+pub fn issue(claims: &SessionClaims, key: &KeyPair, rng: &dyn RngProvider) -> String {
+    let json = serde_json::to_vec(claims).expect("claims serialize");
+    let signature = key.sign(&token_transcript(&json), rng);
+    format!(
+        "{}.{}.{}",
+        hex(&json),
+        signature.e.to_str_radix(16),
+        signature.s.to_str_radix(16)
+    )
+}
+
+// Returns the claims of a token signed by verifier_key that has not expired
+// at now, in unix seconds
+pub fn verify(token: &str, verifier_key: &BigInt, now: u64) -> Result<SessionClaims, TokenError> {
+    let mut parts = token.split('.');
+    let (json, e, s) = match (parts.next(), parts.next(), parts.next(), parts.next()) {
+        (Some(json), Some(e), Some(s), None) => (json, e, s),
+        _ => return Err(TokenError::Malformed),
+    };
+    let json = unhex(json).ok_or(TokenError::Malformed)?;
+    let signature = Signature::try_from(&crate::blind_auth_api::Signature {
+        e: e.to_string(),
+        s: s.to_string(),
+    })
+    .map_err(|_| TokenError::Malformed)?;
+    if !schnorr::verify(verifier_key, &token_transcript(&json), &signature) {
+        return Err(TokenError::InvalidSignature);
+    }
+    let claims: SessionClaims = serde_json::from_slice(&json).map_err(|_| TokenError::Malformed)?;
+    if now >= claims.expires_at {
+        return Err(TokenError::Expired);
+    }
+    Ok(claims)
+}
+
+// Tells tokens apart from session ids, which never contain a dot
+pub fn is_token(credential: &str) -> bool {
+    credential.contains('.')
+}
+
+fn token_transcript(claims: &[u8]) -> [u8; 32] {
+    let mut transcript = Transcript::new("blind-auth/session-token/v1");
+    transcript.append("claims", claims);
+    transcript.finalize()
+}
@@ -1,2 +1,4 @@
+pub mod file;
 pub mod models;
+#[allow(clippy::module_inception)]
 pub mod store;
@@ -0,0 +1,144 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+use super::models::{User, UserStatus};
+use crate::blind_auth_api::RegisterRequest;
+use crate::validation::{
+    validate_attributes, validate_roles, validate_scopes, Registration, ValidationError,
+};
+
+// Bumped when the file layout changes incompatibly
+const USER_FILE_VERSION: u32 = 1;
+
+// User as written to the user file and printed by exports, keys in hex like
+// on the wire
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UserRecord {
+    pub id: String,
+    pub y1: String,
+    pub y2: String,
+    #[serde(default)]
+    pub status: UserStatus,
+    // unix seconds
+    #[serde(default)]
+    pub created_at: u64,
+    #[serde(default)]
+    pub last_login_at: Option<u64>,
+    #[serde(default)]
+    pub key_created_at: u64,
+    #[serde(default)]
+    pub key_expires_at: Option<u64>,
+    #[serde(default)]
+    pub locked_until: Option<u64>,
+    #[serde(default)]
+    pub roles: Vec<String>,
+    #[serde(default)]
+    pub scopes: Vec<String>,
+    #[serde(default)]
+    pub attributes: BTreeMap<String, String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct UserFile {
+    version: u32,
+    users: Vec<UserRecord>,
+}
+
+impl From<&User> for UserRecord {
+    fn from(user: &User) -> Self {
+        UserRecord {
+            id: user.id.clone(),
+            y1: user.y1.to_str_radix(16),
+            y2: user.y2.to_str_radix(16),
+            status: user.status,
+            created_at: user.created_at,
+            last_login_at: user.last_login_at,
+            key_created_at: user.key_created_at,
+            key_expires_at: user.key_expires_at,
+            locked_until: user.locked_until,
+            roles: user.roles.clone(),
+            scopes: user.scopes.clone(),
+            attributes: user.attributes.clone(),
+        }
+    }
+}
+
+impl TryFrom<&UserRecord> for User {
+    type Error = io::Error;
+
+    // Records come from outside the verifier, so they get the same checks
+    // as a registration and the admin API
+    fn try_from(record: &UserRecord) -> Result<Self, Self::Error> {
+        let invalid = |err: ValidationError| invalid_data(format!("user {:?}: {}", record.id, err));
+        let registration = Registration::try_from(&RegisterRequest {
+            user: record.id.clone(),
+            y1: record.y1.clone(),
+            y2: record.y2.clone(),
+        })
+        .map_err(invalid)?;
+        Ok(User {
+            roles: validate_roles(&record.roles).map_err(invalid)?,
+            scopes: validate_scopes(&record.scopes).map_err(invalid)?,
+            attributes: validate_attributes(&record.attributes).map_err(invalid)?,
+            id: registration.user,
+            y1: registration.y1,
+            y2: registration.y2,
+            status: record.status,
+            created_at: record.created_at,
+            last_login_at: record.last_login_at,
+            key_created_at: record.key_created_at,
+            key_expires_at: record.key_expires_at,
+            failed_logins: 0,
+            locked_until: record.locked_until,
+        })
+    }
+}
+
+// The user file format, also what exports are written in
+pub fn to_json(records: &[UserRecord]) -> io::Result<String> {
+    let file = UserFile {
+        version: USER_FILE_VERSION,
+        users: records.to_vec(),
+    };
+    serde_json::to_string_pretty(&file).map_err(io::Error::from)
+}
+
+// Records are not validated here, see TryFrom<&UserRecord> for User
+pub fn from_json(json: &str) -> io::Result<Vec<UserRecord>> {
+    let file: UserFile = serde_json::from_str(json)?;
+    if file.version != USER_FILE_VERSION {
+        return Err(invalid_data(format!(
+            "unsupported user file version {}",
+            file.version
+        )));
+    }
+    Ok(file.users)
+}
+
+// A missing file is an empty user database
+pub fn load(path: &Path) -> io::Result<Vec<User>> {
+    match fs::read_to_string(path) {
+        Ok(json) => from_json(&json)?.iter().map(User::try_from).collect(),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(vec![]),
+        Err(err) => Err(err),
+    }
+}
+
+// Writes to a temporary file first and renames it over path, so a crash
+// leaves either the old or the new database behind
+pub fn save(path: &Path, users: &[User]) -> io::Result<()> {
+    let records: Vec<UserRecord> = users.iter().map(UserRecord::from).collect();
+    let json = to_json(&records)?;
+    let tmp = path.with_extension("tmp");
+    let mut file = fs::File::create(&tmp)?;
+    file.write_all(json.as_bytes())?;
+    file.sync_all()?;
+    fs::rename(&tmp, path)
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
@@ -1,10 +1,72 @@
 use num_bigint::BigInt;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
 
-#[derive(Clone)]
+use crate::schnorr::KeyPair;
+use crate::session_key::SessionKey;
+use crate::tls::ChannelBinding;
+
+#[derive(Clone, Default)]
 pub struct User {
     pub id: String,
     pub y1: BigInt,
     pub y2: BigInt,
+    pub status: UserStatus,
+    // unix seconds
+    pub created_at: u64,
+    pub last_login_at: Option<u64>,
+    // when y1, y2 were registered, logins are refused after key_expires_at
+    pub key_created_at: u64,
+    pub key_expires_at: Option<u64>,
+    // failed proofs since the last successful login
+    pub failed_logins: u32,
+    // end of the lock when Locked
+    pub locked_until: Option<u64>,
+    // set by an admin and copied into sessions at login
+    pub roles: Vec<String>,
+    pub attributes: BTreeMap<String, String>,
+    // scopes the user may ask for at login
+    pub scopes: Vec<String>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UserStatus {
+    #[default]
+    Active,
+    // can not log in, set by an admin
+    Disabled,
+    // too many failed logins, lifts itself at locked_until
+    Locked,
+    // registered but waiting for an admin to enable it
+    Pending,
+}
+
+impl fmt::Display for UserStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UserStatus::Active => write!(f, "active"),
+            UserStatus::Disabled => write!(f, "disabled"),
+            UserStatus::Locked => write!(f, "locked"),
+            UserStatus::Pending => write!(f, "pending"),
+        }
+    }
+}
+
+impl FromStr for UserStatus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "active" => Ok(UserStatus::Active),
+            "disabled" => Ok(UserStatus::Disabled),
+            "locked" => Ok(UserStatus::Locked),
+            "pending" => Ok(UserStatus::Pending),
+            _ => Err(format!("unknown user status {:?}", s)),
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -14,10 +76,45 @@ pub struct Challenge {
     pub r2: BigInt,
     pub user_id: String,
     pub id: String,
+    // verifier's ephemeral key pair when the prover asked for a key exchange
+    pub ephemeral: Option<KeyPair>,
+    // key the session will be bound to
+    pub session_public_key: Option<BigInt>,
+    // TLS connection the challenge was requested on, the answer must use it too
+    pub channel_binding: Option<ChannelBinding>,
+    // service and scopes the session will be granted
+    pub audience: String,
+    pub scopes: Vec<String>,
+    // unix seconds, the challenge can not be answered after expires_at
+    pub issued_at: u64,
+    pub expires_at: u64,
+}
+
+// Challenge the verifier committed to before seeing the prover's commitments
+#[derive(Clone)]
+pub struct CommittedChallenge {
+    pub id: String,
+    pub user_id: String,
+    pub c: BigInt,
+    // random value hiding c in the commitment
+    pub opening: String,
+    pub issued_at: u64,
+    pub expires_at: u64,
 }
 
 #[derive(Clone)]
 pub struct Session {
     pub id: String,
     pub user_id: String,
+    pub key: Option<SessionKey>,
+    // requests on the session must be signed with this key when set
+    pub public_key: Option<BigInt>,
+    // requests on the session must arrive on this TLS connection when set
+    pub channel_binding: Option<ChannelBinding>,
+    // service and scopes the session was granted at login
+    pub audience: String,
+    pub scopes: Vec<String>,
+    // the user's roles and attributes at login
+    pub roles: Vec<String>,
+    pub attributes: BTreeMap<String, String>,
 }
@@ -1,47 +1,403 @@
-use super::models::{Challenge, Session, User};
+use super::file;
+use super::models::{Challenge, CommittedChallenge, Session, User, UserStatus};
+use crate::clock::{Clock, SystemClock};
+use log::error;
 use moka::sync::Cache;
-use std::time::Duration;
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, Condvar, Mutex, Weak};
+use std::thread;
+use std::time::{Duration, SystemTime};
 
+pub const CHALLENGE_TTL: Duration = Duration::from_secs(10);
+pub const SESSION_TTL: Duration = Duration::from_secs(360);
+
+// Challenges and committed challenges a user can have outstanding at once
+pub const MAX_PENDING_CHALLENGES: usize = 16;
+
+// How long a commitment (r1, r2) is remembered for nonce reuse detection
+const COMMITMENT_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+const MAX_COMMITMENTS: u64 = 1_000_000;
+
+// How long a signed request nonce is remembered, long enough to outlive the
+// timestamp window requests are accepted in
+pub const REQUEST_NONCE_TTL: Duration = Duration::from_secs(2 * 60);
+
+// Changes to the users reach the user file this long after they were made,
+// the changes in between share one write
+pub const USER_FILE_DELAY: Duration = Duration::from_secs(1);
+
+// Cached value with a deadline taken from the store clock. The cache TTL only
+// bounds memory, expiry is decided against the clock on read.
+#[derive(Clone)]
+struct Expiring<T> {
+    value: T,
+    expires_at: SystemTime,
+}
+
+// Ids and deadlines of the challenges issued to a user
+type PendingChallenges = Arc<Mutex<Vec<(String, SystemTime)>>>;
+
+// File the users are saved to. The path lock orders the writes, dirty marks
+// changes the writer thread has not saved yet.
+struct UserFile {
+    path: Mutex<PathBuf>,
+    dirty: Mutex<bool>,
+    changed: Condvar,
+}
+
+impl UserFile {
+    // The store keeps serving from memory when the write fails, the next
+    // change tries again with the full set of users
+    fn save(&self, users: &Cache<String, User>) {
+        let path = self.path.lock().unwrap();
+        if let Err(err) = file::save(&path, &sorted_users(users, "", usize::MAX)) {
+            error!("failed to save users to {}: {}", path.display(), err);
+        }
+    }
+}
+
+// Clones share the same underlying caches
+#[derive(Clone)]
 pub struct DataStore {
+    clock: Arc<dyn Clock>,
     users: Cache<String, User>,
-    sessions: Cache<String, Session>,
-    challenges: Cache<String, Challenge>,
+    // users are written here after every change, see persist_users
+    user_file: Option<Arc<UserFile>>,
+    user_updates: Arc<Mutex<()>>,
+    sessions: Cache<String, Expiring<Session>>,
+    challenges: Cache<String, Expiring<Challenge>>,
+    committed_challenges: Cache<String, Expiring<CommittedChallenge>>,
+    pending_challenges: Cache<String, PendingChallenges>,
+    commitments: Cache<(String, [u8; 32]), ()>,
+    request_nonces: Cache<(String, String), ()>,
+}
+
+impl Default for DataStore {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl DataStore {
     pub fn new() -> Self {
+        Self::with_clock(Arc::new(SystemClock))
+    }
+
+    pub fn with_clock(clock: Arc<dyn Clock>) -> Self {
         Self {
+            clock,
             users: Cache::builder().build(),
-            challenges: Cache::builder()
-                .time_to_live(Duration::from_secs(10))
-                .build(),
-            sessions: Cache::builder()
-                .time_to_live(Duration::from_secs(360))
+            user_file: None,
+            user_updates: Default::default(),
+            challenges: Cache::builder().time_to_live(CHALLENGE_TTL).build(),
+            committed_challenges: Cache::builder().time_to_live(CHALLENGE_TTL).build(),
+            pending_challenges: Cache::builder().time_to_idle(CHALLENGE_TTL).build(),
+            sessions: Cache::builder().time_to_live(SESSION_TTL).build(),
+            commitments: Cache::builder()
+                .time_to_live(COMMITMENT_TTL)
+                .max_capacity(MAX_COMMITMENTS)
                 .build(),
+            request_nonces: Cache::builder().time_to_live(REQUEST_NONCE_TTL).build(),
+        }
+    }
+
+    // Loads the users in path, if it exists, and keeps it up to date with
+    // every change to them. Sessions and challenges stay in memory only.
+    pub fn with_user_file(mut self, path: PathBuf) -> io::Result<Self> {
+        for user in file::load(&path)? {
+            self.users.insert(user.id.clone(), user);
         }
+        let user_file = Arc::new(UserFile {
+            path: Mutex::new(path),
+            dirty: Mutex::new(false),
+            changed: Condvar::new(),
+        });
+        let (writer, users) = (Arc::downgrade(&user_file), self.users.clone());
+        thread::Builder::new()
+            .name("user-file".to_string())
+            .spawn(move || write_deferred(writer, users))?;
+        self.user_file = Some(user_file);
+        Ok(self)
+    }
+
+    pub fn now(&self) -> SystemTime {
+        self.clock.now()
+    }
+
+    pub fn clock(&self) -> Arc<dyn Clock> {
+        self.clock.clone()
     }
 
     pub fn insert_user(&self, user: User) {
-        self.users.insert(user.id.clone(), user)
+        self.users.insert(user.id.clone(), user);
+        self.users_changed();
+    }
+
+    // Inserts the user unless one with the same id exists, false then
+    pub fn insert_new_user(&self, user: User) -> bool {
+        let _guard = self.user_updates.lock().unwrap();
+        let inserted = self.users.entry(user.id.clone()).or_insert(user).is_fresh();
+        if inserted {
+            self.users_changed();
+        }
+        inserted
+    }
+
+    // Inserts or replaces all users with a single write of the user file
+    pub fn insert_users(&self, users: Vec<User>) {
+        for user in users {
+            self.users.insert(user.id.clone(), user);
+        }
+        self.users_changed();
     }
 
     pub fn get_user(&self, id: &String) -> Option<User> {
-        return self.users.get(id);
+        self.users.get(id)
+    }
+
+    // Up to limit users ordered by id, starting after the given id
+    pub fn list_users(&self, after: &str, limit: usize) -> Vec<User> {
+        sorted_users(&self.users, after, limit)
+    }
+
+    // Read-modify-write of a user, serialized with every other update
+    pub fn update_user<F>(&self, id: &String, update: F) -> Option<User>
+    where
+        F: FnOnce(&mut User),
+    {
+        let _guard = self.user_updates.lock().unwrap();
+        let mut user = self.users.get(id)?;
+        update(&mut user);
+        self.users.insert(id.clone(), user.clone());
+        self.users_changed();
+        Some(user)
+    }
+
+    pub fn set_user_status(&self, id: &String, status: UserStatus) -> Option<User> {
+        self.update_user(id, |user| user.status = status)
+    }
+
+    pub fn remove_user(&self, id: &String) -> Option<User> {
+        let user = self.users.remove(id)?;
+        self.users_changed();
+        Some(user)
+    }
+
+    // Changes to the users only reach the user file USER_FILE_DELAY later,
+    // from a background thread, so no request waits on the disk and changes
+    // in between share a write. Changes in that window are lost if the
+    // process dies. Callers whose reply promises durability, like a
+    // registration or an admin disabling a user, write the file now with
+    // this, on a blocking thread.
+    pub async fn persist_users(&self) {
+        let Some(user_file) = self.user_file.clone() else {
+            return;
+        };
+        let users = self.users.clone();
+        let saved = tokio::task::spawn_blocking(move || {
+            // the writer thread may have taken the flag and not saved yet,
+            // so this writes whether or not it is set
+            *user_file.dirty.lock().unwrap() = false;
+            user_file.save(&users);
+        })
+        .await;
+        if let Err(err) = saved {
+            error!("failed to save users: {}", err);
+        }
     }
 
-    pub fn insert_challenge(&self, challenge: Challenge) {
-        self.challenges.insert(challenge.id.clone(), challenge)
+    // Returns false without storing the challenge if its user already has
+    // MAX_PENDING_CHALLENGES outstanding
+    pub fn insert_challenge(&self, challenge: Challenge) -> bool {
+        let (user_id, id) = (challenge.user_id.clone(), challenge.id.clone());
+        let expiring = self.expiring(challenge, CHALLENGE_TTL);
+        self.insert_pending(&user_id, id, expiring, &self.challenges)
     }
 
     pub fn get_challenge(&self, id: &String) -> Option<Challenge> {
-        return self.challenges.get(id);
+        self.get_unexpired(&self.challenges, id)
+    }
+
+    // Removes and returns a challenge, of concurrent calls for the same id
+    // only one gets it
+    pub fn take_challenge(&self, id: &String) -> Option<Challenge> {
+        let entry = self.challenges.remove(id)?;
+        if self.clock.now() >= entry.expires_at {
+            return None;
+        }
+        Some(entry.value)
+    }
+
+    // Same limit as insert_challenge, both count towards it
+    pub fn insert_committed_challenge(&self, committed: CommittedChallenge) -> bool {
+        let (user_id, id) = (committed.user_id.clone(), committed.id.clone());
+        let expiring = self.expiring(committed, CHALLENGE_TTL);
+        self.insert_pending(&user_id, id, expiring, &self.committed_challenges)
+    }
+
+    // Removes and returns a committed challenge, each can be used once. One
+    // committed for another user is left in place so that guessing its id
+    // does not let anyone burn it
+    pub fn take_committed_challenge(
+        &self,
+        id: &String,
+        user_id: &String,
+    ) -> Option<CommittedChallenge> {
+        if self.committed_challenges.get(id)?.value.user_id != *user_id {
+            return None;
+        }
+        let entry = self.committed_challenges.remove(id)?;
+        if self.clock.now() >= entry.expires_at || entry.value.user_id != *user_id {
+            return None;
+        }
+        Some(entry.value)
     }
 
     pub fn insert_session(&self, session: Session) {
-        self.sessions.insert(session.id.clone(), session)
+        let expiring = self.expiring(session, SESSION_TTL);
+        self.sessions.insert(expiring.value.id.clone(), expiring)
     }
 
     pub fn get_session(&self, id: &String) -> Option<Session> {
-        return self.sessions.get(id);
+        self.get_unexpired(&self.sessions, id)
+    }
+
+    // Live session along with its expiry
+    pub fn get_session_with_expiry(&self, id: &String) -> Option<(Session, SystemTime)> {
+        let entry = self.sessions.get(id)?;
+        if self.clock.now() >= entry.expires_at {
+            self.sessions.invalidate(id);
+            return None;
+        }
+        Some((entry.value, entry.expires_at))
+    }
+
+    // Live sessions of a user along with their expiry
+    pub fn user_sessions(&self, user_id: &str) -> Vec<(Session, SystemTime)> {
+        let now = self.clock.now();
+        self.sessions
+            .iter()
+            .filter(|(_, entry)| entry.value.user_id == user_id && now < entry.expires_at)
+            .map(|(_, entry)| (entry.value, entry.expires_at))
+            .collect()
+    }
+
+    // Returns whether a live session was removed
+    pub fn remove_session(&self, id: &String) -> bool {
+        let now = self.clock.now();
+        self.sessions
+            .remove(id)
+            .is_some_and(|entry| now < entry.expires_at)
+    }
+
+    // Removes every session of a user, returns how many were live
+    pub fn remove_user_sessions(&self, user_id: &str) -> usize {
+        self.user_sessions(user_id)
+            .iter()
+            .filter(|(session, _)| self.remove_session(&session.id))
+            .count()
+    }
+
+    // Records a commitment hash for the user, returns false if it was already seen
+    pub fn insert_commitment(&self, user_id: &str, commitment: [u8; 32]) -> bool {
+        self.commitments
+            .entry((user_id.to_string(), commitment))
+            .or_insert(())
+            .is_fresh()
+    }
+
+    // Records a request nonce for the session, returns false if it was already seen
+    pub fn insert_request_nonce(&self, session_id: &str, nonce: &str) -> bool {
+        self.request_nonces
+            .entry((session_id.to_string(), nonce.to_string()))
+            .or_insert(())
+            .is_fresh()
+    }
+
+    // Wakes the writer thread, see persist_users
+    fn users_changed(&self) {
+        if let Some(user_file) = &self.user_file {
+            *user_file.dirty.lock().unwrap() = true;
+            user_file.changed.notify_one();
+        }
+    }
+
+    fn insert_pending<T>(
+        &self,
+        user_id: &str,
+        id: String,
+        expiring: Expiring<T>,
+        cache: &Cache<String, Expiring<T>>,
+    ) -> bool
+    where
+        T: Clone + Send + Sync + 'static,
+    {
+        let now = self.clock.now();
+        let pending = self
+            .pending_challenges
+            .get_with(user_id.to_string(), Default::default);
+        // the lock makes the count and the insert atomic per user
+        let mut pending = pending.lock().unwrap();
+        pending.retain(|(id, expires_at)| {
+            now < *expires_at
+                && (self.challenges.contains_key(id) || self.committed_challenges.contains_key(id))
+        });
+        if pending.len() >= MAX_PENDING_CHALLENGES {
+            return false;
+        }
+        pending.push((id.clone(), expiring.expires_at));
+        cache.insert(id, expiring);
+        true
+    }
+
+    fn expiring<T>(&self, value: T, ttl: Duration) -> Expiring<T> {
+        Expiring {
+            value,
+            expires_at: self.clock.now() + ttl,
+        }
+    }
+
+    fn get_unexpired<T>(&self, cache: &Cache<String, Expiring<T>>, id: &String) -> Option<T>
+    where
+        T: Clone + Send + Sync + 'static,
+    {
+        let entry = cache.get(id)?;
+        if self.clock.now() >= entry.expires_at {
+            cache.invalidate(id);
+            return None;
+        }
+        Some(entry.value)
+    }
+}
+
+fn sorted_users(users: &Cache<String, User>, after: &str, limit: usize) -> Vec<User> {
+    let mut users: Vec<User> = users
+        .iter()
+        .filter(|(id, _)| id.as_str() > after)
+        .map(|(_, user)| user)
+        .collect();
+    users.sort_by(|a, b| a.id.cmp(&b.id));
+    users.truncate(limit);
+    users
+}
+
+// Writes the users USER_FILE_DELAY after a change, until the last store
+// sharing the file is dropped
+fn write_deferred(user_file: Weak<UserFile>, users: Cache<String, User>) {
+    while let Some(user_file) = user_file.upgrade() {
+        let dirty = user_file.dirty.lock().unwrap();
+        // wakes up now and then to notice the store is gone
+        let (mut dirty, _) = user_file
+            .changed
+            .wait_timeout_while(dirty, Duration::from_secs(1), |dirty| !*dirty)
+            .unwrap();
+        if !*dirty {
+            continue;
+        }
+        *dirty = false;
+        drop(dirty);
+        thread::sleep(USER_FILE_DELAY);
+        user_file.save(&users);
     }
 }
@@ -0,0 +1,335 @@
+use axum::extract::connect_info::Connected as HttpConnected;
+use axum::Router;
+use hyper::server::accept;
+use log::{debug, warn};
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, Semaphore};
+use tokio_rustls::rustls::server::AllowAnyAuthenticatedClient;
+use tokio_rustls::rustls::{self, ClientConfig, ServerConfig, ServerName};
+use tokio_rustls::{server, TlsAcceptor, TlsConnector};
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::transport::server::Connected;
+use tonic::transport::{Channel, Endpoint, Uri};
+use tonic::Request;
+
+pub use crate::transcript::ChannelBinding;
+
+// Exporter label of the tls-exporter channel binding (RFC 9266)
+const EXPORTER_LABEL: &[u8] = b"EXPORTER-Channel-Binding";
+
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Connections waiting for the server to pick them up
+const ACCEPT_BACKLOG: usize = 64;
+
+// Handshakes in flight before incoming stops accepting connections
+pub const DEFAULT_MAX_HANDSHAKES: usize = 256;
+
+impl ChannelBinding {
+    fn export<Data>(conn: &rustls::ConnectionCommon<Data>) -> io::Result<Self> {
+        conn.export_keying_material([0u8; 32], EXPORTER_LABEL, None)
+            .map(ChannelBinding)
+            .map_err(io::Error::other)
+    }
+}
+
+// TLS 1.3 only: RFC 9266 exporters are not safe on TLS 1.2 without
+// extended master secret
+pub fn server_config(cert_pem: &[u8], key_pem: &[u8]) -> io::Result<Arc<ServerConfig>> {
+    build_server_config(cert_pem, key_pem, None)
+}
+
+// Like server_config, for the HTTP listener where clients speak h2 or
+// HTTP/1.1
+pub fn http_server_config(cert_pem: &[u8], key_pem: &[u8]) -> io::Result<Arc<ServerConfig>> {
+    let mut config = build_server_config(cert_pem, key_pem, None)?;
+    Arc::make_mut(&mut config).alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Ok(config)
+}
+
+// Like server_config but only accepts clients presenting a certificate
+// issued by one of the CAs in client_ca_pem
+pub fn server_config_with_client_auth(
+    cert_pem: &[u8],
+    key_pem: &[u8],
+    client_ca_pem: &[u8],
+) -> io::Result<Arc<ServerConfig>> {
+    build_server_config(cert_pem, key_pem, Some(root_store(client_ca_pem)?))
+}
+
+fn build_server_config(
+    cert_pem: &[u8],
+    key_pem: &[u8],
+    client_roots: Option<rustls::RootCertStore>,
+) -> io::Result<Arc<ServerConfig>> {
+    let (certs, key) = (certificates(cert_pem)?, private_key(key_pem)?);
+    let builder = ServerConfig::builder()
+        .with_safe_default_cipher_suites()
+        .with_safe_default_kx_groups()
+        .with_protocol_versions(&[&rustls::version::TLS13])
+        .map_err(invalid_config)?;
+    let builder = match client_roots {
+        Some(roots) => {
+            builder.with_client_cert_verifier(AllowAnyAuthenticatedClient::new(roots).boxed())
+        }
+        None => builder.with_no_client_auth(),
+    };
+    let mut config = builder
+        .with_single_cert(certs, key)
+        .map_err(invalid_config)?;
+    config.alpn_protocols = vec![b"h2".to_vec()];
+    Ok(Arc::new(config))
+}
+
+// Trusts the CA certificates in ca_pem only
+pub fn client_config(ca_pem: &[u8]) -> io::Result<Arc<ClientConfig>> {
+    build_client_config(ca_pem, None)
+}
+
+// Like client_config but presents the certificate in cert_pem to servers
+// asking for one
+pub fn client_config_with_identity(
+    ca_pem: &[u8],
+    cert_pem: &[u8],
+    key_pem: &[u8],
+) -> io::Result<Arc<ClientConfig>> {
+    build_client_config(
+        ca_pem,
+        Some((certificates(cert_pem)?, private_key(key_pem)?)),
+    )
+}
+
+fn build_client_config(
+    ca_pem: &[u8],
+    identity: Option<(Vec<rustls::Certificate>, rustls::PrivateKey)>,
+) -> io::Result<Arc<ClientConfig>> {
+    let builder = ClientConfig::builder()
+        .with_safe_default_cipher_suites()
+        .with_safe_default_kx_groups()
+        .with_protocol_versions(&[&rustls::version::TLS13])
+        .map_err(invalid_config)?
+        .with_root_certificates(root_store(ca_pem)?);
+    let mut config = match identity {
+        Some((certs, key)) => builder
+            .with_client_auth_cert(certs, key)
+            .map_err(invalid_config)?,
+        None => builder.with_no_client_auth(),
+    };
+    config.alpn_protocols = vec![b"h2".to_vec()];
+    Ok(Arc::new(config))
+}
+
+fn certificates(pem: &[u8]) -> io::Result<Vec<rustls::Certificate>> {
+    Ok(rustls_pemfile::certs(&mut &pem[..])?
+        .into_iter()
+        .map(rustls::Certificate)
+        .collect())
+}
+
+fn private_key(pem: &[u8]) -> io::Result<rustls::PrivateKey> {
+    rustls_pemfile::pkcs8_private_keys(&mut &pem[..])?
+        .into_iter()
+        .next()
+        .map(rustls::PrivateKey)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "no PKCS#8 private key"))
+}
+
+fn root_store(ca_pem: &[u8]) -> io::Result<rustls::RootCertStore> {
+    let mut roots = rustls::RootCertStore::empty();
+    for cert in rustls_pemfile::certs(&mut &ca_pem[..])? {
+        roots
+            .add(&rustls::Certificate(cert))
+            .map_err(invalid_config)?;
+    }
+    Ok(roots)
+}
+
+fn invalid_config(err: rustls::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, err)
+}
+
+// Server side TLS stream that exposes its channel binding to handlers as a
+// request extension
+pub struct BoundStream {
+    inner: server::TlsStream<TcpStream>,
+    binding: ChannelBinding,
+    addr: SocketAddr,
+}
+
+// What tonic handlers learn about a connection from the TLS listener, as a
+// request extension
+#[derive(Clone, Copy, Debug)]
+pub struct TlsConnectInfo {
+    pub remote_addr: SocketAddr,
+    pub binding: ChannelBinding,
+}
+
+impl Connected for BoundStream {
+    type ConnectInfo = TlsConnectInfo;
+
+    fn connect_info(&self) -> TlsConnectInfo {
+        TlsConnectInfo {
+            remote_addr: self.addr,
+            binding: self.binding,
+        }
+    }
+}
+
+// Binding of the TLS connection request arrived on, if it came over TLS
+pub fn channel_binding<T>(request: &Request<T>) -> Option<&ChannelBinding> {
+    request
+        .extensions()
+        .get::<TlsConnectInfo>()
+        .map(|info| &info.binding)
+}
+
+// The client address for axum's ConnectInfo<SocketAddr>, as on plain HTTP
+impl HttpConnected<&BoundStream> for SocketAddr {
+    fn connect_info(stream: &BoundStream) -> SocketAddr {
+        stream.addr
+    }
+}
+
+impl AsyncRead for BoundStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for BoundStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+// Accepts TLS connections on listener for Server::serve_with_incoming.
+// Handshakes run on their own tasks so a slow client can not hold up the
+// accept loop, failed ones are dropped rather than ending the server. At most
+// max_handshakes run at once, further connections wait in the listen queue
+// until one finishes or times out.
+pub fn incoming(
+    listener: TcpListener,
+    config: Arc<ServerConfig>,
+    max_handshakes: usize,
+) -> ReceiverStream<io::Result<BoundStream>> {
+    let (tx, rx) = mpsc::channel(ACCEPT_BACKLOG);
+    let acceptor = TlsAcceptor::from(config);
+    let handshakes = Arc::new(Semaphore::new(max_handshakes));
+    tokio::spawn(async move {
+        loop {
+            let Ok(permit) = handshakes.clone().acquire_owned().await else {
+                return;
+            };
+            let (tcp, addr) = match listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(err) => {
+                    warn!("failed to accept connection: {}", err);
+                    continue;
+                }
+            };
+            let acceptor = acceptor.clone();
+            let tx = tx.clone();
+            tokio::spawn(async move {
+                let handshake = tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(tcp));
+                let handshake = handshake.await;
+                drop(permit);
+                let inner = match handshake {
+                    Ok(Ok(inner)) => inner,
+                    Ok(Err(err)) => return debug!("TLS handshake with {} failed: {}", addr, err),
+                    Err(_) => return debug!("TLS handshake with {} timed out", addr),
+                };
+                let binding = match ChannelBinding::export(inner.get_ref().1) {
+                    Ok(binding) => binding,
+                    Err(err) => return debug!("no channel binding for {}: {}", addr, err),
+                };
+                let stream = BoundStream {
+                    inner,
+                    binding,
+                    addr,
+                };
+                let _ = tx.send(Ok(stream)).await;
+            });
+        }
+    });
+    ReceiverStream::new(rx)
+}
+
+// Serves router over TLS on listener, handlers get the client address as
+// ConnectInfo<SocketAddr> like with axum::Server::bind
+pub async fn serve_http(
+    listener: TcpListener,
+    config: Arc<ServerConfig>,
+    router: Router,
+    max_handshakes: usize,
+) -> hyper::Result<()> {
+    hyper::Server::builder(accept::from_stream(incoming(
+        listener,
+        config,
+        max_handshakes,
+    )))
+    .serve(router.into_make_service_with_connect_info::<SocketAddr>())
+    .await
+}
+
+// Connects to endpoint over TLS and returns the channel along with the
+// binding of its connection. Should the channel reconnect, challenges bound
+// to the old connection no longer verify and the caller has to log in again.
+pub async fn connect(
+    endpoint: Endpoint,
+    config: Arc<ClientConfig>,
+) -> Result<(Channel, ChannelBinding), Box<dyn std::error::Error + Send + Sync>> {
+    let connector = TlsConnector::from(config);
+    let binding = Arc::new(Mutex::new(None));
+
+    let slot = binding.clone();
+    let channel = endpoint
+        .connect_with_connector(tower::service_fn(move |uri: Uri| {
+            let connector = connector.clone();
+            let slot = slot.clone();
+            async move {
+                let host = uri
+                    .host()
+                    .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "uri has no host"))?
+                    .to_string();
+                let port = uri.port_u16().unwrap_or(443);
+                let server_name = ServerName::try_from(host.as_str())
+                    .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+
+                let tcp = TcpStream::connect((host.as_str(), port)).await?;
+                let stream = connector.connect(server_name, tcp).await?;
+                *slot.lock().unwrap() = Some(ChannelBinding::export(stream.get_ref().1)?);
+                Ok::<_, io::Error>(stream)
+            }
+        }))
+        .await?;
+
+    let binding = binding
+        .lock()
+        .unwrap()
+        .take()
+        .ok_or("connected without a TLS handshake")?;
+    Ok((channel, binding))
+}
@@ -0,0 +1,147 @@
+use num_bigint::{BigInt, Sign};
+use sha2::{Digest, Sha256};
+
+use crate::public_params::PublicParams;
+
+// tls-exporter value of a TLS 1.3 connection. Both ends of one connection
+// compute the same value and a relay in the middle can not make its two
+// connections agree on it. See tls.rs for how it is exported.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ChannelBinding(pub [u8; 32]);
+
+// Domain separated, length prefixed hash of protocol messages. Both sides
+// build the same transcript so that signatures and derived keys are bound to
+// every value exchanged.
+pub struct Transcript {
+    hasher: Sha256,
+}
+
+impl Transcript {
+    pub fn new(domain: &str) -> Self {
+        let mut transcript = Self {
+            hasher: Sha256::new(),
+        };
+        transcript.append("domain", domain.as_bytes());
+        transcript
+    }
+
+    pub fn append(&mut self, label: &str, data: &[u8]) -> &mut Self {
+        for part in [label.as_bytes(), data] {
+            self.hasher.update((part.len() as u64).to_be_bytes());
+            self.hasher.update(part);
+        }
+        self
+    }
+
+    pub fn append_int(&mut self, label: &str, v: &BigInt) -> &mut Self {
+        let (_, bytes) = v.to_bytes_be();
+        self.append(label, &bytes)
+    }
+
+    pub fn finalize(self) -> [u8; 32] {
+        self.hasher.finalize().into()
+    }
+}
+
+// Values of a challenge the verifier signs in AuthChallengeResponse. Optional
+// values are only part of the transcript when the prover asked for them.
+pub struct ChallengeTranscript<'a> {
+    pub user: &'a str,
+    pub auth_id: &'a str,
+    pub r1: &'a BigInt,
+    pub r2: &'a BigInt,
+    pub c: &'a BigInt,
+    // verifier's ephemeral key for the key exchange
+    pub ephemeral: Option<&'a BigInt>,
+    // prover's key the session is bound to
+    pub session_public_key: Option<&'a BigInt>,
+    pub context: ChallengeContext<'a>,
+}
+
+impl ChallengeTranscript<'_> {
+    pub fn hash(&self) -> [u8; 32] {
+        let mut transcript = Transcript::new("blind-auth/challenge/v1");
+        transcript
+            .append("user", self.user.as_bytes())
+            .append("auth_id", self.auth_id.as_bytes())
+            .append_int("r1", self.r1)
+            .append_int("r2", self.r2)
+            .append_int("c", self.c);
+        if let Some(ephemeral) = self.ephemeral {
+            transcript.append_int("ephemeral", ephemeral);
+        }
+        if let Some(session_public_key) = self.session_public_key {
+            transcript.append_int("session_public_key", session_public_key);
+        }
+        self.context.append_to(&mut transcript);
+        transcript.finalize()
+    }
+}
+
+// What a challenge is good for: the audience and scopes the session will be
+// granted, when it was issued and until when it can be answered, and the TLS
+// connection it was requested on.
+pub struct ChallengeContext<'a> {
+    pub audience: &'a str,
+    pub scopes: &'a [String],
+    // unix seconds
+    pub issued_at: u64,
+    pub expires_at: u64,
+    pub channel_binding: Option<&'a ChannelBinding>,
+}
+
+impl ChallengeContext<'_> {
+    // Challenge the prover answers: c on a plain login, H(c, context) mod q
+    // once the prover asked for an audience, scopes or channel binding. A
+    // proof made for one context then does not verify for any other, e.g.
+    // a relay asking for an admin audience or with its own TLS connection.
+    pub fn challenge(&self, c: &BigInt) -> BigInt {
+        if self.audience.is_empty() && self.scopes.is_empty() && self.channel_binding.is_none() {
+            return c.clone();
+        }
+        let mut transcript = Transcript::new("blind-auth/challenge-context/v1");
+        transcript.append_int("c", c);
+        self.append_to(&mut transcript);
+        BigInt::from_bytes_be(Sign::Plus, &transcript.finalize()) % PublicParams::q()
+    }
+
+    fn append_to(&self, transcript: &mut Transcript) {
+        transcript
+            .append("audience", self.audience.as_bytes())
+            .append("scopes", &(self.scopes.len() as u64).to_be_bytes());
+        for scope in self.scopes {
+            transcript.append("scope", scope.as_bytes());
+        }
+        transcript
+            .append("issued_at", &self.issued_at.to_be_bytes())
+            .append("expires_at", &self.expires_at.to_be_bytes());
+        if let Some(channel_binding) = self.channel_binding {
+            transcript.append("channel_binding", &channel_binding.0);
+        }
+    }
+}
+
+// Hash commitment to a challenge sent by CommitChallenge. The random opening
+// hides c until the verifier reveals it along with the opening.
+pub struct CommittedChallengeTranscript<'a> {
+    pub user: &'a str,
+    pub commit_id: &'a str,
+    pub c: &'a BigInt,
+    pub opening: &'a str,
+    pub issued_at: u64,
+    pub expires_at: u64,
+}
+
+impl CommittedChallengeTranscript<'_> {
+    pub fn hash(&self) -> [u8; 32] {
+        let mut transcript = Transcript::new("blind-auth/challenge-commitment/v1");
+        transcript
+            .append("user", self.user.as_bytes())
+            .append("commit_id", self.commit_id.as_bytes())
+            .append_int("c", self.c)
+            .append("opening", self.opening.as_bytes())
+            .append("issued_at", &self.issued_at.to_be_bytes())
+            .append("expires_at", &self.expires_at.to_be_bytes());
+        transcript.finalize()
+    }
+}
@@ -0,0 +1,315 @@
+use num_bigint::BigInt;
+use num_traits::{Num, One, Zero};
+use std::collections::BTreeMap;
+use std::fmt;
+#[cfg(feature = "net")]
+use tonic::Status;
+
+use crate::blind_auth_api::{
+    AuthAnswerRequest, AuthChallengeRequest, RegisterRequest, RotateKeyRequest,
+};
+use crate::public_params::PublicParams;
+
+pub const MAX_USER_ID_LEN: usize = 64;
+pub const MAX_AUTH_ID_LEN: usize = 64;
+pub const MAX_AUDIENCE_LEN: usize = 128;
+pub const MAX_SCOPE_LEN: usize = 64;
+pub const MAX_SCOPES: usize = 32;
+pub const MAX_ROLE_LEN: usize = 64;
+pub const MAX_ROLES: usize = 32;
+pub const MAX_ATTRIBUTE_KEY_LEN: usize = 64;
+pub const MAX_ATTRIBUTE_VALUE_LEN: usize = 256;
+pub const MAX_ATTRIBUTES: usize = 32;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ValidationError {
+    Missing(&'static str),
+    TooLong(&'static str, usize),
+    TooMany(&'static str, usize),
+    Duplicate(&'static str),
+    InvalidCharacters(&'static str),
+    NotBase16(&'static str),
+    NotInGroup(&'static str),
+    OutOfRange(&'static str),
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValidationError::Missing(field) => write!(f, "{} field is not set", field),
+            ValidationError::TooLong(field, max) => {
+                write!(f, "{} field is longer than {} characters", field, max)
+            }
+            ValidationError::TooMany(field, max) => {
+                write!(f, "{} field has more than {} entries", field, max)
+            }
+            ValidationError::Duplicate(field) => write!(f, "{} field has duplicate entries", field),
+            ValidationError::InvalidCharacters(field) => {
+                write!(f, "{} field contains invalid characters", field)
+            }
+            ValidationError::NotBase16(field) => {
+                write!(f, "{} field is not base16 number string", field)
+            }
+            ValidationError::NotInGroup(field) => {
+                write!(f, "{} field is not an element of the group", field)
+            }
+            ValidationError::OutOfRange(field) => write!(f, "{} field is out of range", field),
+        }
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+#[cfg(feature = "net")]
+impl From<ValidationError> for Status {
+    fn from(err: ValidationError) -> Self {
+        Status::invalid_argument(err.to_string())
+    }
+}
+
+// Validated RegisterRequest
+#[derive(Debug, Clone)]
+pub struct Registration {
+    pub user: String,
+    pub y1: BigInt,
+    pub y2: BigInt,
+}
+
+// Validated RotateKeyRequest
+#[derive(Debug, Clone)]
+pub struct KeyRotation {
+    pub session_id: String,
+    pub y1: BigInt,
+    pub y2: BigInt,
+}
+
+// Validated AuthChallengeRequest
+#[derive(Debug, Clone)]
+pub struct ChallengeRequest {
+    pub user: String,
+    pub r1: BigInt,
+    pub r2: BigInt,
+    pub key_exchange: bool,
+    pub session_public_key: Option<BigInt>,
+    pub channel_binding: bool,
+    pub audience: String,
+    pub scopes: Vec<String>,
+    // CommitChallenge id in strict mode
+    pub commit_id: Option<String>,
+}
+
+// Validated AuthAnswerRequest
+#[derive(Debug, Clone)]
+pub struct AnswerRequest {
+    pub auth_id: String,
+    pub s: BigInt,
+}
+
+impl TryFrom<&RegisterRequest> for Registration {
+    type Error = ValidationError;
+
+    fn try_from(req: &RegisterRequest) -> Result<Self, Self::Error> {
+        Ok(Registration {
+            user: validate_user_id(&req.user)?,
+            y1: parse_group_element("y1", &req.y1)?,
+            y2: parse_group_element("y2", &req.y2)?,
+        })
+    }
+}
+
+impl TryFrom<&RotateKeyRequest> for KeyRotation {
+    type Error = ValidationError;
+
+    fn try_from(req: &RotateKeyRequest) -> Result<Self, Self::Error> {
+        Ok(KeyRotation {
+            session_id: validate_session_id(&req.session_id)?,
+            y1: parse_group_element("y1", &req.y1)?,
+            y2: parse_group_element("y2", &req.y2)?,
+        })
+    }
+}
+
+impl TryFrom<&AuthChallengeRequest> for ChallengeRequest {
+    type Error = ValidationError;
+
+    fn try_from(req: &AuthChallengeRequest) -> Result<Self, Self::Error> {
+        Ok(ChallengeRequest {
+            user: validate_user_id(&req.user)?,
+            r1: parse_group_element("r1", &req.r1)?,
+            r2: parse_group_element("r2", &req.r2)?,
+            key_exchange: req.key_exchange,
+            session_public_key: match req.session_public_key.as_str() {
+                "" => None,
+                key => Some(parse_group_element("session_public_key", key)?),
+            },
+            channel_binding: req.channel_binding,
+            audience: validate_audience(&req.audience)?,
+            scopes: validate_scopes(&req.scopes)?,
+            commit_id: match req.commit_id.as_str() {
+                "" => None,
+                id => Some(validate_id("commit_id", id, MAX_AUTH_ID_LEN)?),
+            },
+        })
+    }
+}
+
+impl TryFrom<&AuthAnswerRequest> for AnswerRequest {
+    type Error = ValidationError;
+
+    fn try_from(req: &AuthAnswerRequest) -> Result<Self, Self::Error> {
+        Ok(AnswerRequest {
+            auth_id: validate_auth_id(&req.auth_id)?,
+            s: parse_scalar("s", &req.s)?,
+        })
+    }
+}
+
+// User ids are 1..=64 chars of [A-Za-z0-9._@-]
+pub fn validate_user_id(user: &str) -> Result<String, ValidationError> {
+    validate_id("user", user, MAX_USER_ID_LEN)
+}
+
+// Challenge ids are 1..=64 chars of [A-Za-z0-9._@-]
+pub fn validate_auth_id(auth_id: &str) -> Result<String, ValidationError> {
+    validate_id("auth_id", auth_id, MAX_AUTH_ID_LEN)
+}
+
+// Session ids are 1..=64 chars of [A-Za-z0-9._@-]
+pub fn validate_session_id(session_id: &str) -> Result<String, ValidationError> {
+    validate_id("session_id", session_id, MAX_AUTH_ID_LEN)
+}
+
+// Audiences are empty or up to 128 chars of [A-Za-z0-9._:/-]
+pub fn validate_audience(audience: &str) -> Result<String, ValidationError> {
+    if audience.is_empty() {
+        return Ok(String::new());
+    }
+    validate_name("audience", audience, MAX_AUDIENCE_LEN)
+}
+
+// Up to 32 distinct scopes of 1..=64 chars of [A-Za-z0-9._:/-]. The order is
+// kept as sent since the prover binds the scopes in that order.
+pub fn validate_scopes(scopes: &[String]) -> Result<Vec<String>, ValidationError> {
+    validate_names("scopes", scopes, MAX_SCOPE_LEN, MAX_SCOPES)
+}
+
+// Up to 32 distinct roles of 1..=64 chars of [A-Za-z0-9._:/-]
+pub fn validate_roles(roles: &[String]) -> Result<Vec<String>, ValidationError> {
+    validate_names("roles", roles, MAX_ROLE_LEN, MAX_ROLES)
+}
+
+// Up to 32 attributes with keys of 1..=64 chars of [A-Za-z0-9._:/-] and
+// values of up to 256 printable characters
+pub fn validate_attributes<'a, I>(
+    attributes: I,
+) -> Result<BTreeMap<String, String>, ValidationError>
+where
+    I: IntoIterator<Item = (&'a String, &'a String)>,
+{
+    let mut validated = BTreeMap::new();
+    for (key, value) in attributes {
+        if validated.len() == MAX_ATTRIBUTES {
+            return Err(ValidationError::TooMany("attributes", MAX_ATTRIBUTES));
+        }
+        validate_name("attributes", key, MAX_ATTRIBUTE_KEY_LEN)?;
+        if value.len() > MAX_ATTRIBUTE_VALUE_LEN {
+            return Err(ValidationError::TooLong(
+                "attributes",
+                MAX_ATTRIBUTE_VALUE_LEN,
+            ));
+        }
+        if value.chars().any(char::is_control) {
+            return Err(ValidationError::InvalidCharacters("attributes"));
+        }
+        if validated.insert(key.clone(), value.clone()).is_some() {
+            return Err(ValidationError::Duplicate("attributes"));
+        }
+    }
+    Ok(validated)
+}
+
+fn validate_names(
+    field: &'static str,
+    values: &[String],
+    max_len: usize,
+    max: usize,
+) -> Result<Vec<String>, ValidationError> {
+    if values.len() > max {
+        return Err(ValidationError::TooMany(field, max));
+    }
+    for (i, value) in values.iter().enumerate() {
+        validate_name(field, value, max_len)?;
+        if values[..i].contains(value) {
+            return Err(ValidationError::Duplicate(field));
+        }
+    }
+    Ok(values.to_vec())
+}
+
+fn validate_id(
+    field: &'static str,
+    value: &str,
+    max_len: usize,
+) -> Result<String, ValidationError> {
+    validate_chars(field, value, max_len, |ch| {
+        matches!(ch, '.' | '_' | '@' | '-')
+    })
+}
+
+fn validate_name(
+    field: &'static str,
+    value: &str,
+    max_len: usize,
+) -> Result<String, ValidationError> {
+    validate_chars(field, value, max_len, |ch| {
+        matches!(ch, '.' | '_' | ':' | '/' | '-')
+    })
+}
+
+fn validate_chars(
+    field: &'static str,
+    value: &str,
+    max_len: usize,
+    allowed: impl Fn(char) -> bool,
+) -> Result<String, ValidationError> {
+    if value.is_empty() {
+        return Err(ValidationError::Missing(field));
+    }
+    if value.len() > max_len {
+        return Err(ValidationError::TooLong(field, max_len));
+    }
+    if !value
+        .chars()
+        .all(|ch| ch.is_ascii_alphanumeric() || allowed(ch))
+    {
+        return Err(ValidationError::InvalidCharacters(field));
+    }
+    Ok(value.to_string())
+}
+
+fn parse_hex(field: &'static str, value: &str) -> Result<BigInt, ValidationError> {
+    BigInt::from_str_radix(value, 16).map_err(|_| ValidationError::NotBase16(field))
+}
+
+// Parses an element of the order q subgroup: 1 < v < p and v^q = 1 (mod p)
+pub fn parse_group_element(field: &'static str, value: &str) -> Result<BigInt, ValidationError> {
+    let v = parse_hex(field, value)?;
+    if !is_group_element(&v) {
+        return Err(ValidationError::NotInGroup(field));
+    }
+    Ok(v)
+}
+
+// Parses a scalar in the range 0 <= v < q
+pub fn parse_scalar(field: &'static str, value: &str) -> Result<BigInt, ValidationError> {
+    let v = parse_hex(field, value)?;
+    if v < BigInt::zero() || v >= *PublicParams::q() {
+        return Err(ValidationError::OutOfRange(field));
+    }
+    Ok(v)
+}
+
+pub fn is_group_element(v: &BigInt) -> bool {
+    let p = PublicParams::p();
+    *v > BigInt::one() && v < p && v.modpow(PublicParams::q(), p).is_one()
+}
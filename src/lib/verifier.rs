@@ -1,61 +1,267 @@
 use log::{debug, info};
 use num_bigint::BigInt;
-use num_traits::Num;
+use num_traits::{One, Zero};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::net::SocketAddr;
 use std::ops::Sub;
+use std::sync::Arc;
+use std::time::Duration;
 use tonic::{Request, Response, Status};
-use uuid::Uuid;
+use uuid::Builder;
 
+use crate::account::{check_login, check_status, record_login, AccountPolicy};
 use crate::blind_auth_api::blind_auth_server::BlindAuth;
 use crate::blind_auth_api::{
-    AuthAnswerRequest, AuthAnswerResponse, AuthChallengeRequest, AuthChallengeResponse,
-    RegisterRequest, RegisterResponse,
+    AuthAnswerBatchRequest, AuthAnswerBatchResponse, AuthAnswerRequest, AuthAnswerResponse,
+    AuthAnswerResult, AuthChallengeRequest, AuthChallengeResponse, CancelChallengeRequest,
+    CancelChallengeResponse, ChallengeCommitRequest, ChallengeCommitResponse,
+    ChallengeStatusRequest, ChallengeStatusResponse, PublicParamsRequest, PublicParamsResponse,
+    Puzzle, RegisterRequest, RegisterResponse, RotateKeyRequest, RotateKeyResponse,
+    ValidateSessionRequest, ValidateSessionResponse,
 };
-use crate::generate_randomness;
+use crate::clock::unix_seconds;
+use crate::events::{self, SecurityEvent};
+use crate::group::multi_exp;
+use crate::pool::CryptoPool;
 use crate::public_params::PublicParams;
-use crate::store::models::{Challenge, Session, User};
+use crate::puzzle::{PuzzleConfig, PuzzleError, PuzzleGuard};
+use crate::random_in_range;
+use crate::rng::{OsRngProvider, RngProvider};
+use crate::schnorr::KeyPair;
+use crate::session_key::{self, SessionKey};
+use crate::session_token::{self, SessionClaims};
+use crate::store::models::{Challenge, CommittedChallenge, Session, User};
 use crate::store::store;
+use crate::tls::{self, ChannelBinding, TlsConnectInfo};
+use crate::transcript::{ChallengeContext, ChallengeTranscript, CommittedChallengeTranscript};
+use crate::validation::{
+    validate_auth_id, validate_session_id, validate_user_id, AnswerRequest, ChallengeRequest,
+    KeyRotation, Registration,
+};
+
+// Client address of requests that did not arrive over tonic, set by the JSON
+// gateway
+#[derive(Clone, Copy, Debug)]
+pub struct PeerAddr(pub SocketAddr);
+
+// Maximum number of answers in one VerifyAuthenticationBatch call
+pub const MAX_BATCH_SIZE: usize = 1024;
+
+// Size of the random weights in batch verification
+const BATCH_EXPONENT_BITS: usize = 128;
 
 pub struct AuthServer {
     pub store: store::DataStore,
+    rng: Arc<dyn RngProvider>,
+    pool: CryptoPool,
+    key: KeyPair,
+    puzzles: Option<PuzzleGuard>,
+    accounts: AccountPolicy,
+    // lifetime of session tokens, none are issued when not set
+    session_token_ttl: Option<Duration>,
+}
+
+// Session started for a verified challenge
+struct StartedSession {
+    session: Session,
+    // unix seconds
+    expires_at: u64,
+    // the user's key is due for rotation
+    key_rotation_required: bool,
+    token: Option<String>,
+}
+
+impl AuthServer {
+    pub fn new(store: store::DataStore) -> Self {
+        Self::with_rng(store, Arc::new(OsRngProvider))
+    }
+
+    pub fn with_rng(store: store::DataStore, rng: Arc<dyn RngProvider>) -> Self {
+        // ephemeral key unless with_verifier_key is used
+        let key = KeyPair::generate(rng.as_ref());
+        Self {
+            store,
+            rng,
+            pool: CryptoPool::default(),
+            key,
+            puzzles: None,
+            accounts: AccountPolicy::default(),
+            session_token_ttl: None,
+        }
+    }
+
+    // Long-term key the verifier signs challenges with
+    pub fn with_verifier_key(mut self, key: KeyPair) -> Self {
+        self.key = key;
+        self
+    }
+
+    pub fn verifier_key(&self) -> &BigInt {
+        &self.key.public
+    }
+
+    // Makes challenge requests solve a puzzle first once they exceed the rates
+    // in config
+    pub fn with_puzzles(mut self, config: PuzzleConfig) -> Self {
+        self.puzzles = Some(PuzzleGuard::new(config, self.rng.as_ref()));
+        self
+    }
+
+    // Lockouts, key rotation and expiry, and approval of new users
+    pub fn with_account_policy(mut self, policy: AccountPolicy) -> Self {
+        self.accounts = policy;
+        self
+    }
+
+    // Issues session tokens valid for ttl, at most as long as their session,
+    // with logins that are not bound to a key or channel and did no key
+    // exchange
+    pub fn with_session_tokens(mut self, ttl: Duration) -> Self {
+        self.session_token_ttl = Some(ttl);
+        self
+    }
+
+    // Runs group arithmetic on the given pool instead of the default one
+    pub fn with_crypto_pool(mut self, pool: CryptoPool) -> Self {
+        self.pool = pool;
+        self
+    }
 }
 
 #[tonic::async_trait]
 impl BlindAuth for AuthServer {
+    async fn get_public_params(
+        &self,
+        _request: Request<PublicParamsRequest>,
+    ) -> Result<Response<PublicParamsResponse>, Status> {
+        Ok(Response::new(PublicParamsResponse {
+            p: PublicParams::p().to_str_radix(16),
+            q: PublicParams::q().to_str_radix(16),
+            g: PublicParams::g().to_str_radix(16),
+            h: PublicParams::h().to_str_radix(16),
+            verifier_key: self.key.public.to_str_radix(16),
+        }))
+    }
+
     async fn register(
         &self,
         request: Request<RegisterRequest>,
     ) -> Result<Response<RegisterResponse>, Status> {
         info!("register request: {:?}", request.get_ref());
+        let request = request.into_inner();
+        let registration = self
+            .pool
+            .run(move || Registration::try_from(&request))
+            .await??;
 
-        if request.get_ref().user.is_empty() {
-            return Err(Status::invalid_argument("user field is not set"));
+        // an existing key is only replaced through RotateKey, which proves it
+        let mut user = User {
+            id: registration.user,
+            y1: registration.y1,
+            y2: registration.y2,
+            ..Default::default()
+        };
+        self.accounts
+            .new_user(&mut user, unix_seconds(self.store.now()));
+        if !self.store.insert_new_user(user) {
+            return Err(Status::already_exists("user is already registered"));
         }
+        self.store.persist_users().await;
 
-        let y1 = match BigInt::from_str_radix(request.get_ref().y1.as_str(), 16) {
-            Ok(y) => y,
-            Err(_) => {
-                return Err(Status::invalid_argument(
-                    "y1 field is not base16 number string",
-                ))
-            }
-        };
+        Ok(Response::new(RegisterResponse { success: true }))
+    }
 
-        let y2 = match BigInt::from_str_radix(request.get_ref().y2.as_str(), 16) {
-            Ok(r) => r,
-            Err(_) => {
-                return Err(Status::invalid_argument(
-                    "y2 field is not base16 number string",
-                ))
-            }
-        };
+    async fn rotate_key(
+        &self,
+        request: Request<RotateKeyRequest>,
+    ) -> Result<Response<RotateKeyResponse>, Status> {
+        // the request carries the session id, which must not end up in logs
+        info!("rotate_key request");
+        let request = request.into_inner();
+        let rotation = self
+            .pool
+            .run(move || KeyRotation::try_from(&request))
+            .await??;
 
-        self.store.insert_user(User {
-            id: request.get_ref().user.to_string(),
-            y1: y1,
-            y2: y2,
-        });
+        let session = self
+            .store
+            .get_session(&rotation.session_id)
+            .ok_or_else(|| Status::not_found("session not found"))?;
+        info!("rotate_key for user {}", session.user_id);
+        // a bearer session id is the proof of the current key, sessions bound
+        // to a key or a connection are only valid with that proof as well
+        if session.key.is_some()
+            || session.public_key.is_some()
+            || session.channel_binding.is_some()
+        {
+            return Err(Status::failed_precondition(
+                "the key can not be rotated on a bound session",
+            ));
+        }
 
-        Ok(Response::new(RegisterResponse { success: true }))
+        let now = unix_seconds(self.store.now());
+        let mut status = Ok(());
+        self.store
+            .update_user(&session.user_id, |user| {
+                // rotating must not lift a disable, a lock or a pending
+                // approval. The session was made with a valid key, so a key
+                // that expired since can still be replaced on it.
+                status = check_status(user, now);
+                if status.is_ok() {
+                    user.y1 = rotation.y1.clone();
+                    user.y2 = rotation.y2.clone();
+                    self.accounts.new_key(user, now);
+                }
+            })
+            .ok_or_else(|| Status::not_found("user not found"))?;
+        status?;
+        self.store.persist_users().await;
+        Ok(Response::new(RotateKeyResponse {}))
+    }
+
+    async fn commit_challenge(
+        &self,
+        request: Request<ChallengeCommitRequest>,
+    ) -> Result<Response<ChallengeCommitResponse>, Status> {
+        info!("commit_challenge req: {:?}", request.get_ref());
+        let user_id = validate_user_id(&request.get_ref().user)?;
+        let user = self
+            .store
+            .get_user(&user_id)
+            .ok_or_else(|| Status::failed_precondition("user is not registered"))?;
+        check_login(&user, unix_seconds(self.store.now()))?;
+
+        let mut opening = [0u8; 32];
+        self.rng.fill_bytes(&mut opening);
+        let issued_at = unix_seconds(self.store.now());
+        let committed = CommittedChallenge {
+            id: generate_id(self.rng.as_ref()),
+            user_id: user.id,
+            c: self.random_challenge(),
+            opening: hex(&opening),
+            issued_at,
+            expires_at: issued_at + store::CHALLENGE_TTL.as_secs(),
+        };
+        let commitment = CommittedChallengeTranscript {
+            user: &committed.user_id,
+            commit_id: &committed.id,
+            c: &committed.c,
+            opening: &committed.opening,
+            issued_at: committed.issued_at,
+            expires_at: committed.expires_at,
+        }
+        .hash();
+        if !self.store.insert_committed_challenge(committed.clone()) {
+            return Err(too_many_challenges());
+        }
+
+        Ok(Response::new(ChallengeCommitResponse {
+            commit_id: committed.id,
+            commitment: hex(&commitment),
+            issued_at: committed.issued_at,
+            expires_at: committed.expires_at,
+        }))
     }
 
     async fn create_authentication_challenge(
@@ -66,46 +272,132 @@ impl BlindAuth for AuthServer {
             "create_authentication_challenge req: {:?}",
             request.get_ref()
         );
-
-        if request.get_ref().user.is_empty() {
-            return Err(Status::invalid_argument("user field is not set"));
+        // checked before anything costly, such as validating r1 and r2
+        if let Some(puzzle) = self.required_puzzle(&request)? {
+            return Ok(Response::new(AuthChallengeResponse {
+                puzzle: Some(puzzle),
+                server_time: unix_seconds(self.store.now()),
+                ..Default::default()
+            }));
         }
 
-        let r1 = match BigInt::from_str_radix(request.get_ref().r1.as_str(), 16) {
-            Ok(r) => r,
-            Err(_) => {
-                return Err(Status::invalid_argument(
-                    "r1 field is not base16 number string",
+        let channel = tls::channel_binding(&request).copied();
+        let request = request.into_inner();
+        let challenge_request = self
+            .pool
+            .run(move || ChallengeRequest::try_from(&request))
+            .await??;
+
+        let channel_binding = match (challenge_request.channel_binding, channel) {
+            (false, _) => None,
+            (true, Some(channel)) => Some(channel),
+            (true, None) => {
+                return Err(Status::failed_precondition(
+                    "channel binding needs a TLS connection",
                 ))
             }
         };
 
-        let r2 = match BigInt::from_str_radix(request.get_ref().r2.as_str(), 16) {
-            Ok(r) => r,
-            Err(_) => {
-                return Err(Status::invalid_argument(
-                    "r2 field is not base16 number string",
-                ))
+        if let Some(user) = self.store.get_user(&challenge_request.user) {
+            check_login(&user, unix_seconds(self.store.now()))?;
+            if let Some(scope) = challenge_request
+                .scopes
+                .iter()
+                .find(|scope| !user.scopes.contains(scope))
+            {
+                return Err(Status::permission_denied(format!(
+                    "scope {} is not allowed for user",
+                    scope
+                )));
+            }
+            // in strict mode c and the validity period bound into it were
+            // fixed before r1 and r2 were known
+            let (c, opening, issued_at, expires_at) = match &challenge_request.commit_id {
+                Some(commit_id) => {
+                    let committed = self
+                        .store
+                        .take_committed_challenge(commit_id, &user.id)
+                        .ok_or_else(|| Status::not_found("challenge commitment not found"))?;
+                    (
+                        committed.c,
+                        committed.opening,
+                        committed.issued_at,
+                        committed.expires_at,
+                    )
+                }
+                None => {
+                    let issued_at = unix_seconds(self.store.now());
+                    (
+                        self.random_challenge(),
+                        String::new(),
+                        issued_at,
+                        issued_at + store::CHALLENGE_TTL.as_secs(),
+                    )
+                }
+            };
+
+            let commitment = commitment_hash(&challenge_request.r1, &challenge_request.r2);
+            if !self.store.insert_commitment(&user.id, commitment) {
+                events::emit(SecurityEvent::CommitmentReuse {
+                    user_id: &user.id,
+                    commitment: &hex(&commitment),
+                });
+                return Err(Status::already_exists(
+                    "commitment (r1, r2) was already used, refusing to issue a challenge",
+                ));
             }
-        };
 
-        if let Some(user) = self.store.get_user(&request.get_ref().user) {
-            let c = generate_randomness(&BigInt::from(2), &PublicParams::q().sub(2));
-            let challenge = Challenge {
+            let mut challenge = Challenge {
                 c: c.clone(),
                 user_id: user.id,
-                r1: r1,
-                r2: r2,
-                id: generate_id(),
+                r1: challenge_request.r1,
+                r2: challenge_request.r2,
+                id: generate_id(self.rng.as_ref()),
+                ephemeral: None,
+                session_public_key: challenge_request.session_public_key,
+                channel_binding,
+                audience: challenge_request.audience,
+                scopes: challenge_request.scopes,
+                issued_at,
+                expires_at,
             };
-            self.store.insert_challenge(challenge.clone());
+
+            // proves to the prover that this challenge, including the ephemeral
+            // key and the session key it binds, comes from the pinned verifier
+            let key = self.key.clone();
+            let rng = self.rng.clone();
+            let key_exchange = challenge_request.key_exchange;
+            let (challenge, signature) = self
+                .pool
+                .run(move || {
+                    if key_exchange {
+                        challenge.ephemeral = Some(KeyPair::generate(rng.as_ref()));
+                    }
+                    let transcript = signed_transcript(&challenge);
+                    let signature = key.sign(&transcript, rng.as_ref());
+                    (challenge, signature)
+                })
+                .await?;
+            if !self.store.insert_challenge(challenge.clone()) {
+                return Err(too_many_challenges());
+            }
 
             Ok(Response::new(AuthChallengeResponse {
                 auth_id: challenge.id,
                 c: c.to_str_radix(16),
+                verifier_signature: Some(signature.into()),
+                ephemeral: challenge
+                    .ephemeral
+                    .map(|ephemeral| ephemeral.public.to_str_radix(16))
+                    .unwrap_or_default(),
+                issued_at: challenge.issued_at,
+                expires_at: challenge.expires_at,
+                opening,
+                server_time: unix_seconds(self.store.now()),
+                puzzle: None,
             }))
         } else {
-            return Err(Status::failed_precondition("user is not registered"));
+            Err(Status::failed_precondition("user is not registered"))
         }
     }
 
@@ -114,74 +406,517 @@ impl BlindAuth for AuthServer {
         request: Request<AuthAnswerRequest>,
     ) -> Result<Response<AuthAnswerResponse>, Status> {
         info!("verify_authentication req: {:?}", request.get_ref());
+        let answer = AnswerRequest::try_from(request.get_ref())?;
+        let channel = tls::channel_binding(&request);
+        let (user, challenge) = self.load_challenge(&answer.auth_id, channel)?;
 
-        if request.get_ref().auth_id.is_empty() {
-            return Err(Status::invalid_argument("auth_id field is not set"));
+        let verified = {
+            let challenge = challenge.clone();
+            self.pool
+                .run(move || {
+                    verify_challenge(&user, &challenge, answer.s.clone())
+                        .then(|| derive_session_key(&challenge))
+                })
+                .await?
+        };
+        let key = match verified {
+            Some(key) => key,
+            None => {
+                self.record_failure(&challenge.user_id);
+                return Err(Status::permission_denied("auth challenge failed"));
+            }
+        };
+        let started = self.start_session(&challenge, key)?;
+        let session = started.session;
+        Ok(Response::new(AuthAnswerResponse {
+            session_id: session.id,
+            key_id: session.key.map(|key| key.id).unwrap_or_default(),
+            audience: session.audience,
+            scopes: session.scopes,
+            expires_at: started.expires_at,
+            key_rotation_required: started.key_rotation_required,
+            session_token: started.token.unwrap_or_default(),
+        }))
+    }
+
+    async fn verify_authentication_batch(
+        &self,
+        request: Request<AuthAnswerBatchRequest>,
+    ) -> Result<Response<AuthAnswerBatchResponse>, Status> {
+        info!(
+            "verify_authentication_batch req: {} answers",
+            request.get_ref().answers.len()
+        );
+        let channel = tls::channel_binding(&request).copied();
+        let answers = request.into_inner().answers;
+        if answers.len() > MAX_BATCH_SIZE {
+            return Err(Status::invalid_argument(format!(
+                "batch is larger than {} answers",
+                MAX_BATCH_SIZE
+            )));
+        }
+
+        // requests that fail validation or lookup are answered right away,
+        // the rest are verified together
+        let mut results: Vec<AuthAnswerResult> = Vec::with_capacity(answers.len());
+        let mut pending = Vec::new();
+        let mut seen = HashSet::new();
+        for answer in answers.iter() {
+            let mut result = AuthAnswerResult {
+                auth_id: answer.auth_id.clone(),
+                ..Default::default()
+            };
+            // a challenge answered twice in one batch gets one session at most
+            if !seen.insert(answer.auth_id.as_str()) {
+                result.error = "auth_id appears more than once in the batch".to_string();
+                results.push(result);
+                continue;
+            }
+            let loaded = AnswerRequest::try_from(answer)
+                .map_err(Status::from)
+                .and_then(|answer| {
+                    let (user, challenge) =
+                        self.load_challenge(&answer.auth_id, channel.as_ref())?;
+                    Ok((user, challenge, answer.s))
+                });
+            match loaded {
+                Ok(item) => pending.push((results.len(), item)),
+                Err(status) => result.error = status.message().to_string(),
+            }
+            results.push(result);
         }
 
-        let challenge = match self.store.get_challenge(&request.get_ref().auth_id) {
+        let (indices, items): (Vec<usize>, Vec<BatchItem>) = pending.into_iter().unzip();
+        let challenges: Vec<Challenge> = items
+            .iter()
+            .map(|(_, challenge, _)| challenge.clone())
+            .collect();
+        let rng = self.rng.clone();
+        let verified = self
+            .pool
+            .run(move || {
+                let verified = verify_batch(&items, rng.as_ref());
+                items
+                    .iter()
+                    .zip(verified)
+                    .map(|((_, challenge, _), success)| {
+                        success.then(|| derive_session_key(challenge))
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .await?;
+
+        for ((index, challenge), verified) in indices.into_iter().zip(challenges).zip(verified) {
+            let result = &mut results[index];
+            match verified {
+                Some(key) => match self.start_session(&challenge, key) {
+                    Ok(started) => {
+                        result.success = true;
+                        result.session_id = started.session.id;
+                        result.key_id = started.session.key.map(|key| key.id).unwrap_or_default();
+                        result.expires_at = started.expires_at;
+                        result.key_rotation_required = started.key_rotation_required;
+                        result.session_token = started.token.unwrap_or_default();
+                    }
+                    Err(status) => result.error = status.message().to_string(),
+                },
+                None => {
+                    self.record_failure(&challenge.user_id);
+                    result.error = "auth challenge failed".to_string();
+                }
+            }
+        }
+
+        Ok(Response::new(AuthAnswerBatchResponse { results }))
+    }
+
+    async fn get_challenge_status(
+        &self,
+        request: Request<ChallengeStatusRequest>,
+    ) -> Result<Response<ChallengeStatusResponse>, Status> {
+        let auth_id = validate_auth_id(&request.get_ref().auth_id)?;
+        let challenge = self
+            .store
+            .get_challenge(&auth_id)
+            .ok_or_else(|| Status::not_found("challenge not found"))?;
+        Ok(Response::new(ChallengeStatusResponse {
+            expires_at: challenge.expires_at,
+            server_time: unix_seconds(self.store.now()),
+        }))
+    }
+
+    async fn cancel_challenge(
+        &self,
+        request: Request<CancelChallengeRequest>,
+    ) -> Result<Response<CancelChallengeResponse>, Status> {
+        info!("cancel_challenge req: {:?}", request.get_ref());
+        let auth_id = validate_auth_id(&request.get_ref().auth_id)?;
+        let channel = tls::channel_binding(&request);
+        // only the connection a bound challenge belongs to can cancel it
+        self.load_challenge(&auth_id, channel)?;
+        Ok(Response::new(CancelChallengeResponse {}))
+    }
+
+    async fn validate_session(
+        &self,
+        request: Request<ValidateSessionRequest>,
+    ) -> Result<Response<ValidateSessionResponse>, Status> {
+        let session_id = validate_session_id(&request.get_ref().session_id)?;
+        let (session, expires_at) = self
+            .store
+            .get_session_with_expiry(&session_id)
+            .ok_or_else(|| Status::not_found("session not found"))?;
+        Ok(Response::new(ValidateSessionResponse {
+            user: session.user_id,
+            audience: session.audience,
+            scopes: session.scopes,
+            roles: session.roles,
+            attributes: session.attributes.into_iter().collect(),
+            expires_at: unix_seconds(expires_at),
+            session_public_key: session
+                .public_key
+                .map(|key| key.to_str_radix(16))
+                .unwrap_or_default(),
+            channel_bound: session.channel_binding.is_some(),
+            key_exchange: session.key.is_some(),
+        }))
+    }
+}
+
+impl AuthServer {
+    // Puzzle a challenge request has to solve first, when requests from its
+    // peer or from everyone are over the configured rates
+    fn required_puzzle(
+        &self,
+        request: &Request<AuthChallengeRequest>,
+    ) -> Result<Option<Puzzle>, Status> {
+        let puzzles = match &self.puzzles {
+            Some(puzzles) => puzzles,
+            None => return Ok(None),
+        };
+        let req = request.get_ref();
+        let user = validate_user_id(&req.user)?;
+        // requests handed to the server directly carry no address, they are
+        // counted by user
+        let addr = request
+            .remote_addr()
+            .or_else(|| {
+                let info = request.extensions().get::<TlsConnectInfo>();
+                info.map(|info| info.remote_addr)
+            })
+            .or_else(|| request.extensions().get::<PeerAddr>().map(|peer| peer.0));
+        let peer = match addr {
+            Some(addr) => addr.ip().to_string(),
+            None => format!("user:{}", user),
+        };
+        let now = self.store.now();
+        let difficulty = puzzles.record(&peer, now);
+        if difficulty == 0 {
+            return Ok(None);
+        }
+
+        let issue = || puzzles.issue(&user, difficulty, now, self.rng.as_ref());
+        match &req.puzzle {
+            Some(puzzle) => match puzzles.check(&user, puzzle, req.puzzle_solution, now) {
+                Ok(()) => Ok(None),
+                Err(PuzzleError::Expired) => Ok(Some(issue())),
+                Err(err) => Err(err.into()),
+            },
+            None => Ok(Some(issue())),
+        }
+    }
+
+    fn random_challenge(&self) -> BigInt {
+        random_in_range(
+            &mut self.rng.rng(),
+            &BigInt::from(2),
+            &PublicParams::q().sub(2),
+        )
+    }
+
+    // Takes a challenge and its user for an answer that arrived on channel.
+    // The challenge is used up whether the answer turns out right or wrong,
+    // only an answer on the wrong channel leaves it in place.
+    fn load_challenge(
+        &self,
+        auth_id: &String,
+        channel: Option<&ChannelBinding>,
+    ) -> Result<(User, Challenge), Status> {
+        let bound = match self.store.get_challenge(auth_id) {
+            Some(challenge) => challenge.channel_binding,
+            None => return Err(Status::not_found("challenge not found")),
+        };
+        if bound.is_some() && bound.as_ref() != channel {
+            return Err(Status::permission_denied(
+                "answer did not arrive on the TLS channel the challenge was requested on",
+            ));
+        }
+        // concurrent answers race for the challenge here, one of them wins
+        let challenge = match self.store.take_challenge(auth_id) {
             Some(challenge) => challenge,
             None => return Err(Status::not_found("challenge not found")),
         };
+        if unix_seconds(self.store.now()) > challenge.expires_at {
+            return Err(Status::not_found("challenge expired"));
+        }
 
-        let user = match self.store.get_user(&challenge.user_id) {
-            Some(user) => user,
-            None => {
-                return Err(Status::failed_precondition(
-                    "failed to fetch user for supplied challenge",
-                ))
+        match self.store.get_user(&challenge.user_id) {
+            Some(user) => {
+                check_login(&user, unix_seconds(self.store.now()))?;
+                Ok((user, challenge))
             }
-        };
+            None => Err(Status::failed_precondition(
+                "failed to fetch user for supplied challenge",
+            )),
+        }
+    }
 
-        let auth_s = match BigInt::from_str_radix(request.get_ref().s.as_str(), 16) {
-            Ok(s) => s,
-            Err(err) => return Err(Status::invalid_argument(err.to_string())),
-        };
+    // Starts a session for a verified challenge, inheriting its bindings.
+    // The user may have been disabled or deleted while its proof was being
+    // verified, so the status is checked again under the user update lock.
+    // The session is inserted under it too, an admin disabling the user
+    // right after still revokes it.
+    fn start_session(
+        &self,
+        challenge: &Challenge,
+        key: Option<SessionKey>,
+    ) -> Result<StartedSession, Status> {
+        let now = unix_seconds(self.store.now());
+        let mut started = Err(Status::failed_precondition(
+            "failed to fetch user for supplied challenge",
+        ));
+        self.store.update_user(&challenge.user_id, |user| {
+            if let Err(err) = check_status(user, now) {
+                started = Err(err.into());
+                return;
+            }
+            record_login(user, now);
+            let session = Session {
+                id: generate_id(self.rng.as_ref()),
+                user_id: challenge.user_id.clone(),
+                key,
+                public_key: challenge.session_public_key.clone(),
+                channel_binding: challenge.channel_binding,
+                audience: challenge.audience.clone(),
+                scopes: challenge.scopes.clone(),
+                roles: user.roles.clone(),
+                attributes: user.attributes.clone(),
+            };
+            self.store.insert_session(session.clone());
+            started = Ok((session, self.accounts.key_rotation_due(user, now)));
+        });
+        let (session, key_rotation_required) = started?;
+        let expires_at = now + store::SESSION_TTL.as_secs();
+        Ok(StartedSession {
+            token: self.session_token(&session, now, expires_at),
+            key_rotation_required,
+            session,
+            expires_at,
+        })
+    }
 
-        let success = verify_challenge(&user, &challenge, auth_s);
-        let session = Session {
-            id: generate_id(),
-            user_id: user.id,
+    // A token would let anyone holding it skip the proofs a bound session
+    // needs, so only unbound sessions get one
+    fn session_token(&self, session: &Session, now: u64, expires_at: u64) -> Option<String> {
+        let ttl = self.session_token_ttl?;
+        if session.key.is_some()
+            || session.public_key.is_some()
+            || session.channel_binding.is_some()
+        {
+            return None;
+        }
+        let claims = SessionClaims {
+            session_id: session.id.clone(),
+            user: session.user_id.clone(),
+            audience: session.audience.clone(),
+            scopes: session.scopes.clone(),
+            roles: session.roles.clone(),
+            attributes: session.attributes.clone(),
+            issued_at: now,
+            expires_at: expires_at.min(now + ttl.as_secs()),
         };
-        self.store.insert_session(session.clone());
-        if !success {
-            return Err(Status::permission_denied("auth challenge failed"));
+        Some(session_token::issue(&claims, &self.key, self.rng.as_ref()))
+    }
+
+    // Counts a failed proof against the user, locking it after too many
+    fn record_failure(&self, user_id: &String) {
+        let now = unix_seconds(self.store.now());
+        let mut locked = false;
+        let user = self.store.update_user(user_id, |user| {
+            locked = self.accounts.record_failure(user, now)
+        });
+        if let (true, Some(user)) = (locked, user) {
+            events::emit(SecurityEvent::AccountLocked {
+                user_id,
+                failed_logins: user.failed_logins,
+                until: user.locked_until.unwrap_or_default(),
+            });
         }
-        Ok(Response::new(AuthAnswerResponse {
-            session_id: session.id,
-        }))
     }
 }
 
+fn too_many_challenges() -> Status {
+    Status::resource_exhausted(format!(
+        "user has {} outstanding challenges, answer or cancel one first",
+        store::MAX_PENDING_CHALLENGES
+    ))
+}
+
+// Transcript the verifier signs for a challenge
+fn signed_transcript(challenge: &Challenge) -> [u8; 32] {
+    ChallengeTranscript {
+        user: &challenge.user_id,
+        auth_id: &challenge.id,
+        r1: &challenge.r1,
+        r2: &challenge.r2,
+        c: &challenge.c,
+        ephemeral: challenge
+            .ephemeral
+            .as_ref()
+            .map(|ephemeral| &ephemeral.public),
+        session_public_key: challenge.session_public_key.as_ref(),
+        context: challenge_context(challenge),
+    }
+    .hash()
+}
+
+fn challenge_context(challenge: &Challenge) -> ChallengeContext<'_> {
+    ChallengeContext {
+        audience: &challenge.audience,
+        scopes: &challenge.scopes,
+        issued_at: challenge.issued_at,
+        expires_at: challenge.expires_at,
+        channel_binding: challenge.channel_binding.as_ref(),
+    }
+}
+
+// Exponent the prover answers, the sent c or H(c, context) when the login
+// asked for an audience, scopes or channel binding
+fn challenge_exponent(challenge: &Challenge) -> BigInt {
+    challenge_context(challenge).challenge(&challenge.c)
+}
+
+// Session key from r1^b = g^kb when the prover asked for a key exchange. Only
+// called once the proof verified, so the key is bound to an authenticated r1.
+fn derive_session_key(challenge: &Challenge) -> Option<SessionKey> {
+    let ephemeral = challenge.ephemeral.as_ref()?;
+    let shared = session_key::shared_secret(&challenge.r1, ephemeral.secret());
+    Some(SessionKey::derive(&shared, &signed_transcript(challenge)))
+}
+
 // Verifiers challenge response following the predicate:
 // r1 == g^s * y1*c (mod p) && r2 == h^s * y2^c (mod p)
+// where c is the challenge exponent
 fn verify_challenge(user: &User, challenge: &Challenge, s: BigInt) -> bool {
+    let c = challenge_exponent(challenge);
     debug!(
         "y1 {}, g {}, y2 {}, h {}, c {}, s {}",
         user.y1,
         PublicParams::g(),
         user.y2,
         PublicParams::h(),
-        challenge.c,
+        c,
         s
     );
 
-    let rhs = (PublicParams::g().modpow(&s, &PublicParams::p())
-        * user.y1.modpow(&challenge.c, &PublicParams::p()))
-        % &PublicParams::p();
-
-    let lhs = (PublicParams::h().modpow(&s, &PublicParams::p())
-        * user.y2.modpow(&challenge.c, &PublicParams::p()))
-        % &PublicParams::p();
+    let mont = PublicParams::montgomery();
+    let rhs = multi_exp(mont, &[(PublicParams::g_table(), &s)], &[(&user.y1, &c)]);
+    let lhs = multi_exp(mont, &[(PublicParams::h_table(), &s)], &[(&user.y2, &c)]);
 
     debug!(
         "rhs {}, r1 {}, lhs {}, r2 {}",
         rhs, challenge.r1, lhs, challenge.r2
     );
 
-    return rhs == challenge.r1 && lhs == challenge.r2;
+    rhs == challenge.r1 && lhs == challenge.r2
+}
+
+// A proof to verify: the user, the challenge it answers and the response s
+pub type BatchItem = (User, Challenge, BigInt);
+
+// Verifies many proofs at once with the small exponents test. With random
+// 128 bit d_i, e_i all predicates hold (except with probability 2^-128) iff
+//
+//   prod(r1_i^d_i * r2_i^e_i) == g^sum(d_i*s_i) * h^sum(e_i*s_i) * prod(y1_i^(d_i*c_i) * y2_i^(e_i*c_i))
+//
+// which is evaluated with two multi-exponentiations sharing their squarings.
+// Every y and r must be in the order q subgroup, which validation guarantees.
+// If the combined check fails each proof is checked on its own to find the
+// bad ones.
+pub fn verify_batch(items: &[BatchItem], rng: &dyn RngProvider) -> Vec<bool> {
+    if items.is_empty() {
+        return Vec::new();
+    }
+
+    let q = PublicParams::q();
+    let bound = BigInt::one() << BATCH_EXPONENT_BITS;
+    let mut rng = rng.rng();
+    let mut weights = Vec::with_capacity(items.len());
+    let mut g_exponent = BigInt::zero();
+    let mut h_exponent = BigInt::zero();
+    let mut y_exponents = Vec::with_capacity(items.len() * 2);
+    for (_, challenge, s) in items {
+        let d = random_in_range(&mut rng, &BigInt::zero(), &bound);
+        let e = random_in_range(&mut rng, &BigInt::zero(), &bound);
+        let c = challenge_exponent(challenge);
+        g_exponent += &d * s;
+        h_exponent += &e * s;
+        y_exponents.push((&d * &c) % q);
+        y_exponents.push((&e * &c) % q);
+        weights.push(d);
+        weights.push(e);
+    }
+    g_exponent %= q;
+    h_exponent %= q;
+
+    let mut commitments = Vec::with_capacity(items.len() * 2);
+    let mut keys = Vec::with_capacity(items.len() * 2);
+    for (i, (user, challenge, _)) in items.iter().enumerate() {
+        commitments.push((&challenge.r1, &weights[2 * i]));
+        commitments.push((&challenge.r2, &weights[2 * i + 1]));
+        keys.push((&user.y1, &y_exponents[2 * i]));
+        keys.push((&user.y2, &y_exponents[2 * i + 1]));
+    }
+
+    let mont = PublicParams::montgomery();
+    let lhs = multi_exp(mont, &[], &commitments);
+    let rhs = multi_exp(
+        mont,
+        &[
+            (PublicParams::g_table(), &g_exponent),
+            (PublicParams::h_table(), &h_exponent),
+        ],
+        &keys,
+    );
+    if lhs == rhs {
+        return vec![true; items.len()];
+    }
+
+    debug!("batch verification failed, checking proofs one by one");
+    items
+        .iter()
+        .map(|(user, challenge, s)| verify_challenge(user, challenge, s.clone()))
+        .collect()
+}
+
+// Hashes a commitment as sha256(len(r1) || r1 || len(r2) || r2)
+fn commitment_hash(r1: &BigInt, r2: &BigInt) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    for r in [r1, r2] {
+        let (_, bytes) = r.to_bytes_be();
+        hasher.update((bytes.len() as u64).to_be_bytes());
+        hasher.update(bytes);
+    }
+    hasher.finalize().into()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
 }
 
-pub fn generate_id() -> String {
-    Uuid::new_v4().to_string()
+// Generates a random (v4) UUID string
+pub fn generate_id(rng: &dyn RngProvider) -> String {
+    let mut bytes = [0u8; 16];
+    rng.fill_bytes(&mut bytes);
+    Builder::from_random_bytes(bytes).into_uuid().to_string()
 }
@@ -0,0 +1,149 @@
+use num_bigint::BigInt;
+use num_traits::Num;
+use rand::rngs::OsRng;
+use rand::RngCore;
+use wasm_bindgen::prelude::*;
+
+use crate::blind_auth_api::{
+    AuthAnswerRequest, AuthChallengeRequest, AuthChallengeResponse, RegisterRequest,
+};
+use crate::gen_params;
+use crate::prover::{
+    compute_auth_secret, derive_nonce, derive_secret_from_password, verify_challenge_signature,
+    ChallengeCommitment, PASSWORD_ITERATIONS,
+};
+use crate::validation::{parse_group_element, parse_scalar};
+
+// Prover for browsers and Node. It reads and writes the JSON messages of the
+// gateway (see gateway.rs), the page sends them with fetch, so x never leaves
+// it. Randomness comes from crypto.getRandomValues.
+
+// Values sent in the last challenge request, needed to answer it
+struct Pending {
+    k: BigInt,
+    r1: BigInt,
+    r2: BigInt,
+    audience: String,
+    scopes: Vec<String>,
+}
+
+#[wasm_bindgen]
+pub struct Prover {
+    user: String,
+    x: BigInt,
+    // verifier key the page trusts, challenges it did not sign are not answered
+    verifier_key: BigInt,
+    pending: Option<Pending>,
+}
+
+#[wasm_bindgen]
+impl Prover {
+    // secret is x and verifier_key is V = g^v (mod p), both hex
+    #[wasm_bindgen(constructor)]
+    pub fn new(user: String, secret: &str, verifier_key: &str) -> Result<Prover, JsError> {
+        Ok(Prover {
+            user,
+            x: parse_scalar("secret", secret)?,
+            verifier_key: parse_group_element("verifier_key", verifier_key)?,
+            pending: None,
+        })
+    }
+
+    // x derived from the password with derive_secret_from_password, so the
+    // page keeps nothing between logins. Takes about a second on purpose.
+    #[wasm_bindgen(js_name = fromPassword)]
+    pub fn from_password(
+        user: String,
+        password: &str,
+        verifier_key: &str,
+    ) -> Result<Prover, JsError> {
+        if password.is_empty() {
+            return Err(JsError::new("password is empty"));
+        }
+        Ok(Prover {
+            x: derive_secret_from_password(password, &user, PASSWORD_ITERATIONS),
+            user,
+            verifier_key: parse_group_element("verifier_key", verifier_key)?,
+            pending: None,
+        })
+    }
+
+    // Body of POST /v1/register
+    #[wasm_bindgen(js_name = registerRequest)]
+    pub fn register_request(&self) -> String {
+        let (y1, y2) = gen_params(&self.x);
+        to_json(&RegisterRequest {
+            user: self.user.clone(),
+            y1,
+            y2,
+        })
+    }
+
+    // Body of POST /v1/challenges, with a fresh nonce k. Only the last
+    // challenge requested can be answered.
+    #[wasm_bindgen(js_name = challengeRequest)]
+    pub fn challenge_request(&mut self, audience: String, scopes: Vec<String>) -> String {
+        let mut entropy = [0u8; 32];
+        OsRng.fill_bytes(&mut entropy);
+        let context = self.verifier_key.to_str_radix(16);
+        let k = derive_nonce(&self.x, &self.user, &context, &entropy);
+        let (r1, r2) = gen_params(&k);
+        let request = AuthChallengeRequest {
+            user: self.user.clone(),
+            r1: r1.clone(),
+            r2: r2.clone(),
+            audience: audience.clone(),
+            scopes: scopes.clone(),
+            ..Default::default()
+        };
+        self.pending = Some(Pending {
+            k,
+            r1: BigInt::from_str_radix(&r1, 16).expect("gen_params returns hex"),
+            r2: BigInt::from_str_radix(&r2, 16).expect("gen_params returns hex"),
+            audience,
+            scopes,
+        });
+        to_json(&request)
+    }
+
+    // Body of POST /v1/answers for the challenge response of the verifier.
+    // Throws when the verifier key did not sign it.
+    #[wasm_bindgen(js_name = answerRequest)]
+    pub fn answer_request(&mut self, challenge: &str) -> Result<String, JsError> {
+        let response: AuthChallengeResponse = serde_json::from_str(challenge)?;
+        if response.auth_id.is_empty() && response.puzzle.is_some() {
+            return Err(JsError::new(
+                "the verifier asked for a puzzle, which this prover does not solve",
+            ));
+        }
+        // a nonce is used for one answer at most
+        let pending = self
+            .pending
+            .take()
+            .ok_or_else(|| JsError::new("no challenge was requested"))?;
+        let commitment = ChallengeCommitment {
+            user: &self.user,
+            r1: &pending.r1,
+            r2: &pending.r2,
+            session_public_key: None,
+            channel_binding: None,
+            audience: &pending.audience,
+            scopes: &pending.scopes,
+        };
+        if !verify_challenge_signature(&self.verifier_key, &commitment, &response) {
+            return Err(JsError::new(
+                "verifier signature on the challenge is invalid",
+            ));
+        }
+        let c = commitment.challenge(&response)?;
+        let s = compute_auth_secret(c, pending.k, self.x.clone());
+        Ok(to_json(&AuthAnswerRequest {
+            auth_id: response.auth_id,
+            s: s.to_str_radix(16),
+        }))
+    }
+}
+
+fn to_json<T: serde::Serialize>(message: &T) -> String {
+    serde_json::to_string(message).expect("messages serialize")
+}
@@ -1,21 +1,47 @@
+use std::fs;
+use std::io::Write;
 use std::ops::Sub;
+use std::path::{Path, PathBuf};
 
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
 use common_lib::public_params::PublicParams;
-use log::info;
+use log::{info, warn};
 use num_bigint::BigInt;
 use num_traits::Num;
-use tonic::transport::Channel;
+use rand::rngs::OsRng;
+use rand::RngCore;
+use tonic::transport::{Channel, Endpoint};
 
 use common_lib::blind_auth_api::blind_auth_client::BlindAuthClient;
 use common_lib::blind_auth_api::{
-    AuthAnswerRequest, AuthAnswerResponse, AuthChallengeRequest, RegisterRequest,
+    AuthAnswerRequest, AuthAnswerResponse, AuthChallengeRequest, AuthChallengeResponse,
+    ChallengeCommitRequest, PublicParamsRequest, RegisterRequest,
 };
+use common_lib::prover::{ChallengeCommitment, KnownVerifiers};
+use common_lib::rng::OsRngProvider;
+use common_lib::schnorr::KeyPair;
+use common_lib::tls;
+use common_lib::validation::parse_group_element;
+
+// Puzzles solved for one challenge before giving up
+const MAX_PUZZLES: usize = 3;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Cli {
     server: String,
+    /// How the commitment nonce k is generated
+    #[arg(long, value_enum, default_value_t = NonceMode::Deterministic)]
+    nonce_mode: NonceMode,
+    /// Expected verifier public key (hex), instead of pinning on first use
+    #[arg(long)]
+    verifier_key: Option<String>,
+    /// File of verifier keys pinned on first use, defaults to ~/.blind-auth/known_verifiers
+    #[arg(long)]
+    known_verifiers: Option<PathBuf>,
+    /// Connects over TLS, trusting the CA certificates in this PEM file
+    #[arg(long)]
+    tls_ca: Option<PathBuf>,
     #[command(subcommand)]
     command: Commands,
 }
@@ -31,46 +57,174 @@ enum Commands {
         /// Sets the user name
         #[arg(short, long)]
         client_id: String,
+        /// Derives a session key with the verifier instead of relying on the session id alone
+        #[arg(long)]
+        key_exchange: bool,
+        /// Binds the session to a new signing key and writes the session id and key to this file
+        #[arg(long)]
+        bind_session: Option<PathBuf>,
+        /// Binds the login and the session to the TLS connection, needs --tls-ca
+        #[arg(long)]
+        channel_binding: bool,
+        /// Service the session is for, bound into the challenge
+        #[arg(long, default_value = "")]
+        audience: String,
+        /// Scope to request for the session, may be repeated
+        #[arg(long = "scope")]
+        scopes: Vec<String>,
+        /// Has the verifier commit to the challenge before sending the commitments (full zero knowledge)
+        #[arg(long)]
+        strict: bool,
     },
 }
 
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum NonceMode {
+    /// k is sampled uniformly from the OS RNG
+    Random,
+    /// k is derived from x, the user, the server and fresh randomness (RFC 6979 style)
+    Deterministic,
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     env_logger::init();
     let cli = Cli::parse();
 
     info!("Connect to server");
-    let channel = Channel::from_shared(cli.server)?.connect().await?;
+    let endpoint = Endpoint::from_shared(cli.server.clone())?;
+    let (channel, binding) = match &cli.tls_ca {
+        Some(ca) => {
+            let (channel, binding) = tls::connect(endpoint, tls::client_config(&fs::read(ca)?)?)
+                .await
+                .map_err(|err| err.to_string())?;
+            (channel, Some(binding))
+        }
+        None => (endpoint.connect().await?, None),
+    };
     let mut client: BlindAuthClient<Channel> = BlindAuthClient::new(channel);
 
+    let verifier_key = authenticate_verifier(&mut client, &cli).await?;
+
     match cli.command {
         Commands::Register { client_id } => {
-            let (y1, y2) = common_lib::gen_params(&common_lib::prover::read_secret());
+            let (y1, y2) = tokio::task::spawn_blocking(|| {
+                common_lib::gen_params(&common_lib::prover::read_secret())
+            })
+            .await?;
             let req = RegisterRequest {
                 user: client_id,
-                y1: y1,
-                y2: y2,
+                y1,
+                y2,
             };
             info!("Sending RegisterRequest: {:?}", req);
             let response = client.register(req).await?;
             info!("Received RegisterResponse: {:?}", response.get_ref());
         }
-        Commands::Login { client_id } => {
-            let k = common_lib::generate_randomness(&BigInt::from(2), &PublicParams::q().sub(2));
-            info!("Generate K = {}", k);
-            let (r1, r2) = common_lib::gen_params(&k);
+        Commands::Login {
+            client_id,
+            key_exchange,
+            bind_session,
+            channel_binding,
+            audience,
+            scopes,
+            strict,
+        } => {
+            let channel_binding = match (channel_binding, &binding) {
+                (false, _) => None,
+                (true, Some(binding)) => Some(binding),
+                (true, None) => return Err("--channel-binding needs --tls-ca".into()),
+            };
+            let x = common_lib::prover::read_secret();
+            let k = match cli.nonce_mode {
+                NonceMode::Random => common_lib::random_in_range(
+                    &mut OsRng,
+                    &BigInt::from(2),
+                    &PublicParams::q().sub(2),
+                ),
+                NonceMode::Deterministic => {
+                    let mut entropy = [0u8; 32];
+                    OsRng.fill_bytes(&mut entropy);
+                    common_lib::prover::derive_nonce(&x, &client_id, &cli.server, &entropy)
+                }
+            };
+            let (r1_hex, r2_hex) = {
+                let k = k.clone();
+                tokio::task::spawn_blocking(move || common_lib::gen_params(&k)).await?
+            };
+            let r1 = BigInt::from_str_radix(&r1_hex, 16)?;
+            let r2 = BigInt::from_str_radix(&r2_hex, 16)?;
+            let session_signing_key = bind_session
+                .as_ref()
+                .map(|_| KeyPair::generate(&OsRngProvider));
+            let commitment = ChallengeCommitment {
+                user: &client_id,
+                r1: &r1,
+                r2: &r2,
+                session_public_key: session_signing_key.as_ref().map(|key| &key.public),
+                channel_binding,
+                audience: &audience,
+                scopes: &scopes,
+            };
+
+            // the verifier commits to c before it gets to see r1 and r2
+            let committed = if strict {
+                let req = ChallengeCommitRequest {
+                    user: client_id.clone(),
+                };
+                info!("Sending ChallengeCommitRequest: {:?}", req);
+                let response = client.commit_challenge(req).await?;
+                info!("Received ChallengeCommitResponse: {:?}", response.get_ref());
+                Some(response.into_inner())
+            } else {
+                None
+            };
 
-            let req = AuthChallengeRequest {
+            let mut req = AuthChallengeRequest {
                 user: client_id.clone(),
-                r1: r1,
-                r2: r2,
+                r1: r1_hex,
+                r2: r2_hex,
+                key_exchange,
+                session_public_key: commitment
+                    .session_public_key
+                    .map(|key| key.to_str_radix(16))
+                    .unwrap_or_default(),
+                channel_binding: channel_binding.is_some(),
+                audience: audience.clone(),
+                scopes: scopes.clone(),
+                commit_id: committed
+                    .as_ref()
+                    .map(|committed| committed.commit_id.clone())
+                    .unwrap_or_default(),
+                ..Default::default()
             };
-            info!("Sending AuthChallengeRequest: {:?}", req);
-            let response = client.create_authentication_challenge(req).await?;
-            info!("Received AuthChallengeResponse: {:?}", response.get_ref());
+            let response = request_challenge(&mut client, &mut req).await?;
 
-            let x = common_lib::prover::read_secret();
-            let c = BigInt::from_str_radix(response.get_ref().c.as_str(), 16)?;
+            // never answer a challenge that the pinned verifier did not sign
+            if !common_lib::prover::verify_challenge_signature(
+                &verifier_key,
+                &commitment,
+                response.get_ref(),
+            ) {
+                return Err("verifier signature on the challenge is invalid".into());
+            }
+            if let Some(committed) = &committed {
+                if !common_lib::prover::verify_challenge_opening(
+                    &client_id,
+                    committed,
+                    response.get_ref(),
+                ) {
+                    return Err("challenge does not open the verifier's commitment".into());
+                }
+            }
+
+            let session_key =
+                common_lib::prover::derive_session_key(&k, &commitment, response.get_ref())?;
+            if key_exchange && session_key.is_none() {
+                return Err("verifier did not send an ephemeral key".into());
+            }
+
+            let c = commitment.challenge(response.get_ref())?;
             let s = common_lib::prover::compute_auth_secret(c, k, x);
 
             let req = AuthAnswerRequest {
@@ -82,7 +236,112 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             let response: tonic::Response<AuthAnswerResponse> =
                 client.verify_authentication(req).await?;
             info!("Received AuthAnswerResponse: {:?}", response.get_ref());
+            if response.get_ref().key_rotation_required {
+                warn!("The verifier asks for a new key, rotate it with RotateKey on this session");
+            }
+
+            // both sides derive the key id, a match confirms they hold the same key
+            if let Some(session_key) = session_key {
+                if session_key.id != response.get_ref().key_id {
+                    return Err("verifier derived a different session key".into());
+                }
+                info!("Established session key {}", session_key.id);
+            }
+            if let (Some(path), Some(key)) = (bind_session, session_signing_key) {
+                write_session_key(&path, &response.get_ref().session_id, &key)?;
+                info!("Session bound to key, written to {}", path.display());
+            }
         }
     }
     Ok(())
 }
+
+// Sends req until the verifier issues a challenge, solving the puzzles it
+// hands out under load
+async fn request_challenge(
+    client: &mut BlindAuthClient<Channel>,
+    req: &mut AuthChallengeRequest,
+) -> Result<tonic::Response<AuthChallengeResponse>, Box<dyn std::error::Error>> {
+    for _ in 0..=MAX_PUZZLES {
+        info!("Sending AuthChallengeRequest: {:?}", req);
+        let response = client.create_authentication_challenge(req.clone()).await?;
+        info!("Received AuthChallengeResponse: {:?}", response.get_ref());
+
+        let puzzle = match response.get_ref().puzzle.clone() {
+            Some(puzzle) => puzzle,
+            None => return Ok(response),
+        };
+        info!("Solving puzzle of difficulty {}", puzzle.difficulty);
+        let user = req.user.clone();
+        req.puzzle_solution = {
+            let puzzle = puzzle.clone();
+            tokio::task::spawn_blocking(move || common_lib::puzzle::solve(&user, &puzzle)).await?
+        };
+        req.puzzle = Some(puzzle);
+    }
+    Err("verifier keeps asking for puzzles".into())
+}
+
+// Writes "session_id secret" readable by the owner only, requests on the
+// session are signed with the secret
+fn write_session_key(
+    path: &Path,
+    session_id: &str,
+    key: &KeyPair,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(path)?;
+    writeln!(file, "{} {}", session_id, key.secret().to_str_radix(16))?;
+    Ok(())
+}
+
+// Fetches the verifier's public parameters and checks its key against the
+// configured or pinned one
+async fn authenticate_verifier(
+    client: &mut BlindAuthClient<Channel>,
+    cli: &Cli,
+) -> Result<BigInt, Box<dyn std::error::Error>> {
+    let params = client
+        .get_public_params(PublicParamsRequest {})
+        .await?
+        .into_inner();
+    info!("Received PublicParamsResponse: {:?}", params);
+
+    for (name, value, expected) in [
+        ("p", &params.p, PublicParams::p()),
+        ("q", &params.q, PublicParams::q()),
+        ("g", &params.g, PublicParams::g()),
+        ("h", &params.h, PublicParams::h()),
+    ] {
+        if BigInt::from_str_radix(value, 16).ok().as_ref() != Some(expected) {
+            return Err(format!("verifier uses unexpected public parameter {}", name).into());
+        }
+    }
+
+    // a key outside the group is refused before it is pinned or used
+    let key = parse_group_element("verifier_key", &params.verifier_key)?;
+    match &cli.verifier_key {
+        Some(expected) => {
+            if parse_group_element("verifier_key", expected)? != key {
+                return Err("verifier key does not match --verifier-key".into());
+            }
+        }
+        None => {
+            let known = KnownVerifiers::new(
+                cli.known_verifiers
+                    .clone()
+                    .unwrap_or_else(KnownVerifiers::default_path),
+            );
+            if known.check_or_pin(&cli.server, &key)? {
+                warn!(
+                    "Pinned verifier key {} for {}",
+                    params.verifier_key, cli.server
+                );
+            }
+        }
+    }
+    Ok(key)
+}
@@ -1,23 +1,353 @@
+use axum::Router;
+use clap::Parser;
+use common_lib::account::AccountPolicy;
+use common_lib::admin::{AdminAuth, AdminServer};
+use common_lib::blind_auth_api::blind_auth_admin_server::BlindAuthAdminServer;
 use common_lib::blind_auth_api::blind_auth_server::BlindAuthServer;
+use common_lib::envoy_auth_api::authorization_server::AuthorizationServer;
+use common_lib::ext_authz::ExtAuthzServer;
+use common_lib::forward_auth;
+use common_lib::gateway;
+use common_lib::middleware::SessionValidator;
+use common_lib::pool::{default_parallelism, CryptoPool, DEFAULT_QUEUE_DEPTH};
+use common_lib::public_params::PublicParams;
+use common_lib::puzzle::PuzzleConfig;
+use common_lib::rng::OsRngProvider;
+use common_lib::schnorr::KeyPair;
 use common_lib::store::store::DataStore;
+use common_lib::tls;
+use num_bigint::BigInt;
+use num_traits::Num;
+use std::fs;
+use std::io::Write;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio_rustls::rustls::ServerConfig;
 
 use common_lib::verifier::AuthServer;
-use log::info;
+use log::{error, info, warn};
 use tonic::transport::Server;
 
+#[derive(Parser, Debug, Clone)]
+#[command(author, version, about, long_about = None)]
+struct Cli {
+    /// Number of proof verifications run in parallel, defaults to the number of cores
+    #[arg(long)]
+    crypto_workers: Option<usize>,
+    /// Number of verifications allowed to wait for a worker before RESOURCE_EXHAUSTED
+    #[arg(long, default_value_t = DEFAULT_QUEUE_DEPTH)]
+    crypto_queue: usize,
+    /// File holding the verifier's long-term signing key, created if missing,
+    /// defaults to ~/.blind-auth/verifier.key
+    #[arg(long, conflicts_with = "ephemeral_key")]
+    key_file: Option<PathBuf>,
+    /// Sign with a new key on every start, provers that pinned the previous
+    /// one refuse its challenges. Only for tests and throwaway setups
+    #[arg(long)]
+    ephemeral_key: bool,
+    /// PEM certificate chain, serves TLS (1.3 only) together with --tls-key
+    #[arg(long, requires = "tls_key")]
+    tls_cert: Option<PathBuf>,
+    /// PEM PKCS#8 private key of the TLS certificate
+    #[arg(long, requires = "tls_cert")]
+    tls_key: Option<PathBuf>,
+    /// TLS handshakes in progress per listener before new connections wait to be accepted
+    #[arg(long, default_value_t = tls::DEFAULT_MAX_HANDSHAKES)]
+    max_pending_handshakes: usize,
+    /// Challenge requests per 10s a peer can make before it has to solve puzzles
+    #[arg(long, default_value_t = PuzzleConfig::default().peer_rate)]
+    puzzle_peer_rate: u64,
+    /// Challenge requests per 10s from all peers before everyone has to solve puzzles
+    #[arg(long, default_value_t = PuzzleConfig::default().global_rate)]
+    puzzle_global_rate: u64,
+    /// Leading zero bits a puzzle asks for just above the rate
+    #[arg(long, default_value_t = PuzzleConfig::default().base_difficulty)]
+    puzzle_difficulty: u32,
+    /// Failed logins in a row before a user is locked, 0 never locks
+    #[arg(long, default_value_t = AccountPolicy::default().max_failed_logins)]
+    max_failed_logins: u32,
+    /// How long a lock lasts
+    #[arg(long, default_value_t = AccountPolicy::default().lockout.as_secs())]
+    lockout_secs: u64,
+    /// Age in days after which logins ask the prover to register a new key
+    #[arg(long)]
+    key_rotation_days: Option<u64>,
+    /// Days a newly registered key can be used for
+    #[arg(long)]
+    key_lifetime_days: Option<u64>,
+    /// New users can not log in until an admin enables them
+    #[arg(long)]
+    require_approval: bool,
+    /// Seconds the signed session tokens issued with unbound logins are valid
+    /// for, no tokens are issued otherwise
+    #[arg(long)]
+    session_token_secs: Option<u64>,
+    /// Also serve Envoy's envoy.service.auth.v3.Authorization/Check on the
+    /// verifier address, for the ext_authz filter
+    #[arg(long)]
+    ext_authz: bool,
+    /// Address to serve HTTP on, GET /auth for nginx auth_request and Traefik
+    /// forwardAuth. It is not served otherwise.
+    #[arg(long)]
+    http_addr: Option<SocketAddr>,
+    /// Also serve the BlindAuth RPCs as JSON under /v1 on --http-addr, see
+    /// proto/blind_auth.openapi.yaml. Needs --tls-cert and --tls-key, or
+    /// --http-insecure.
+    #[arg(long, requires = "http_addr")]
+    http_gateway: bool,
+    /// Serves --http-gateway over plain HTTP, for a TLS terminating proxy in
+    /// front of the verifier
+    #[arg(long, requires = "http_gateway")]
+    http_insecure: bool,
+    /// JSON file users are loaded from and saved to, users are kept in memory only otherwise
+    #[arg(long)]
+    store_file: Option<PathBuf>,
+    /// Address to serve the admin service on, it is not served otherwise
+    #[arg(long)]
+    admin_addr: Option<SocketAddr>,
+    /// File holding the bearer token admin requests have to carry, at least 32 characters
+    #[arg(long, requires = "admin_addr")]
+    admin_token_file: Option<PathBuf>,
+    /// PEM CA certificates admin clients have to present a certificate from,
+    /// serves the admin service over TLS with the --tls-cert key pair
+    #[arg(long, requires_all = ["admin_addr", "tls_cert"])]
+    admin_client_ca: Option<PathBuf>,
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     env_logger::init();
+    let cli = Cli::parse();
+
+    // build the fixed base tables up front rather than on the first login
+    PublicParams::g_table();
+    PublicParams::h_table();
+
+    let addr: SocketAddr = "0.0.0.0:50051".parse()?;
+    let store = match &cli.store_file {
+        Some(path) => DataStore::new().with_user_file(path.clone())?,
+        None => DataStore::new(),
+    };
+    let pool = CryptoPool::new(
+        cli.crypto_workers.unwrap_or_else(default_parallelism),
+        cli.crypto_queue,
+    );
+    let mut blind_auth = AuthServer::new(store.clone())
+        .with_crypto_pool(pool.clone())
+        .with_puzzles(PuzzleConfig {
+            peer_rate: cli.puzzle_peer_rate,
+            global_rate: cli.puzzle_global_rate,
+            base_difficulty: cli.puzzle_difficulty,
+            max_difficulty: cli.puzzle_difficulty + 8,
+            ..Default::default()
+        });
+    let days = |days: u64| Duration::from_secs(days * 24 * 60 * 60);
+    blind_auth = blind_auth.with_account_policy(AccountPolicy {
+        max_failed_logins: cli.max_failed_logins,
+        lockout: Duration::from_secs(cli.lockout_secs),
+        key_rotation_age: cli.key_rotation_days.map(days),
+        key_lifetime: cli.key_lifetime_days.map(days),
+        require_approval: cli.require_approval,
+    });
+    if let Some(secs) = cli.session_token_secs {
+        blind_auth = blind_auth.with_session_tokens(Duration::from_secs(secs));
+    }
+    if cli.ephemeral_key {
+        warn!("--ephemeral-key given, provers will see a new verifier key on every restart");
+    } else {
+        let path = cli.key_file.clone().unwrap_or_else(default_key_path);
+        blind_auth = blind_auth.with_verifier_key(load_or_create_key(&path)?);
+    }
+    info!(
+        "Verifier key {}",
+        blind_auth.verifier_key().to_str_radix(16)
+    );
+
+    if let Some(admin_addr) = cli.admin_addr {
+        tokio::spawn(serve_admin(cli.clone(), admin_addr, store.clone()));
+    }
+
+    // proxy integrations check sessions in this verifier's store
+    let validator = SessionValidator::new()
+        .with_store(store.clone())
+        .with_crypto_pool(pool)
+        .with_verifier_key(blind_auth.verifier_key().clone())?;
+    // the gRPC service and the JSON gateway share one AuthServer, so
+    // challenges, puzzle rates and sessions are the same on both
+    let blind_auth = Arc::new(blind_auth);
+    if let Some(http_addr) = cli.http_addr {
+        // HTTP is served over TLS as well when the verifier has a certificate
+        let tls = match (&cli.tls_cert, &cli.tls_key) {
+            (Some(cert), Some(key)) => {
+                Some(tls::http_server_config(&fs::read(cert)?, &fs::read(key)?)?)
+            }
+            _ => None,
+        };
+        // logins on the gateway carry proofs and session ids
+        if cli.http_gateway && tls.is_none() && !cli.http_insecure {
+            return Err("--http-gateway needs --tls-cert and --tls-key, or --http-insecure".into());
+        }
+        let mut router = forward_auth::router(validator.clone());
+        if cli.http_gateway {
+            router = router.merge(gateway::router(blind_auth.clone()));
+        }
+        tokio::spawn(serve_http(
+            http_addr,
+            router,
+            tls,
+            cli.max_pending_handshakes,
+        ));
+    }
+    let ext_authz = cli
+        .ext_authz
+        .then(|| AuthorizationServer::new(ExtAuthzServer::new(validator)));
+    let server = Server::builder()
+        .add_service(BlindAuthServer::from_arc(blind_auth))
+        .add_optional_service(ext_authz);
+    match (&cli.tls_cert, &cli.tls_key) {
+        (Some(cert), Some(key)) => {
+            let config = tls::server_config(&fs::read(cert)?, &fs::read(key)?)?;
+            let listener = TcpListener::bind(addr).await?;
+            info!("Server listening on {} (TLS)", addr);
+            server
+                .serve_with_incoming(tls::incoming(listener, config, cli.max_pending_handshakes))
+                .await?;
+        }
+        _ => {
+            info!("Server listening on {}", addr);
+            server.serve(addr).await?;
+        }
+    }
+
+    Ok(())
+}
+
+// Serves HTTP on its own address. Exits the process if it can not be
+// started, like serve_admin.
+async fn serve_http(
+    addr: SocketAddr,
+    router: Router,
+    tls: Option<Arc<ServerConfig>>,
+    max_handshakes: usize,
+) {
+    if let Err(err) = try_serve_http(addr, router, tls, max_handshakes).await {
+        error!("HTTP listener failed: {}", err);
+        std::process::exit(1);
+    }
+}
 
-    let addr = "0.0.0.0:50051".parse()?;
-    let store = DataStore::new();
-    let blind_auth = AuthServer { store };
+async fn try_serve_http(
+    addr: SocketAddr,
+    router: Router,
+    tls: Option<Arc<ServerConfig>>,
+    max_handshakes: usize,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // the gateway counts challenge requests per client address
+    match tls {
+        Some(config) => {
+            let listener = TcpListener::bind(addr).await?;
+            info!("HTTP listening on {} (TLS)", addr);
+            tls::serve_http(listener, config, router, max_handshakes).await?;
+        }
+        None => {
+            let server = axum::Server::try_bind(&addr)?;
+            info!("HTTP listening on {}", addr);
+            server
+                .serve(router.into_make_service_with_connect_info::<SocketAddr>())
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+// Serves the admin service on its own address, sharing the store with the
+// public one. Exits the process if it can not be started, an admin service
+// that silently failed to come up is worse than no verifier.
+async fn serve_admin(cli: Cli, addr: SocketAddr, store: DataStore) {
+    if let Err(err) = try_serve_admin(&cli, addr, store).await {
+        error!("Admin service failed: {}", err);
+        std::process::exit(1);
+    }
+}
 
-    info!("Server listening on {}", addr);
-    Server::builder()
-        .add_service(BlindAuthServer::new(blind_auth))
-        .serve(addr)
-        .await?;
+async fn try_serve_admin(
+    cli: &Cli,
+    addr: SocketAddr,
+    store: DataStore,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let auth = match (&cli.admin_token_file, &cli.admin_client_ca) {
+        (Some(path), _) => AdminAuth::token(fs::read_to_string(path)?.trim())?,
+        (None, Some(_)) => AdminAuth::client_certificate(),
+        (None, None) => {
+            return Err("--admin-addr needs --admin-token-file or --admin-client-ca".into())
+        }
+    };
+    let service = BlindAuthAdminServer::with_interceptor(AdminServer::new(store), auth);
+    let server = Server::builder().add_service(service);
 
+    match (&cli.admin_client_ca, &cli.tls_cert, &cli.tls_key) {
+        (Some(ca), Some(cert), Some(key)) => {
+            let config = tls::server_config_with_client_auth(
+                &fs::read(cert)?,
+                &fs::read(key)?,
+                &fs::read(ca)?,
+            )?;
+            let listener = TcpListener::bind(addr).await?;
+            info!("Admin service listening on {} (mutual TLS)", addr);
+            server
+                .serve_with_incoming(tls::incoming(listener, config, cli.max_pending_handshakes))
+                .await?;
+        }
+        (None, Some(cert), Some(key)) => {
+            let config = tls::server_config(&fs::read(cert)?, &fs::read(key)?)?;
+            let listener = TcpListener::bind(addr).await?;
+            info!("Admin service listening on {} (TLS)", addr);
+            server
+                .serve_with_incoming(tls::incoming(listener, config, cli.max_pending_handshakes))
+                .await?;
+        }
+        _ => {
+            warn!(
+                "Admin service listening on {} without TLS, the token is sent in the clear",
+                addr
+            );
+            server.serve(addr).await?;
+        }
+    }
     Ok(())
 }
+
+// Reads the hex encoded secret from path, or generates one and writes it
+// readable by the owner only
+// ~/.blind-auth/verifier.key, next to the prover's known_verifiers
+fn default_key_path() -> PathBuf {
+    let home = std::env::var_os("HOME").unwrap_or_else(|| ".".into());
+    PathBuf::from(home).join(".blind-auth").join("verifier.key")
+}
+
+fn load_or_create_key(path: &Path) -> Result<KeyPair, Box<dyn std::error::Error>> {
+    if path.exists() {
+        let secret = BigInt::from_str_radix(fs::read_to_string(path)?.trim(), 16)?;
+        return Ok(KeyPair::from_secret(secret));
+    }
+
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        let mut builder = fs::DirBuilder::new();
+        builder.recursive(true);
+        #[cfg(unix)]
+        std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+        builder.create(dir)?;
+    }
+    let key = KeyPair::generate(&OsRngProvider);
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(path)?;
+    writeln!(file, "{}", key.secret().to_str_radix(16))?;
+    info!("Generated verifier key in {}", path.display());
+    Ok(key)
+}
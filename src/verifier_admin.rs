@@ -0,0 +1,643 @@
+use clap::{Parser, Subcommand};
+use common_lib::blind_auth_api::blind_auth_admin_client::BlindAuthAdminClient;
+use common_lib::blind_auth_api::{
+    AdminSession, AdminUser, AdminUserRequest, ExportUsersRequest, ExportedUser,
+    ImportUsersRequest, ListUsersRequest, RevokeSessionRequest, SetUserAttributesRequest,
+    SetUserRolesRequest, SetUserScopesRequest,
+};
+use common_lib::store::file::{self, UserRecord};
+use common_lib::store::models::{User, UserStatus};
+use common_lib::tls;
+use common_lib::validation::{validate_attributes, validate_roles, validate_scopes};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use tonic::metadata::MetadataValue;
+use tonic::transport::{Channel, Endpoint};
+use tonic::Request;
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Cli {
+    /// Admin endpoint of a running verifier, e.g. http://127.0.0.1:50052
+    #[arg(
+        long,
+        conflicts_with = "store_file",
+        required_unless_present = "store_file"
+    )]
+    server: Option<String>,
+    /// File holding the admin token of the verifier
+    #[arg(long, requires = "server")]
+    token_file: Option<PathBuf>,
+    /// PEM CA certificate to trust for an https:// server
+    #[arg(long, requires = "server")]
+    tls_ca: Option<PathBuf>,
+    /// PEM client certificate for a verifier started with --admin-client-ca
+    #[arg(long, requires_all = ["tls_key", "tls_ca"])]
+    tls_cert: Option<PathBuf>,
+    /// PEM PKCS#8 private key of the client certificate
+    #[arg(long, requires = "tls_cert")]
+    tls_key: Option<PathBuf>,
+    /// User file of a stopped verifier (its --store-file) to work on directly
+    #[arg(long)]
+    store_file: Option<PathBuf>,
+    /// Print JSON instead of tables
+    #[arg(long)]
+    json: bool,
+    #[command(subcommand)]
+    command: Commands,
+}
+
+#[derive(Subcommand, Debug)]
+enum Commands {
+    /// Lists all users
+    Users,
+    /// Shows one user
+    User { id: String },
+    /// Stops a user from logging in and revokes its sessions
+    Disable { id: String },
+    /// Lets a disabled, locked or pending user log in again
+    Enable { id: String },
+    /// Deletes a user and revokes its sessions
+    Delete { id: String },
+    /// Lists the live sessions of a user
+    Sessions { id: String },
+    /// Revokes one session
+    RevokeSession { session_id: String },
+    /// Revokes all sessions of a user
+    RevokeSessions { id: String },
+    /// Lists disabled, locked and pending users
+    Lockouts,
+    /// Replaces the roles of a user, copied into its sessions at login
+    SetRoles { id: String, roles: Vec<String> },
+    /// Replaces the scopes a user may ask for at login
+    SetScopes { id: String, scopes: Vec<String> },
+    /// Replaces the attributes of a user, given as key=value
+    SetAttributes {
+        id: String,
+        #[arg(value_parser = parse_attribute)]
+        attributes: Vec<(String, String)>,
+    },
+    /// Writes all users in the user file format
+    Export {
+        /// Written to stdout when not given
+        #[arg(long)]
+        out: Option<PathBuf>,
+    },
+    /// Adds the users of an export, replacing users with the same id
+    Import { file: PathBuf },
+}
+
+#[derive(Serialize)]
+struct UserRow {
+    id: String,
+    status: UserStatus,
+    // unix seconds
+    created_at: u64,
+    last_login_at: Option<u64>,
+    key_expires_at: Option<u64>,
+    locked_until: Option<u64>,
+    roles: Vec<String>,
+    scopes: Vec<String>,
+    attributes: BTreeMap<String, String>,
+}
+
+#[derive(Serialize)]
+struct SessionRow {
+    id: String,
+    user: String,
+    audience: String,
+    scopes: Vec<String>,
+    key_bound: bool,
+    channel_bound: bool,
+    expires_at: u64,
+}
+
+#[derive(Serialize)]
+struct Count {
+    count: u32,
+}
+
+// What set-roles, set-scopes and set-attributes replace
+enum Grant {
+    Roles(Vec<String>),
+    Scopes(Vec<String>),
+    Attributes(BTreeMap<String, String>),
+}
+
+// Where the users live: a running verifier or the user file of a stopped one
+enum Backend {
+    Remote {
+        client: BlindAuthAdminClient<Channel>,
+        token: Option<String>,
+    },
+    File(PathBuf),
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    env_logger::init();
+    let cli = Cli::parse();
+    let mut backend = match (&cli.server, &cli.store_file) {
+        (Some(server), _) => connect(&cli, server).await?,
+        (None, Some(path)) => Backend::File(path.clone()),
+        (None, None) => unreachable!("clap requires --server or --store-file"),
+    };
+
+    match &cli.command {
+        Commands::Users => print_users(&cli, backend.users().await?),
+        Commands::User { id } => print_users(&cli, vec![backend.user(id).await?]),
+        Commands::Disable { id } => print_users(
+            &cli,
+            vec![backend.set_status(id, UserStatus::Disabled).await?],
+        ),
+        Commands::Enable { id } => print_users(
+            &cli,
+            vec![backend.set_status(id, UserStatus::Active).await?],
+        ),
+        Commands::Delete { id } => {
+            let revoked = backend.delete(id).await?;
+            print_count(&cli, "revoked sessions", revoked)
+        }
+        Commands::Sessions { id } => print_sessions(&cli, backend.sessions(id).await?),
+        Commands::RevokeSession { session_id } => {
+            let revoked = backend.revoke_session(session_id).await?;
+            print_count(&cli, "revoked sessions", revoked)
+        }
+        Commands::RevokeSessions { id } => {
+            let revoked = backend.revoke_sessions(id).await?;
+            print_count(&cli, "revoked sessions", revoked)
+        }
+        Commands::Lockouts => {
+            let users = backend.users().await?;
+            let locked = users
+                .into_iter()
+                .filter(|user| user.status != UserStatus::Active)
+                .collect();
+            print_users(&cli, locked)
+        }
+        Commands::SetRoles { id, roles } => print_users(
+            &cli,
+            vec![backend.grant(id, Grant::Roles(roles.clone())).await?],
+        ),
+        Commands::SetScopes { id, scopes } => print_users(
+            &cli,
+            vec![backend.grant(id, Grant::Scopes(scopes.clone())).await?],
+        ),
+        Commands::SetAttributes { id, attributes } => {
+            let attributes = attributes.iter().cloned().collect();
+            print_users(
+                &cli,
+                vec![backend.grant(id, Grant::Attributes(attributes)).await?],
+            )
+        }
+        Commands::Export { out } => {
+            let json = file::to_json(&backend.export().await?)?;
+            match out {
+                Some(path) => fs::write(path, json + "\n")?,
+                None => println!("{}", json),
+            }
+        }
+        Commands::Import { file: path } => {
+            let records = file::from_json(&fs::read_to_string(path)?)?;
+            let imported = backend.import(records).await?;
+            print_count(&cli, "imported users", imported)
+        }
+    }
+    Ok(())
+}
+
+async fn connect(cli: &Cli, server: &str) -> Result<Backend> {
+    let endpoint = Endpoint::from_shared(server.to_string())?;
+    let channel = match (&cli.tls_ca, &cli.tls_cert, &cli.tls_key) {
+        (Some(ca), Some(cert), Some(key)) => {
+            let config = tls::client_config_with_identity(
+                &fs::read(ca)?,
+                &fs::read(cert)?,
+                &fs::read(key)?,
+            )?;
+            tls::connect(endpoint, config)
+                .await
+                .map_err(|err| err.to_string())?
+                .0
+        }
+        (Some(ca), _, _) => {
+            tls::connect(endpoint, tls::client_config(&fs::read(ca)?)?)
+                .await
+                .map_err(|err| err.to_string())?
+                .0
+        }
+        _ => endpoint.connect().await?,
+    };
+    let token = match &cli.token_file {
+        Some(path) => Some(fs::read_to_string(path)?.trim().to_string()),
+        None => None,
+    };
+    Ok(Backend::Remote {
+        client: BlindAuthAdminClient::new(channel),
+        token,
+    })
+}
+
+impl Backend {
+    async fn users(&mut self) -> Result<Vec<UserRow>> {
+        let (client, token) = match self {
+            Backend::File(path) => return Ok(file::load(path)?.iter().map(user_row).collect()),
+            Backend::Remote { client, token } => (client, token),
+        };
+        let mut users = vec![];
+        let mut page_token = String::new();
+        loop {
+            let page = client
+                .list_users(authorized(
+                    token,
+                    ListUsersRequest {
+                        page_size: 0,
+                        page_token,
+                    },
+                )?)
+                .await?
+                .into_inner();
+            for user in &page.users {
+                users.push(admin_user_row(user)?);
+            }
+            if page.next_page_token.is_empty() {
+                return Ok(users);
+            }
+            page_token = page.next_page_token;
+        }
+    }
+
+    async fn user(&mut self, id: &str) -> Result<UserRow> {
+        match self {
+            Backend::File(path) => file::load(path)?
+                .iter()
+                .find(|user| user.id == id)
+                .map(user_row)
+                .ok_or_else(|| "user not found".into()),
+            Backend::Remote { client, token } => {
+                let user = client
+                    .get_user(authorized(token, user_request(id))?)
+                    .await?;
+                admin_user_row(user.get_ref())
+            }
+        }
+    }
+
+    // Disables, or enables which also approves and unlocks
+    async fn set_status(&mut self, id: &str, status: UserStatus) -> Result<UserRow> {
+        match self {
+            Backend::File(path) => {
+                let mut users = file::load(path)?;
+                let user = users
+                    .iter_mut()
+                    .find(|user| user.id == id)
+                    .ok_or("user not found")?;
+                user.status = status;
+                user.failed_logins = 0;
+                user.locked_until = None;
+                let row = user_row(user);
+                file::save(path, &users)?;
+                Ok(row)
+            }
+            Backend::Remote { client, token } => {
+                let request = authorized(token, user_request(id))?;
+                let user = match status {
+                    UserStatus::Disabled => client.disable_user(request).await?,
+                    _ => client.enable_user(request).await?,
+                };
+                admin_user_row(user.get_ref())
+            }
+        }
+    }
+
+    // Checked like the admin API does before touching the user file
+    async fn grant(&mut self, id: &str, grant: Grant) -> Result<UserRow> {
+        match self {
+            Backend::File(path) => {
+                let mut users = file::load(path)?;
+                let user = users
+                    .iter_mut()
+                    .find(|user| user.id == id)
+                    .ok_or("user not found")?;
+                match grant {
+                    Grant::Roles(roles) => user.roles = validate_roles(&roles)?,
+                    Grant::Scopes(scopes) => user.scopes = validate_scopes(&scopes)?,
+                    Grant::Attributes(attributes) => {
+                        user.attributes = validate_attributes(&attributes)?
+                    }
+                }
+                let row = user_row(user);
+                file::save(path, &users)?;
+                Ok(row)
+            }
+            Backend::Remote { client, token } => {
+                let user = id.to_string();
+                let updated = match grant {
+                    Grant::Roles(roles) => {
+                        let request = SetUserRolesRequest { user, roles };
+                        client.set_user_roles(authorized(token, request)?).await?
+                    }
+                    Grant::Scopes(scopes) => {
+                        let request = SetUserScopesRequest { user, scopes };
+                        client.set_user_scopes(authorized(token, request)?).await?
+                    }
+                    Grant::Attributes(attributes) => {
+                        let request = SetUserAttributesRequest {
+                            user,
+                            attributes: attributes.into_iter().collect(),
+                        };
+                        client
+                            .set_user_attributes(authorized(token, request)?)
+                            .await?
+                    }
+                };
+                admin_user_row(updated.get_ref())
+            }
+        }
+    }
+
+    // Returns the number of revoked sessions, none for a stopped verifier
+    async fn delete(&mut self, id: &str) -> Result<u32> {
+        match self {
+            Backend::File(path) => {
+                let mut users = file::load(path)?;
+                let count = users.len();
+                users.retain(|user| user.id != id);
+                if users.len() == count {
+                    return Err("user not found".into());
+                }
+                file::save(path, &users)?;
+                Ok(0)
+            }
+            Backend::Remote { client, token } => {
+                let deleted = client
+                    .delete_user(authorized(token, user_request(id))?)
+                    .await?;
+                Ok(deleted.get_ref().revoked_sessions)
+            }
+        }
+    }
+
+    async fn sessions(&mut self, id: &str) -> Result<Vec<SessionRow>> {
+        let (client, token) = self.remote()?;
+        let sessions = client
+            .list_sessions(authorized(token, user_request(id))?)
+            .await?
+            .into_inner();
+        Ok(sessions.sessions.into_iter().map(session_row).collect())
+    }
+
+    async fn revoke_session(&mut self, session_id: &str) -> Result<u32> {
+        let (client, token) = self.remote()?;
+        let request = RevokeSessionRequest {
+            session_id: session_id.to_string(),
+        };
+        let revoked = client.revoke_session(authorized(token, request)?).await?;
+        Ok(revoked.get_ref().revoked)
+    }
+
+    async fn revoke_sessions(&mut self, id: &str) -> Result<u32> {
+        let (client, token) = self.remote()?;
+        let revoked = client
+            .revoke_all_sessions(authorized(token, user_request(id))?)
+            .await?;
+        Ok(revoked.get_ref().revoked)
+    }
+
+    async fn export(&mut self) -> Result<Vec<UserRecord>> {
+        match self {
+            Backend::File(path) => Ok(file::load(path)?.iter().map(UserRecord::from).collect()),
+            Backend::Remote { client, token } => {
+                let exported = client
+                    .export_users(authorized(token, ExportUsersRequest {})?)
+                    .await?
+                    .into_inner();
+                let records = exported
+                    .users
+                    .into_iter()
+                    .map(UserRecord::try_from)
+                    .collect::<std::result::Result<_, _>>()?;
+                Ok(records)
+            }
+        }
+    }
+
+    async fn import(&mut self, records: Vec<UserRecord>) -> Result<u32> {
+        match self {
+            Backend::File(path) => {
+                let imported = records
+                    .iter()
+                    .map(User::try_from)
+                    .collect::<std::result::Result<Vec<User>, _>>()?;
+                let mut users = file::load(path)?;
+                users.retain(|user| !imported.iter().any(|new| new.id == user.id));
+                let count = imported.len() as u32;
+                users.extend(imported);
+                file::save(path, &users)?;
+                Ok(count)
+            }
+            Backend::Remote { client, token } => {
+                let request = ImportUsersRequest {
+                    users: records.into_iter().map(ExportedUser::from).collect(),
+                };
+                let imported = client.import_users(authorized(token, request)?).await?;
+                Ok(imported.get_ref().imported)
+            }
+        }
+    }
+
+    fn remote(&mut self) -> Result<(&mut BlindAuthAdminClient<Channel>, &Option<String>)> {
+        match self {
+            Backend::Remote { client, token } => Ok((client, token)),
+            Backend::File(_) => {
+                Err("sessions only live in a running verifier, use --server".into())
+            }
+        }
+    }
+}
+
+fn authorized<T>(token: &Option<String>, message: T) -> Result<Request<T>> {
+    let mut request = Request::new(message);
+    if let Some(token) = token {
+        let value: MetadataValue<_> = format!("Bearer {}", token).parse()?;
+        request.metadata_mut().insert("authorization", value);
+    }
+    Ok(request)
+}
+
+fn user_request(id: &str) -> AdminUserRequest {
+    AdminUserRequest {
+        user: id.to_string(),
+    }
+}
+
+fn user_row(user: &User) -> UserRow {
+    UserRow {
+        id: user.id.clone(),
+        status: user.status,
+        created_at: user.created_at,
+        last_login_at: user.last_login_at,
+        key_expires_at: user.key_expires_at,
+        locked_until: user.locked_until,
+        roles: user.roles.clone(),
+        scopes: user.scopes.clone(),
+        attributes: user.attributes.clone(),
+    }
+}
+
+fn admin_user_row(user: &AdminUser) -> Result<UserRow> {
+    let non_zero = |timestamp: u64| (timestamp != 0).then_some(timestamp);
+    Ok(UserRow {
+        id: user.id.clone(),
+        status: user.status.parse()?,
+        created_at: user.created_at,
+        last_login_at: non_zero(user.last_login_at),
+        key_expires_at: non_zero(user.key_expires_at),
+        locked_until: non_zero(user.locked_until),
+        roles: user.roles.clone(),
+        scopes: user.scopes.clone(),
+        attributes: user.attributes.clone().into_iter().collect(),
+    })
+}
+
+fn parse_attribute(attribute: &str) -> std::result::Result<(String, String), String> {
+    attribute
+        .split_once('=')
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .ok_or_else(|| format!("{:?} is not key=value", attribute))
+}
+
+fn session_row(session: AdminSession) -> SessionRow {
+    SessionRow {
+        id: session.id,
+        user: session.user,
+        audience: session.audience,
+        scopes: session.scopes,
+        key_bound: session.key_bound,
+        channel_bound: session.channel_bound,
+        expires_at: session.expires_at,
+    }
+}
+
+fn print_users(cli: &Cli, users: Vec<UserRow>) {
+    if cli.json {
+        return print_json(&users);
+    }
+    let rows = users
+        .iter()
+        .map(|user| {
+            vec![
+                user.id.clone(),
+                user.status.to_string(),
+                user.created_at.to_string(),
+                timestamp(user.last_login_at),
+                timestamp(user.key_expires_at),
+                timestamp(user.locked_until),
+                list(&user.roles),
+                list(&user.scopes),
+                list(
+                    &user
+                        .attributes
+                        .iter()
+                        .map(|(key, value)| format!("{}={}", key, value))
+                        .collect::<Vec<_>>(),
+                ),
+            ]
+        })
+        .collect();
+    print_table(
+        &[
+            "ID",
+            "STATUS",
+            "CREATED AT",
+            "LAST LOGIN",
+            "KEY EXPIRES AT",
+            "LOCKED UNTIL",
+            "ROLES",
+            "SCOPES",
+            "ATTRIBUTES",
+        ],
+        rows,
+    );
+}
+
+fn timestamp(timestamp: Option<u64>) -> String {
+    timestamp.map_or_else(|| "-".to_string(), |timestamp| timestamp.to_string())
+}
+
+fn list(values: &[String]) -> String {
+    match values.is_empty() {
+        true => "-".to_string(),
+        false => values.join(","),
+    }
+}
+
+fn print_sessions(cli: &Cli, sessions: Vec<SessionRow>) {
+    if cli.json {
+        return print_json(&sessions);
+    }
+    let rows = sessions
+        .iter()
+        .map(|session| {
+            vec![
+                session.id.clone(),
+                session.audience.clone(),
+                session.scopes.join(","),
+                session.key_bound.to_string(),
+                session.channel_bound.to_string(),
+                session.expires_at.to_string(),
+            ]
+        })
+        .collect();
+    print_table(
+        &[
+            "ID",
+            "AUDIENCE",
+            "SCOPES",
+            "KEY BOUND",
+            "CHANNEL BOUND",
+            "EXPIRES AT",
+        ],
+        rows,
+    );
+}
+
+fn print_count(cli: &Cli, what: &str, count: u32) {
+    match cli.json {
+        true => print_json(&Count { count }),
+        false => println!("{} {}", count, what),
+    }
+}
+
+fn print_json<T: Serialize>(value: &T) {
+    println!(
+        "{}",
+        serde_json::to_string_pretty(value).expect("rows serialize")
+    );
+}
+
+// Left aligned columns as wide as their widest cell
+fn print_table(headers: &[&str], rows: Vec<Vec<String>>) {
+    let mut widths: Vec<usize> = headers.iter().map(|header| header.len()).collect();
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+    let line = |cells: Vec<&str>| {
+        let padded: Vec<String> = cells
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{:width$}", cell, width = width))
+            .collect();
+        println!("{}", padded.join("  ").trim_end());
+    };
+    line(headers.to_vec());
+    for row in &rows {
+        line(row.iter().map(String::as_str).collect());
+    }
+}
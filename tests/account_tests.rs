@@ -0,0 +1,88 @@
+#![cfg(feature = "net")]
+
+use common_lib::account::{check_login, check_status, record_login, AccountError, AccountPolicy};
+use common_lib::store::models::{User, UserStatus};
+use std::time::Duration;
+
+#[test]
+fn test_failures_lock_active_users_only() {
+    let policy = AccountPolicy {
+        max_failed_logins: 3,
+        lockout: Duration::from_secs(100),
+        ..Default::default()
+    };
+    let mut user = User::default();
+    assert!(!policy.record_failure(&mut user, 1000));
+    assert!(!policy.record_failure(&mut user, 1000));
+    assert!(policy.record_failure(&mut user, 1000));
+    assert_eq!(user.status, UserStatus::Locked);
+    assert_eq!(check_status(&user, 1099), Err(AccountError::Locked(1100)));
+    assert_eq!(check_status(&user, 1100), Ok(()));
+
+    record_login(&mut user, 1100);
+    assert_eq!(user.status, UserStatus::Active);
+    assert_eq!(user.failed_logins, 0);
+    assert_eq!(user.locked_until, None);
+
+    // a disabled user stays disabled rather than being locked
+    let mut user = User {
+        status: UserStatus::Disabled,
+        failed_logins: 10,
+        ..Default::default()
+    };
+    assert!(!policy.record_failure(&mut user, 1000));
+    assert_eq!(user.status, UserStatus::Disabled);
+
+    let never = AccountPolicy {
+        max_failed_logins: 0,
+        ..Default::default()
+    };
+    assert!(!never.record_failure(&mut User::default(), 1000));
+}
+
+#[test]
+fn test_expired_lock_locks_again() {
+    let policy = AccountPolicy {
+        max_failed_logins: 3,
+        lockout: Duration::from_secs(100),
+        ..Default::default()
+    };
+    let mut user = User::default();
+    for _ in 0..3 {
+        policy.record_failure(&mut user, 1000);
+    }
+    assert_eq!(check_status(&user, 1000), Err(AccountError::Locked(1100)));
+
+    // failures during the lock do not extend it
+    assert!(!policy.record_failure(&mut user, 1050));
+    assert_eq!(user.locked_until, Some(1100));
+
+    // past the lockout the guessing starts over rather than going on forever
+    assert!(!policy.record_failure(&mut user, 1200));
+    assert_eq!(user.status, UserStatus::Active);
+    assert_eq!(user.failed_logins, 1);
+    assert!(!policy.record_failure(&mut user, 1200));
+    assert!(policy.record_failure(&mut user, 1200));
+    assert_eq!(check_status(&user, 1200), Err(AccountError::Locked(1300)));
+}
+
+#[test]
+fn test_key_expiry() {
+    let policy = AccountPolicy {
+        key_lifetime: Some(Duration::from_secs(50)),
+        key_rotation_age: Some(Duration::from_secs(30)),
+        ..Default::default()
+    };
+    let mut user = User::default();
+    policy.new_user(&mut user, 1000);
+    assert_eq!(user.key_expires_at, Some(1050));
+
+    assert!(!policy.key_rotation_due(&user, 1029));
+    assert!(policy.key_rotation_due(&user, 1030));
+    assert_eq!(check_login(&user, 1049), Ok(()));
+    assert_eq!(check_login(&user, 1050), Err(AccountError::KeyExpired));
+
+    policy.new_key(&mut user, 1050);
+    assert_eq!(check_login(&user, 1050), Ok(()));
+    assert_eq!(user.created_at, 1000);
+}
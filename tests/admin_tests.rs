@@ -0,0 +1,335 @@
+#![cfg(feature = "net")]
+
+use common_lib::admin::{AdminAuth, AdminServer};
+use common_lib::blind_auth_api::blind_auth_admin_server::BlindAuthAdmin;
+use common_lib::blind_auth_api::blind_auth_server::BlindAuth;
+use common_lib::blind_auth_api::{
+    AdminUserRequest, AuthChallengeRequest, ExportUsersRequest, ExportedUser, ImportUsersRequest,
+    ListUsersRequest, RevokeSessionRequest, SetUserAttributesRequest, SetUserRolesRequest,
+    SetUserScopesRequest,
+};
+use common_lib::gen_params;
+use common_lib::session_key::SessionKey;
+use common_lib::store::models::{Session, User, UserStatus};
+use common_lib::store::store::DataStore;
+use common_lib::verifier::AuthServer;
+use num_bigint::BigInt;
+use num_traits::Num;
+use tonic::service::Interceptor;
+use tonic::{Code, Request};
+
+fn user(id: &str) -> User {
+    User {
+        id: id.to_string(),
+        y1: BigInt::from(4),
+        y2: BigInt::from(9),
+        status: UserStatus::Active,
+        ..Default::default()
+    }
+}
+
+fn session(id: &str, user_id: &str) -> Session {
+    Session {
+        id: id.to_string(),
+        user_id: user_id.to_string(),
+        key: None,
+        public_key: None,
+        channel_binding: None,
+        audience: "billing".to_string(),
+        scopes: vec!["invoices:read".to_string()],
+        roles: vec![],
+        attributes: Default::default(),
+    }
+}
+
+fn user_request(user: &str) -> Request<AdminUserRequest> {
+    Request::new(AdminUserRequest {
+        user: user.to_string(),
+    })
+}
+
+#[tokio::test]
+async fn test_list_users_pages() {
+    let store = DataStore::new();
+    for id in ["carol", "alice", "dave", "bob", "erin"] {
+        store.insert_user(user(id));
+    }
+    let admin = AdminServer::new(store);
+
+    let mut ids = vec![];
+    let mut page_token = String::new();
+    loop {
+        let page = admin
+            .list_users(Request::new(ListUsersRequest {
+                page_size: 2,
+                page_token,
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(page.users.len() <= 2);
+        ids.extend(page.users.into_iter().map(|user| user.id));
+        if page.next_page_token.is_empty() {
+            break;
+        }
+        page_token = page.next_page_token;
+    }
+    assert_eq!(ids, ["alice", "bob", "carol", "dave", "erin"]);
+}
+
+#[tokio::test]
+async fn test_disable_user_revokes_sessions_and_blocks_login() {
+    let store = DataStore::new();
+    store.insert_user(user("alice"));
+    store.insert_session(session("s1", "alice"));
+    store.insert_session(session("s2", "alice"));
+    store.insert_session(session("s3", "bob"));
+    let admin = AdminServer::new(store.clone());
+    let auth_server = AuthServer::new(store.clone());
+
+    let disabled = admin.disable_user(user_request("alice")).await.unwrap();
+    assert_eq!(disabled.get_ref().status, "disabled");
+    assert!(store.get_session(&"s1".to_string()).is_none());
+    assert!(store.get_session(&"s2".to_string()).is_none());
+    assert!(store.get_session(&"s3".to_string()).is_some());
+
+    let (r1, r2) = gen_params(&BigInt::from(42));
+    let request = AuthChallengeRequest {
+        user: "alice".to_string(),
+        r1,
+        r2,
+        ..Default::default()
+    };
+    let status = auth_server
+        .create_authentication_challenge(Request::new(request.clone()))
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::PermissionDenied);
+
+    let enabled = admin.enable_user(user_request("alice")).await.unwrap();
+    assert_eq!(enabled.get_ref().status, "active");
+    assert!(auth_server
+        .create_authentication_challenge(Request::new(request))
+        .await
+        .is_ok());
+}
+
+#[tokio::test]
+async fn test_delete_user() {
+    let store = DataStore::new();
+    store.insert_user(user("alice"));
+    store.insert_session(session("s1", "alice"));
+    let admin = AdminServer::new(store.clone());
+
+    let deleted = admin.delete_user(user_request("alice")).await.unwrap();
+    assert_eq!(deleted.get_ref().revoked_sessions, 1);
+    assert!(store.get_user(&"alice".to_string()).is_none());
+
+    let status = admin.get_user(user_request("alice")).await.unwrap_err();
+    assert_eq!(status.code(), Code::NotFound);
+    let status = admin.delete_user(user_request("alice")).await.unwrap_err();
+    assert_eq!(status.code(), Code::NotFound);
+}
+
+#[tokio::test]
+async fn test_list_and_revoke_sessions() {
+    let store = DataStore::new();
+    store.insert_user(user("alice"));
+    store.insert_session(session("s1", "alice"));
+    store.insert_session(session("s2", "alice"));
+    store.insert_session(Session {
+        key: Some(SessionKey::derive(&BigInt::from(7), &[0u8; 32])),
+        ..session("s3", "alice")
+    });
+    let admin = AdminServer::new(store);
+
+    let sessions = admin.list_sessions(user_request("alice")).await.unwrap();
+    let sessions = &sessions.get_ref().sessions;
+    assert_eq!(sessions.len(), 3);
+    assert!(sessions.iter().all(|session| session.audience == "billing"
+        && session.scopes == ["invoices:read"]
+        && session.key_bound == (session.id == "s3")
+        && session.expires_at > 0));
+
+    let revoke = |session_id: &str| {
+        Request::new(RevokeSessionRequest {
+            session_id: session_id.to_string(),
+        })
+    };
+    let revoked = admin.revoke_session(revoke("s1")).await.unwrap();
+    assert_eq!(revoked.get_ref().revoked, 1);
+    let revoked = admin.revoke_session(revoke("s1")).await.unwrap();
+    assert_eq!(revoked.get_ref().revoked, 0);
+
+    let revoked = admin
+        .revoke_all_sessions(user_request("alice"))
+        .await
+        .unwrap();
+    assert_eq!(revoked.get_ref().revoked, 2);
+    let sessions = admin.list_sessions(user_request("alice")).await.unwrap();
+    assert!(sessions.get_ref().sessions.is_empty());
+}
+
+#[test]
+fn test_admin_token() {
+    let token = "7f3a9c1e5b2d8f4a6c0e9b3d7a1f5c2e";
+    let mut auth = AdminAuth::token(token).unwrap();
+    let with_header = |value: &str| {
+        let mut request = Request::new(());
+        request
+            .metadata_mut()
+            .insert("authorization", value.parse().unwrap());
+        request
+    };
+
+    assert!(auth.call(with_header(&format!("Bearer {}", token))).is_ok());
+    for value in [
+        "Bearer wrong".to_string(),
+        "Bearer ".to_string(),
+        token.to_string(),
+        format!("Basic {}", token),
+    ] {
+        let status = auth.call(with_header(&value)).unwrap_err();
+        assert_eq!(status.code(), Code::Unauthenticated);
+    }
+    let status = auth.call(Request::new(())).unwrap_err();
+    assert_eq!(status.code(), Code::Unauthenticated);
+
+    // an empty or guessable token file does not start the service
+    assert!(AdminAuth::token("").is_err());
+    assert!(AdminAuth::token("s3cret").is_err());
+    assert!(AdminAuth::token(&token[1..]).is_err());
+
+    // the listener checked the client certificate already
+    assert!(AdminAuth::client_certificate()
+        .call(Request::new(()))
+        .is_ok());
+}
+
+#[tokio::test]
+async fn test_export_and_import_users() {
+    let store = DataStore::new();
+    let (y1, y2) = gen_params(&BigInt::from(7));
+    let registered = User {
+        id: "alice".to_string(),
+        y1: BigInt::from_str_radix(&y1, 16).unwrap(),
+        y2: BigInt::from_str_radix(&y2, 16).unwrap(),
+        status: UserStatus::Disabled,
+        ..Default::default()
+    };
+    store.insert_user(registered.clone());
+    let admin = AdminServer::new(store);
+
+    let exported = admin
+        .export_users(Request::new(ExportUsersRequest {}))
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(exported.users.len(), 1);
+    assert_eq!(exported.users[0].status, "disabled");
+
+    let target = DataStore::new();
+    target.insert_session(session("s1", "alice"));
+    let admin = AdminServer::new(target.clone());
+    let imported = admin
+        .import_users(Request::new(ImportUsersRequest {
+            users: exported.users.clone(),
+        }))
+        .await
+        .unwrap();
+    assert_eq!(imported.get_ref().imported, 1);
+    let alice = target.get_user(&"alice".to_string()).unwrap();
+    assert_eq!(alice.y1, registered.y1);
+    assert_eq!(alice.status, UserStatus::Disabled);
+
+    // replacing the keys ends the sessions proven with the old ones
+    target.insert_user(user("alice"));
+    let imported = admin
+        .import_users(Request::new(ImportUsersRequest {
+            users: exported.users.clone(),
+        }))
+        .await
+        .unwrap();
+    assert_eq!(imported.get_ref().revoked_sessions, 1);
+
+    // one bad user fails the whole import
+    let mut users = exported.users;
+    users.push(ExportedUser {
+        id: "bob".to_string(),
+        y1: "1".to_string(),
+        y2: "1".to_string(),
+        status: "active".to_string(),
+        ..Default::default()
+    });
+    let status = admin
+        .import_users(Request::new(ImportUsersRequest { users }))
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::InvalidArgument);
+    assert!(target.get_user(&"bob".to_string()).is_none());
+}
+
+#[tokio::test]
+async fn test_set_user_roles_scopes_and_attributes() {
+    let store = DataStore::new();
+    store.insert_user(user("alice"));
+    let admin = AdminServer::new(store.clone());
+
+    let updated = admin
+        .set_user_roles(Request::new(SetUserRolesRequest {
+            user: "alice".to_string(),
+            roles: vec!["admin".to_string(), "billing:viewer".to_string()],
+        }))
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(updated.roles, ["admin", "billing:viewer"]);
+
+    admin
+        .set_user_scopes(Request::new(SetUserScopesRequest {
+            user: "alice".to_string(),
+            scopes: vec!["invoices:read".to_string()],
+        }))
+        .await
+        .unwrap();
+    let updated = admin
+        .set_user_attributes(Request::new(SetUserAttributesRequest {
+            user: "alice".to_string(),
+            attributes: [("team".to_string(), "payments".to_string())].into(),
+        }))
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(updated.scopes, ["invoices:read"]);
+    assert_eq!(updated.attributes["team"], "payments");
+
+    let alice = store.get_user(&"alice".to_string()).unwrap();
+    assert_eq!(alice.roles, ["admin", "billing:viewer"]);
+    assert_eq!(alice.scopes, ["invoices:read"]);
+    assert_eq!(alice.attributes["team"], "payments");
+
+    let status = admin
+        .set_user_roles(Request::new(SetUserRolesRequest {
+            user: "alice".to_string(),
+            roles: vec!["admin".to_string(), "admin".to_string()],
+        }))
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::InvalidArgument);
+    let status = admin
+        .set_user_attributes(Request::new(SetUserAttributesRequest {
+            user: "alice".to_string(),
+            attributes: [("team".to_string(), "pay\nments".to_string())].into(),
+        }))
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::InvalidArgument);
+    let status = admin
+        .set_user_scopes(Request::new(SetUserScopesRequest {
+            user: "bob".to_string(),
+            scopes: vec![],
+        }))
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::NotFound);
+}
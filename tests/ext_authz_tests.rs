@@ -0,0 +1,197 @@
+#![cfg(feature = "net")]
+
+use common_lib::envoy_auth_api::attribute_context::{HttpRequest, Request as AttributeRequest};
+use common_lib::envoy_auth_api::authorization_client::AuthorizationClient;
+use common_lib::envoy_auth_api::authorization_server::AuthorizationServer;
+use common_lib::envoy_auth_api::check_response::HttpResponse;
+use common_lib::envoy_auth_api::{
+    AttributeContext, CheckRequest, DeniedHttpResponse, OkHttpResponse,
+};
+use common_lib::ext_authz::ExtAuthzServer;
+use common_lib::middleware::{SessionValidator, ROLES_HEADER, SCOPES_HEADER, USER_HEADER};
+use common_lib::rng::OsRngProvider;
+use common_lib::schnorr::KeyPair;
+use common_lib::session_key::SessionKey;
+use common_lib::session_token::{self, SessionClaims};
+use common_lib::store::models::Session;
+use common_lib::store::store::DataStore;
+use num_bigint::BigInt;
+use std::collections::HashMap;
+use tokio::net::TcpListener;
+use tokio_stream::wrappers::TcpListenerStream;
+use tonic::transport::{Channel, Server};
+
+fn session(id: &str) -> Session {
+    Session {
+        id: id.to_string(),
+        user_id: "alice".to_string(),
+        key: None,
+        public_key: None,
+        channel_binding: None,
+        audience: "billing".to_string(),
+        scopes: vec!["invoices:read".to_string()],
+        roles: vec![],
+        attributes: Default::default(),
+    }
+}
+
+// Starts the ext_authz service on a random port, standing in for the
+// verifier, and returns a client playing Envoy
+async fn start(store: DataStore, key: &KeyPair) -> AuthorizationClient<Channel> {
+    let validator = SessionValidator::new()
+        .with_store(store)
+        .with_verifier_key(key.public.clone())
+        .unwrap();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(
+        Server::builder()
+            .add_service(AuthorizationServer::new(ExtAuthzServer::new(validator)))
+            .serve_with_incoming(TcpListenerStream::new(listener)),
+    );
+    AuthorizationClient::connect(url).await.unwrap()
+}
+
+async fn check(
+    client: &mut AuthorizationClient<Channel>,
+    headers: &[(&str, &str)],
+    extensions: &[(&str, &str)],
+) -> HttpResponse {
+    let pairs = |pairs: &[(&str, &str)]| -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    };
+    let request = CheckRequest {
+        attributes: Some(AttributeContext {
+            request: Some(AttributeRequest {
+                http: Some(HttpRequest {
+                    method: "GET".to_string(),
+                    path: "/invoices".to_string(),
+                    headers: pairs(headers),
+                    ..Default::default()
+                }),
+            }),
+            context_extensions: pairs(extensions),
+        }),
+    };
+    client
+        .check(request)
+        .await
+        .unwrap()
+        .into_inner()
+        .http_response
+        .unwrap()
+}
+
+fn ok(response: HttpResponse) -> OkHttpResponse {
+    match response {
+        HttpResponse::OkResponse(ok) => ok,
+        HttpResponse::DeniedResponse(denied) => panic!("denied: {}", denied.body),
+    }
+}
+
+fn denied(response: HttpResponse) -> DeniedHttpResponse {
+    match response {
+        HttpResponse::DeniedResponse(denied) => denied,
+        HttpResponse::OkResponse(_) => panic!("allowed"),
+    }
+}
+
+#[tokio::test]
+async fn test_check_allows_valid_sessions() {
+    let store = DataStore::new();
+    store.insert_session(session("s1"));
+    let key = KeyPair::generate(&OsRngProvider);
+    let mut client = start(store, &key).await;
+
+    let allowed = ok(check(
+        &mut client,
+        &[("authorization", "Bearer s1"), (USER_HEADER, "mallory")],
+        &[("audience", "billing"), ("scopes", "invoices:read")],
+    )
+    .await);
+    let headers: HashMap<String, String> = allowed
+        .headers
+        .iter()
+        .map(|option| option.header.clone().unwrap())
+        .map(|header| (header.key, header.value))
+        .collect();
+    // the client's own x-blindauth-user is overwritten
+    assert_eq!(headers[USER_HEADER], "alice");
+    assert_eq!(headers[SCOPES_HEADER], "invoices:read");
+    assert!(allowed
+        .headers
+        .iter()
+        .all(|option| option.append_action == 2));
+    assert_eq!(allowed.headers_to_remove, [ROLES_HEADER]);
+
+    // signed tokens are checked with the verifier key
+    let claims = SessionClaims {
+        session_id: "s2".to_string(),
+        user: "bob".to_string(),
+        audience: "billing".to_string(),
+        scopes: vec![],
+        roles: vec!["admin".to_string()],
+        attributes: Default::default(),
+        issued_at: 0,
+        expires_at: u64::MAX,
+    };
+    let token = session_token::issue(&claims, &key, &OsRngProvider);
+    let allowed = ok(check(
+        &mut client,
+        &[("authorization", &format!("Bearer {}", token))],
+        &[],
+    )
+    .await);
+    assert_eq!(allowed.headers[0].header.as_ref().unwrap().value, "bob");
+}
+
+#[tokio::test]
+async fn test_check_denies_invalid_sessions() {
+    let store = DataStore::new();
+    store.insert_session(session("s1"));
+    // a key exchange session needs a MAC under its key with each request
+    store.insert_session(Session {
+        key: Some(SessionKey::derive(&BigInt::from(7), &[0u8; 32])),
+        ..session("s2")
+    });
+    let mut client = start(store, &KeyPair::generate(&OsRngProvider)).await;
+
+    let missing = denied(check(&mut client, &[], &[]).await);
+    assert_eq!(missing.status.unwrap().code, 401);
+    assert_eq!(
+        missing.headers[0].header.as_ref().unwrap().key,
+        "www-authenticate"
+    );
+    let unknown = denied(check(&mut client, &[("authorization", "Bearer s9")], &[]).await);
+    assert_eq!(unknown.status.unwrap().code, 401);
+    assert_eq!(unknown.body, "session not found");
+    let keyed = denied(check(&mut client, &[("authorization", "Bearer s2")], &[]).await);
+    assert_eq!(keyed.status.unwrap().code, 401);
+    assert_eq!(keyed.body, "session is bound to a key or channel");
+
+    let other_audience = denied(
+        check(
+            &mut client,
+            &[("authorization", "Bearer s1")],
+            &[("audience", "admin")],
+        )
+        .await,
+    );
+    assert_eq!(other_audience.status.unwrap().code, 403);
+    let missing_scope = denied(
+        check(
+            &mut client,
+            &[("authorization", "Bearer s1")],
+            &[("scopes", "invoices:read, invoices:write")],
+        )
+        .await,
+    );
+    assert_eq!(missing_scope.status.unwrap().code, 403);
+    assert_eq!(
+        missing_scope.body,
+        "session is missing scope invoices:write"
+    );
+}
@@ -0,0 +1,127 @@
+#![cfg(feature = "net")]
+
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use axum::response::Response;
+use axum::Router;
+use common_lib::clock::MockClock;
+use common_lib::forward_auth::{router, SESSION_COOKIE};
+use common_lib::middleware::{SessionValidator, AUDIENCE_HEADER, ROLES_HEADER, USER_HEADER};
+use common_lib::rng::OsRngProvider;
+use common_lib::schnorr::KeyPair;
+use common_lib::session_token::{self, SessionClaims};
+use common_lib::store::models::Session;
+use common_lib::store::store::{DataStore, SESSION_TTL};
+use std::sync::Arc;
+use tower::ServiceExt;
+
+fn setup() -> (Router, DataStore, Arc<MockClock>) {
+    let clock = Arc::new(MockClock::default());
+    let store = DataStore::with_clock(clock.clone());
+    store.insert_session(Session {
+        id: "s1".to_string(),
+        user_id: "alice".to_string(),
+        key: None,
+        public_key: None,
+        channel_binding: None,
+        audience: "billing".to_string(),
+        scopes: vec!["invoices:read".to_string()],
+        roles: vec!["admin".to_string()],
+        attributes: Default::default(),
+    });
+    let validator = SessionValidator::new().with_store(store.clone());
+    (router(validator), store, clock)
+}
+
+async fn get(router: &Router, uri: &str, headers: &[(&str, &str)]) -> Response {
+    let mut request = Request::get(uri);
+    for (name, value) in headers {
+        request = request.header(*name, *value);
+    }
+    router
+        .clone()
+        .oneshot(request.body(Body::empty()).unwrap())
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn test_auth_accepts_bearer_and_cookie() {
+    let (router, _, _) = setup();
+
+    let response = get(&router, "/auth", &[("authorization", "Bearer s1")]).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[USER_HEADER], "alice");
+    assert_eq!(response.headers()[AUDIENCE_HEADER], "billing");
+    assert_eq!(response.headers()[ROLES_HEADER], "admin");
+
+    let cookie = format!("theme=dark; {}=s1", SESSION_COOKIE);
+    let response = get(
+        &router,
+        "/auth?audience=billing&scopes=invoices:read",
+        &[("cookie", &cookie)],
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[USER_HEADER], "alice");
+}
+
+#[tokio::test]
+async fn test_auth_rejects_invalid_sessions() {
+    let (router, _, clock) = setup();
+
+    let response = get(&router, "/auth", &[]).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(response.headers()["www-authenticate"], "Bearer");
+    let response = get(&router, "/auth", &[("authorization", "Basic czE6")]).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let response = get(&router, "/auth", &[("cookie", "blindauth_session=s2")]).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let response = get(
+        &router,
+        "/auth?scopes=invoices:read,invoices:write",
+        &[("authorization", "Bearer s1")],
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let response = get(
+        &router,
+        "/auth?audience=admin",
+        &[("authorization", "Bearer s1")],
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    clock.advance(SESSION_TTL);
+    let response = get(&router, "/auth", &[("authorization", "Bearer s1")]).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn test_auth_accepts_session_tokens() {
+    let key = KeyPair::generate(&OsRngProvider);
+    let validator = SessionValidator::new()
+        .with_store(DataStore::new())
+        .with_verifier_key(key.public.clone())
+        .unwrap();
+    let router = router(validator);
+    let claims = SessionClaims {
+        session_id: "s3".to_string(),
+        user: "bob".to_string(),
+        audience: String::new(),
+        scopes: vec![],
+        roles: vec![],
+        attributes: Default::default(),
+        issued_at: 0,
+        expires_at: u64::MAX,
+    };
+    let token = session_token::issue(&claims, &key, &OsRngProvider);
+
+    let cookie = format!("{}={}", SESSION_COOKIE, token);
+    let response = get(&router, "/auth", &[("cookie", &cookie)]).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[USER_HEADER], "bob");
+    // present but empty, so the proxy replaces what the client sent
+    assert_eq!(response.headers()[ROLES_HEADER], "");
+}
@@ -0,0 +1,143 @@
+#![cfg(feature = "net")]
+
+use axum::body::Body;
+use axum::http::{Method, Request, StatusCode};
+use axum::Router;
+use common_lib::gateway::router;
+use common_lib::gen_params;
+use common_lib::prover::compute_auth_secret;
+use common_lib::store::store::DataStore;
+use common_lib::verifier::AuthServer;
+use num_bigint::BigInt;
+use num_traits::Num;
+use serde_json::{json, Value};
+use std::sync::Arc;
+use tower::ServiceExt;
+
+fn setup() -> Router {
+    router(Arc::new(AuthServer::new(DataStore::new())))
+}
+
+async fn call(
+    router: &Router,
+    method: Method,
+    uri: &str,
+    body: Option<Value>,
+) -> (StatusCode, Value) {
+    let request = Request::builder().method(method).uri(uri);
+    let request = match body {
+        Some(body) => request
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string())),
+        None => request.body(Body::empty()),
+    };
+    let response = router.clone().oneshot(request.unwrap()).await.unwrap();
+    let status = response.status();
+    let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    (
+        status,
+        serde_json::from_slice(&bytes).unwrap_or(Value::Null),
+    )
+}
+
+async fn post(router: &Router, uri: &str, body: Value) -> (StatusCode, Value) {
+    call(router, Method::POST, uri, Some(body)).await
+}
+
+#[tokio::test]
+async fn test_login_over_json() {
+    let router = setup();
+    let (status, params) = call(&router, Method::GET, "/v1/public-params", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(!params["verifier_key"].as_str().unwrap().is_empty());
+
+    let x = BigInt::from(12345);
+    let (y1, y2) = gen_params(&x);
+    let (status, _) = post(
+        &router,
+        "/v1/register",
+        json!({"user": "alice", "y1": y1, "y2": y2}),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    // fields left out take their defaults
+    let k = BigInt::from(678);
+    let (r1, r2) = gen_params(&k);
+    let (status, challenge) = post(
+        &router,
+        "/v1/challenges",
+        json!({"user": "alice", "r1": r1, "r2": r2}),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let auth_id = challenge["auth_id"].as_str().unwrap();
+
+    let uri = format!("/v1/challenges/{}", auth_id);
+    let (status, pending) = call(&router, Method::GET, &uri, None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(pending["expires_at"], challenge["expires_at"]);
+
+    let c = BigInt::from_str_radix(challenge["c"].as_str().unwrap(), 16).unwrap();
+    let s = compute_auth_secret(c, k, x);
+    let (status, answer) = post(
+        &router,
+        "/v1/answers",
+        json!({"auth_id": auth_id, "s": s.to_str_radix(16)}),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, session) = post(
+        &router,
+        "/v1/sessions/validate",
+        json!({"session_id": answer["session_id"]}),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(session["user"], "alice");
+    assert_eq!(session["channel_bound"], false);
+}
+
+#[tokio::test]
+async fn test_errors_carry_grpc_code() {
+    let router = setup();
+
+    let (status, error) = call(&router, Method::GET, "/v1/challenges/missing", None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(error["code"], tonic::Code::NotFound as i32);
+    let (status, _) = call(&router, Method::DELETE, "/v1/challenges/missing", None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let (status, error) = post(&router, "/v1/register", json!({"user": 1})).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(error["code"], tonic::Code::InvalidArgument as i32);
+    let (status, _) = post(&router, "/v1/register", json!({"user": "bob", "y1": "zz"})).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    // no TLS connection to bind to
+    let (r1, r2) = gen_params(&BigInt::from(5));
+    let (status, _) = post(
+        &router,
+        "/v1/challenges",
+        json!({"user": "bob", "r1": r1, "r2": r2, "channel_binding": true}),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_serves_openapi_document() {
+    let router = setup();
+    let request = Request::get("/v1/openapi.yaml")
+        .body(Body::empty())
+        .unwrap();
+    let response = router.oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let document = String::from_utf8(bytes.to_vec()).unwrap();
+    assert!(document.starts_with("openapi: 3"));
+    for path in ["/v1/register:", "/v1/challenges:", "/v1/answers:"] {
+        assert!(document.contains(path));
+    }
+}
@@ -0,0 +1,101 @@
+use common_lib::group::{multi_exp, FixedBaseTable, Montgomery};
+use common_lib::public_params::PublicParams;
+use common_lib::random_in_range;
+use num_bigint::BigInt;
+use num_traits::{One, Zero};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+
+fn exponents(rng: &mut StdRng) -> Vec<BigInt> {
+    let q = PublicParams::q();
+    let mut exponents = vec![
+        BigInt::zero(),
+        BigInt::one(),
+        BigInt::from(31),
+        q - BigInt::one(),
+    ];
+    for _ in 0..4 {
+        exponents.push(random_in_range(rng, &BigInt::zero(), q));
+    }
+    exponents
+}
+
+#[test]
+fn test_montgomery_round_trip() {
+    let mont = PublicParams::montgomery();
+    let p = PublicParams::p();
+
+    for v in [BigInt::zero(), BigInt::from(9), p - BigInt::one()] {
+        assert_eq!(mont.from_mont(&mont.to_mont(&v)), v);
+    }
+    // inputs are reduced mod p first
+    assert_eq!(
+        mont.from_mont(&mont.to_mont(&(p + BigInt::from(5)))),
+        BigInt::from(5)
+    );
+}
+
+#[test]
+fn test_fixed_base_table_matches_modpow() {
+    let mut rng = StdRng::seed_from_u64(1);
+    let mont = PublicParams::montgomery();
+    let p = PublicParams::p();
+
+    for e in exponents(&mut rng) {
+        assert_eq!(
+            multi_exp(mont, &[(PublicParams::g_table(), &e)], &[]),
+            PublicParams::g().modpow(&e, p)
+        );
+        assert_eq!(
+            multi_exp(mont, &[(PublicParams::h_table(), &e)], &[]),
+            PublicParams::h().modpow(&e, p)
+        );
+    }
+}
+
+#[test]
+fn test_multi_exp_matches_modpow() {
+    let mut rng = StdRng::seed_from_u64(2);
+    let p = PublicParams::p();
+    let mont = PublicParams::montgomery();
+    let y1 = PublicParams::g().modpow(&BigInt::from(12345), p);
+    let y2 = PublicParams::h().modpow(&BigInt::from(12345), p);
+
+    let es = exponents(&mut rng);
+    for (s, c) in es.iter().zip(es.iter().rev()) {
+        let expected = (PublicParams::g().modpow(s, p) * y1.modpow(c, p)) % p;
+        assert_eq!(
+            multi_exp(mont, &[(PublicParams::g_table(), s)], &[(&y1, c)]),
+            expected
+        );
+        assert_eq!(
+            multi_exp(mont, &[], &[(PublicParams::g(), s), (&y1, c)]),
+            expected
+        );
+
+        let expected = (expected * PublicParams::h().modpow(c, p) * y2.modpow(s, p)) % p;
+        assert_eq!(
+            multi_exp(
+                mont,
+                &[(PublicParams::g_table(), s), (PublicParams::h_table(), c)],
+                &[(&y1, c), (&y2, s)]
+            ),
+            expected
+        );
+    }
+}
+
+#[test]
+fn test_small_modulus() {
+    let p = BigInt::from(1_000_000_007u64);
+    let mont = Montgomery::new(&p);
+    let table = FixedBaseTable::new(&mont, &BigInt::from(5), 64, 3);
+
+    for e in [0u64, 1, 2, 1_000_000_006, u64::MAX] {
+        let e = BigInt::from(e);
+        assert_eq!(
+            multi_exp(&mont, &[(&table, &e)], &[(&BigInt::from(7), &e)]),
+            (BigInt::from(5).modpow(&e, &p) * BigInt::from(7).modpow(&e, &p)) % &p
+        );
+    }
+}
@@ -0,0 +1,284 @@
+#![cfg(feature = "net")]
+
+use common_lib::blind_auth_api::blind_auth_client::BlindAuthClient;
+use common_lib::blind_auth_api::blind_auth_server::{BlindAuth, BlindAuthServer};
+use common_lib::blind_auth_api::{AuthAnswerRequest, AuthAnswerResponse, AuthChallengeRequest};
+use common_lib::clock::MockClock;
+use common_lib::gen_params;
+use common_lib::middleware::{
+    authenticated_user, require_scopes, AuthenticatedUser, SessionLayer, SessionValidator,
+};
+use common_lib::pool::CryptoPool;
+use common_lib::prover::{compute_auth_secret, ChallengeCommitment};
+use common_lib::public_params::PublicParams;
+use common_lib::store::models::{User, UserStatus};
+use common_lib::store::store::DataStore;
+use common_lib::verifier::AuthServer;
+use num_bigint::BigInt;
+use num_traits::Num;
+use std::convert::Infallible;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio_stream::wrappers::TcpListenerStream;
+use tonic::codegen::http;
+use tonic::transport::Server;
+use tonic::{Code, Request};
+use tower::{Layer, Service, ServiceExt};
+
+const SCOPES: [&str; 2] = ["invoices:read", "invoices:write"];
+
+fn setup(clock: Arc<MockClock>) -> AuthServer {
+    let auth_server =
+        AuthServer::new(DataStore::with_clock(clock)).with_session_tokens(Duration::from_secs(60));
+    let (y1, y2) = gen_params(&BigInt::from(12345));
+    auth_server.store.insert_user(User {
+        id: String::from("dummy"),
+        y1: BigInt::from_str_radix(&y1, 16).unwrap(),
+        y2: BigInt::from_str_radix(&y2, 16).unwrap(),
+        status: UserStatus::Active,
+        roles: vec![String::from("admin")],
+        scopes: SCOPES.iter().map(|scope| scope.to_string()).collect(),
+        ..Default::default()
+    });
+    auth_server
+}
+
+// Logs in to audience "billing" with scopes, key exchange if asked for
+async fn login(
+    auth_server: &AuthServer,
+    scopes: &[&str],
+    key_exchange: bool,
+) -> AuthAnswerResponse {
+    let k = BigInt::from(42);
+    let (r1, r2) = gen_params(&k);
+    let scopes: Vec<String> = scopes.iter().map(|scope| scope.to_string()).collect();
+    let response = auth_server
+        .create_authentication_challenge(Request::new(AuthChallengeRequest {
+            user: String::from("dummy"),
+            r1: r1.clone(),
+            r2: r2.clone(),
+            key_exchange,
+            audience: String::from("billing"),
+            scopes: scopes.clone(),
+            ..Default::default()
+        }))
+        .await
+        .unwrap()
+        .into_inner();
+    let (r1, r2) = (
+        BigInt::from_str_radix(&r1, 16).unwrap(),
+        BigInt::from_str_radix(&r2, 16).unwrap(),
+    );
+    let c = ChallengeCommitment {
+        user: "dummy",
+        r1: &r1,
+        r2: &r2,
+        session_public_key: None,
+        channel_binding: None,
+        audience: "billing",
+        scopes: &scopes,
+    }
+    .challenge(&response)
+    .unwrap();
+    let s = compute_auth_secret(c, k, BigInt::from(12345));
+    auth_server
+        .verify_authentication(Request::new(AuthAnswerRequest {
+            auth_id: response.auth_id,
+            s: s.to_str_radix(16),
+        }))
+        .await
+        .unwrap()
+        .into_inner()
+}
+
+#[tokio::test]
+async fn test_session_tokens_are_checked_offline() {
+    let clock = Arc::new(MockClock::default());
+    let auth_server = setup(clock.clone());
+    let answer = login(&auth_server, &["invoices:read"], false).await;
+    assert!(!answer.session_token.is_empty());
+
+    let validator = SessionValidator::new()
+        .with_verifier_key(auth_server.verifier_key().clone())
+        .unwrap()
+        .with_audience("billing")
+        .with_clock(clock.clone());
+    let user = validator.validate(&answer.session_token).await.unwrap();
+    assert_eq!(user.session_id, answer.session_id);
+    assert_eq!(user.user, "dummy");
+    assert_eq!(user.scopes, ["invoices:read"]);
+    assert!(user.has_role("admin"));
+
+    let mut request = Request::new(());
+    request.extensions_mut().insert(user);
+    assert!(require_scopes(&request, &["invoices:read"]).is_ok());
+    assert_eq!(authenticated_user(&request).unwrap().user, "dummy");
+    let status = require_scopes(&request, &["invoices:read", "invoices:write"]).unwrap_err();
+    assert_eq!(status.code(), Code::PermissionDenied);
+    assert_eq!(status.message(), "session is missing scope invoices:write");
+
+    // tampering with the claims breaks the signature
+    let (claims, signature) = answer.session_token.split_once('.').unwrap();
+    let mut tampered = claims.to_string();
+    tampered.replace_range(..2, if &claims[..2] == "7b" { "5b" } else { "7b" });
+    let status = validator
+        .validate_token(&format!("{}.{}", tampered, signature))
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::Unauthenticated);
+
+    // other services and unauthenticated requests are turned away
+    let admin = validator.clone().with_audience("admin");
+    let status = admin.validate(&answer.session_token).await.unwrap_err();
+    assert_eq!(status.code(), Code::PermissionDenied);
+    assert!(authenticated_user(&Request::new(())).is_err());
+    // without with_verifier or with_store session ids are not accepted
+    let status = validator.validate(&answer.session_id).await.unwrap_err();
+    assert_eq!(status.code(), Code::Unauthenticated);
+
+    clock.advance(Duration::from_secs(60));
+    let status = validator
+        .validate_token(&answer.session_token)
+        .await
+        .unwrap_err();
+    assert_eq!(status.message(), "session token has expired");
+}
+
+#[tokio::test]
+async fn test_token_signatures_are_checked_on_the_crypto_pool() {
+    let clock = Arc::new(MockClock::default());
+    let auth_server = setup(clock.clone());
+    let answer = login(&auth_server, &[], false).await;
+
+    // one worker and no queue, kept busy
+    let pool = CryptoPool::new(1, 0);
+    let (release, blocked) = std::sync::mpsc::channel::<()>();
+    let busy = tokio::spawn({
+        let pool = pool.clone();
+        async move { pool.run(move || blocked.recv()).await }
+    });
+    tokio::task::yield_now().await;
+
+    let validator = SessionValidator::new()
+        .with_verifier_key(auth_server.verifier_key().clone())
+        .unwrap()
+        .with_clock(clock)
+        .with_crypto_pool(pool);
+    let status = validator
+        .validate_token(&answer.session_token)
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::ResourceExhausted);
+
+    release.send(()).unwrap();
+    busy.await.unwrap().unwrap().unwrap();
+    assert!(validator
+        .validate_token(&answer.session_token)
+        .await
+        .is_ok());
+}
+
+#[test]
+fn test_verifier_key_outside_group_rejected() {
+    let p = PublicParams::p();
+    for key in [BigInt::from(1), p - 1u32, p.clone()] {
+        assert!(SessionValidator::new().with_verifier_key(key).is_err());
+    }
+}
+
+#[tokio::test]
+async fn test_no_token_for_key_exchange_sessions() {
+    let auth_server = setup(Arc::new(MockClock::default()));
+    let answer = login(&auth_server, &[], true).await;
+    assert!(!answer.key_id.is_empty());
+    assert!(answer.session_token.is_empty());
+
+    // "{}" with a made up signature
+    let validator = SessionValidator::new()
+        .with_verifier_key(auth_server.verifier_key().clone())
+        .unwrap();
+    let status = validator.validate_token("7b7d.1.1").await.unwrap_err();
+    assert_eq!(status.message(), "session token signature is invalid");
+
+    // nor does the verifier vouch for its id, requests need the session key
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(
+        Server::builder()
+            .add_service(BlindAuthServer::new(auth_server))
+            .serve_with_incoming(TcpListenerStream::new(listener)),
+    );
+    let client = BlindAuthClient::connect(url).await.unwrap();
+    let validator = SessionValidator::new().with_verifier(client);
+    let status = validator.validate(&answer.session_id).await.unwrap_err();
+    assert_eq!(status.code(), Code::Unauthenticated);
+    assert_eq!(status.message(), "session is bound to a key or channel");
+}
+
+#[tokio::test]
+async fn test_layer_checks_session_ids_with_verifier() {
+    let clock = Arc::new(MockClock::default());
+    let auth_server = setup(clock.clone());
+    let store = auth_server.store.clone();
+    let answer = login(&auth_server, &SCOPES, false).await;
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(
+        Server::builder()
+            .add_service(BlindAuthServer::new(auth_server))
+            .serve_with_incoming(TcpListenerStream::new(listener)),
+    );
+    let client = BlindAuthClient::connect(url).await.unwrap();
+    let validator = SessionValidator::new()
+        .with_verifier(client)
+        .with_clock(clock);
+
+    // echoes the user the layer found
+    let echo = tower::service_fn(|request: http::Request<()>| async move {
+        let user = request.extensions().get::<AuthenticatedUser>().cloned();
+        Ok::<_, Infallible>(http::Response::new(user.map(|user| user.user)))
+    });
+    let mut service = SessionLayer::new(validator.clone()).layer(echo);
+    let request = |credential: &str| {
+        http::Request::builder()
+            .header("authorization", format!("Bearer {}", credential))
+            .body(())
+            .unwrap()
+    };
+
+    let response = service
+        .ready()
+        .await
+        .unwrap()
+        .call(request(&answer.session_id))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), http::StatusCode::OK);
+    assert_eq!(response.into_body().as_deref(), Some("dummy"));
+
+    // the cached answer outlives the session, a new validator asks again
+    store.remove_session(&answer.session_id);
+    let user = validator.validate(&answer.session_id).await.unwrap();
+    assert_eq!(user.scopes, SCOPES);
+    let mut service =
+        SessionLayer::new(validator.with_cache_ttl(Duration::from_secs(30))).layer(echo);
+    let response = service
+        .ready()
+        .await
+        .unwrap()
+        .call(request(&answer.session_id))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), http::StatusCode::UNAUTHORIZED);
+
+    // gRPC callers get a status instead
+    let grpc = http::Request::builder()
+        .header("content-type", "application/grpc")
+        .body(())
+        .unwrap();
+    let response = service.ready().await.unwrap().call(grpc).await.unwrap();
+    assert_eq!(response.status(), http::StatusCode::OK);
+    assert_eq!(response.headers()["grpc-status"], "16");
+}
@@ -0,0 +1,49 @@
+#![cfg(feature = "net")]
+
+use common_lib::pool::{CryptoPool, PoolError};
+use std::sync::mpsc;
+use std::time::Duration;
+
+#[tokio::test]
+async fn test_pool_runs_jobs() {
+    let pool = CryptoPool::new(2, 0);
+
+    let (a, b) = tokio::join!(pool.run(|| 1 + 1), pool.run(|| 2 + 2));
+
+    assert_eq!(a, Ok(2));
+    assert_eq!(b, Ok(4));
+}
+
+#[tokio::test]
+async fn test_pool_rejects_when_full() {
+    let pool = CryptoPool::new(1, 1);
+    let (release, wait) = mpsc::channel::<()>();
+
+    // occupy the only worker
+    let busy = tokio::spawn({
+        let pool = pool.clone();
+        async move { pool.run(move || wait.recv().unwrap()).await }
+    });
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    // one job fits in the queue, the next is refused
+    let queued = tokio::spawn({
+        let pool = pool.clone();
+        async move { pool.run(|| "queued").await }
+    });
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(pool.run(|| "rejected").await, Err(PoolError::Exhausted));
+
+    release.send(()).unwrap();
+    assert_eq!(busy.await.unwrap(), Ok(()));
+    assert_eq!(queued.await.unwrap(), Ok("queued"));
+
+    // capacity is released once jobs finish
+    assert_eq!(pool.run(|| "again").await, Ok("again"));
+}
+
+#[tokio::test]
+async fn test_pool_exhausted_maps_to_resource_exhausted() {
+    let status: tonic::Status = PoolError::Exhausted.into();
+    assert_eq!(status.code(), tonic::Code::ResourceExhausted);
+}
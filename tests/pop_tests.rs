@@ -0,0 +1,379 @@
+#![cfg(feature = "net")]
+
+use common_lib::blind_auth_api::blind_auth_server::BlindAuth;
+use common_lib::blind_auth_api::{AuthAnswerRequest, AuthChallengeRequest, RegisterRequest};
+use common_lib::clock::{Clock, MockClock};
+use common_lib::gen_params;
+use common_lib::pool::CryptoPool;
+use common_lib::pop::{authorize, mac_request, sign_request, SessionInterceptor, MAX_CLOCK_SKEW};
+use common_lib::prover::{
+    compute_auth_secret, derive_session_key, verify_challenge_signature, ChallengeCommitment,
+};
+use common_lib::rng::SeededRng;
+use common_lib::schnorr::KeyPair;
+use common_lib::session_key::SessionKey;
+use common_lib::store::models::{User, UserStatus};
+use common_lib::store::store::DataStore;
+use common_lib::verifier::AuthServer;
+use num_bigint::BigInt;
+use num_traits::Num;
+use std::sync::Arc;
+use tonic::service::Interceptor;
+use tonic::{Code, Request, Status};
+
+const METHOD: &str = "/app.Accounts/Transfer";
+
+fn setup() -> (AuthServer, Arc<MockClock>) {
+    let clock = Arc::new(MockClock::default());
+    let auth_server = AuthServer::new(DataStore::with_clock(clock.clone()));
+    auth_server.store.insert_user(User {
+        id: String::from("dummy"),
+        y1: BigInt::from_str_radix(&gen_params(&BigInt::from(12345)).0, 16).unwrap(),
+        y2: BigInt::from_str_radix(&gen_params(&BigInt::from(12345)).1, 16).unwrap(),
+        status: UserStatus::Active,
+        ..Default::default()
+    });
+    (auth_server, clock)
+}
+
+// Logs in with k and a session bound to session_key, returns the session id
+async fn login(auth_server: &AuthServer, k: u64, session_key: Option<&KeyPair>) -> String {
+    let (r1, r2) = gen_params(&BigInt::from(k));
+    let challenge = auth_server
+        .create_authentication_challenge(Request::new(AuthChallengeRequest {
+            user: String::from("dummy"),
+            r1,
+            r2,
+            key_exchange: false,
+            session_public_key: session_key
+                .map(|key| key.public.to_str_radix(16))
+                .unwrap_or_default(),
+            channel_binding: false,
+            audience: String::new(),
+            scopes: vec![],
+            commit_id: String::new(),
+            puzzle: None,
+            puzzle_solution: 0,
+        }))
+        .await
+        .unwrap()
+        .into_inner();
+    let c = BigInt::from_str_radix(&challenge.c, 16).unwrap();
+    auth_server
+        .verify_authentication(Request::new(AuthAnswerRequest {
+            auth_id: challenge.auth_id,
+            s: compute_auth_secret(c, BigInt::from(k), BigInt::from(12345)).to_str_radix(16),
+        }))
+        .await
+        .unwrap()
+        .into_inner()
+        .session_id
+}
+
+fn signed(
+    session_id: &str,
+    key: &KeyPair,
+    clock: &MockClock,
+    amount: &str,
+) -> Request<RegisterRequest> {
+    let mut request = Request::new(body(amount));
+    sign_request(
+        &mut request,
+        session_id,
+        METHOD,
+        key,
+        &SeededRng::new(1),
+        clock.now(),
+    )
+    .unwrap();
+    request
+}
+
+// Any prost message works as the body, reuse one from the api
+fn body(amount: &str) -> RegisterRequest {
+    RegisterRequest {
+        user: String::from("dummy"),
+        y1: amount.to_string(),
+        y2: String::new(),
+    }
+}
+
+// Runs the interceptor the way tonic does, on the metadata only
+#[allow(clippy::result_large_err)]
+fn intercept<T: Clone>(store: &DataStore, request: &Request<T>) -> Result<Request<T>, Status> {
+    let mut bare = Request::new(());
+    *bare.metadata_mut() = request.metadata().clone();
+    let (metadata, extensions, _) = SessionInterceptor::new(store.clone())
+        .call(bare)?
+        .into_parts();
+    Ok(Request::from_parts(
+        metadata,
+        extensions,
+        request.get_ref().clone(),
+    ))
+}
+
+// intercept followed by the authorize call of the METHOD handler
+async fn check<T: Clone + prost::Message>(
+    store: &DataStore,
+    request: &Request<T>,
+) -> Result<String, Status> {
+    let request = intercept(store, request)?;
+    Ok(authorize(&request, METHOD).await?.id.clone())
+}
+
+#[tokio::test]
+async fn test_signed_request_accepted() {
+    let (auth_server, clock) = setup();
+    let key = KeyPair::generate(&SeededRng::new(2));
+    let session_id = login(&auth_server, 42, Some(&key)).await;
+
+    let request = intercept(&auth_server.store, &signed(&session_id, &key, &clock, "10")).unwrap();
+    let session = authorize(&request, METHOD).await.unwrap();
+    assert_eq!(session.id, session_id);
+    assert_eq!(session.user_id, "dummy");
+}
+
+#[tokio::test]
+async fn test_session_id_alone_is_useless() {
+    let (auth_server, clock) = setup();
+    let key = KeyPair::generate(&SeededRng::new(2));
+    let session_id = login(&auth_server, 42, Some(&key)).await;
+
+    let thief = KeyPair::generate(&SeededRng::new(3));
+    let err = check(
+        &auth_server.store,
+        &signed(&session_id, &thief, &clock, "10"),
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(err.code(), Code::Unauthenticated);
+
+    let err = check(&auth_server.store, &Request::new(body("10")))
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), Code::Unauthenticated);
+
+    // sessions without a key cannot be used with the interceptor at all
+    let unbound = login(&auth_server, 43, None).await;
+    let err = check(&auth_server.store, &signed(&unbound, &key, &clock, "10"))
+        .await
+        .unwrap_err();
+    assert_eq!(err.message(), "session is not bound to a key");
+}
+
+#[tokio::test]
+async fn test_key_exchange_session_uses_mac() {
+    let (auth_server, clock) = setup();
+    let k = BigInt::from(42);
+    let (r1_hex, r2_hex) = gen_params(&k);
+    let response = auth_server
+        .create_authentication_challenge(Request::new(AuthChallengeRequest {
+            user: String::from("dummy"),
+            r1: r1_hex.clone(),
+            r2: r2_hex.clone(),
+            key_exchange: true,
+            ..Default::default()
+        }))
+        .await
+        .unwrap()
+        .into_inner();
+    let (r1, r2) = (
+        BigInt::from_str_radix(&r1_hex, 16).unwrap(),
+        BigInt::from_str_radix(&r2_hex, 16).unwrap(),
+    );
+    let commitment = ChallengeCommitment {
+        user: "dummy",
+        r1: &r1,
+        r2: &r2,
+        session_public_key: None,
+        channel_binding: None,
+        audience: "",
+        scopes: &[],
+    };
+    let key = derive_session_key(&k, &commitment, &response)
+        .unwrap()
+        .unwrap();
+    let c = BigInt::from_str_radix(&response.c, 16).unwrap();
+    let session_id = auth_server
+        .verify_authentication(Request::new(AuthAnswerRequest {
+            auth_id: response.auth_id,
+            s: compute_auth_secret(c, k, BigInt::from(12345)).to_str_radix(16),
+        }))
+        .await
+        .unwrap()
+        .into_inner()
+        .session_id;
+
+    let mut request = Request::new(body("10"));
+    mac_request(
+        &mut request,
+        &session_id,
+        METHOD,
+        &key,
+        &SeededRng::new(1),
+        clock.now(),
+    )
+    .unwrap();
+    assert_eq!(
+        check(&auth_server.store, &request).await.unwrap(),
+        session_id
+    );
+
+    // a MAC'd request is used up like a signed one
+    let err = check(&auth_server.store, &request).await.unwrap_err();
+    assert_eq!(err.message(), "request nonce was already used");
+
+    // and goes stale the same way
+    let mut request = Request::new(body("10"));
+    mac_request(
+        &mut request,
+        &session_id,
+        METHOD,
+        &key,
+        &SeededRng::new(2),
+        clock.now(),
+    )
+    .unwrap();
+    clock.advance(MAX_CLOCK_SKEW * 2);
+    let err = check(&auth_server.store, &request).await.unwrap_err();
+    assert_eq!(
+        err.message(),
+        "request timestamp is outside the accepted window"
+    );
+
+    // the session id with a made up MAC is rejected
+    let mut request = Request::new(body("10"));
+    mac_request(
+        &mut request,
+        &session_id,
+        METHOD,
+        &SessionKey::derive(&BigInt::from(7), &[0u8; 32]),
+        &SeededRng::new(3),
+        clock.now(),
+    )
+    .unwrap();
+    let err = check(&auth_server.store, &request).await.unwrap_err();
+    assert_eq!(err.message(), "request mac is invalid");
+}
+
+#[tokio::test]
+async fn test_replayed_request_rejected() {
+    let (auth_server, clock) = setup();
+    let key = KeyPair::generate(&SeededRng::new(2));
+    let session_id = login(&auth_server, 42, Some(&key)).await;
+
+    let request = signed(&session_id, &key, &clock, "10");
+    assert!(check(&auth_server.store, &request).await.is_ok());
+    let err = check(&auth_server.store, &request).await.unwrap_err();
+    assert_eq!(err.message(), "request nonce was already used");
+}
+
+#[tokio::test]
+async fn test_stale_request_rejected() {
+    let (auth_server, clock) = setup();
+    let key = KeyPair::generate(&SeededRng::new(2));
+    let session_id = login(&auth_server, 42, Some(&key)).await;
+
+    let request = signed(&session_id, &key, &clock, "10");
+    clock.advance(MAX_CLOCK_SKEW * 2);
+    let err = check(&auth_server.store, &request).await.unwrap_err();
+    assert_eq!(
+        err.message(),
+        "request timestamp is outside the accepted window"
+    );
+}
+
+#[tokio::test]
+async fn test_signatures_are_checked_on_the_crypto_pool() {
+    let (auth_server, clock) = setup();
+    let key = KeyPair::generate(&SeededRng::new(2));
+    let session_id = login(&auth_server, 42, Some(&key)).await;
+
+    // one worker and no queue, kept busy
+    let pool = CryptoPool::new(1, 0);
+    let (release, blocked) = std::sync::mpsc::channel::<()>();
+    let busy = tokio::spawn({
+        let pool = pool.clone();
+        async move { pool.run(move || blocked.recv()).await }
+    });
+    tokio::task::yield_now().await;
+
+    let request = signed(&session_id, &key, &clock, "10");
+    let mut bare = Request::new(());
+    *bare.metadata_mut() = request.metadata().clone();
+    let (metadata, extensions, _) = SessionInterceptor::new(auth_server.store.clone())
+        .with_crypto_pool(pool)
+        .call(bare)
+        .unwrap()
+        .into_parts();
+    let request = Request::from_parts(metadata, extensions, body("10"));
+    let err = authorize(&request, METHOD).await.map(|_| ()).unwrap_err();
+    assert_eq!(err.code(), Code::ResourceExhausted);
+
+    // the nonce was not used up, the request goes through once there is room
+    release.send(()).unwrap();
+    busy.await.unwrap().unwrap().unwrap();
+    assert_eq!(authorize(&request, METHOD).await.unwrap().id, session_id);
+}
+
+#[tokio::test]
+async fn test_signature_covers_method_and_body() {
+    let (auth_server, clock) = setup();
+    let key = KeyPair::generate(&SeededRng::new(2));
+    let session_id = login(&auth_server, 42, Some(&key)).await;
+
+    let request = intercept(&auth_server.store, &signed(&session_id, &key, &clock, "10")).unwrap();
+    assert!(authorize(&request, "/app.Accounts/Close").await.is_err());
+
+    let (metadata, extensions, _) = request.into_parts();
+    let tampered = Request::from_parts(metadata, extensions, body("10000"));
+    assert!(authorize(&tampered, METHOD).await.is_err());
+}
+
+#[tokio::test]
+async fn test_swapped_session_key_detected_by_prover() {
+    let (auth_server, _) = setup();
+    let key = KeyPair::generate(&SeededRng::new(2));
+    let attacker = KeyPair::generate(&SeededRng::new(3));
+
+    // an attacker rewrites the session key in the prover's AuthChallengeRequest
+    let k = BigInt::from(42);
+    let (r1_hex, r2_hex) = gen_params(&k);
+    let response = auth_server
+        .create_authentication_challenge(Request::new(AuthChallengeRequest {
+            user: String::from("dummy"),
+            r1: r1_hex.clone(),
+            r2: r2_hex.clone(),
+            key_exchange: false,
+            session_public_key: attacker.public.to_str_radix(16),
+            channel_binding: false,
+            audience: String::new(),
+            scopes: vec![],
+            commit_id: String::new(),
+            puzzle: None,
+            puzzle_solution: 0,
+        }))
+        .await
+        .unwrap()
+        .into_inner();
+
+    // the prover checks the signed transcript against the key it sent and
+    // does not answer
+    let r1 = BigInt::from_str_radix(&r1_hex, 16).unwrap();
+    let r2 = BigInt::from_str_radix(&r2_hex, 16).unwrap();
+    let commitment = ChallengeCommitment {
+        user: "dummy",
+        r1: &r1,
+        r2: &r2,
+        session_public_key: Some(&key.public),
+        channel_binding: None,
+        audience: "",
+        scopes: &[],
+    };
+    assert!(!verify_challenge_signature(
+        auth_server.verifier_key(),
+        &commitment,
+        &response
+    ));
+}
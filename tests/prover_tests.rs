@@ -0,0 +1,182 @@
+use common_lib::prover::{
+    compute_auth_secret, derive_nonce, derive_secret_from_password, KnownVerifiers, PinError,
+};
+use common_lib::public_params::PublicParams;
+use common_lib::{gen_params, random_in_range};
+use num_bigint::BigInt;
+use num_traits::Zero;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+
+#[test]
+fn test_random_in_range_bounds() {
+    let mut rng = StdRng::seed_from_u64(7);
+    let min = BigInt::from(10);
+    let max = BigInt::from(17);
+    let mut seen = [false; 7];
+
+    for _ in 0..500 {
+        let v = random_in_range(&mut rng, &min, &max);
+        assert!(v >= min && v < max);
+        seen[usize::try_from(v - &min).unwrap()] = true;
+    }
+    assert!(seen.iter().all(|s| *s));
+}
+
+#[test]
+fn test_random_in_range_full_group() {
+    let mut rng = StdRng::seed_from_u64(7);
+    let q = PublicParams::q();
+
+    for _ in 0..20 {
+        let v = random_in_range(&mut rng, &BigInt::zero(), q);
+        assert!(v >= BigInt::zero() && v < *q);
+    }
+}
+
+#[test]
+fn test_derive_nonce_deterministic() {
+    let x = BigInt::from(12345);
+    let entropy = [1u8; 32];
+
+    let k = derive_nonce(&x, "alice", "http://verifier:50051", &entropy);
+    assert_eq!(
+        k,
+        derive_nonce(&x, "alice", "http://verifier:50051", &entropy)
+    );
+    assert!(k > BigInt::zero() && k < *PublicParams::q());
+
+    // every input contributes to k
+    assert_ne!(
+        k,
+        derive_nonce(&x, "bob", "http://verifier:50051", &entropy)
+    );
+    assert_ne!(k, derive_nonce(&x, "alice", "http://other:50051", &entropy));
+    assert_ne!(
+        k,
+        derive_nonce(&x, "alice", "http://verifier:50051", &[2u8; 32])
+    );
+    assert_ne!(
+        k,
+        derive_nonce(&BigInt::from(1), "alice", "http://verifier:50051", &entropy)
+    );
+    // fields are length prefixed so moving bytes between them changes k
+    assert_ne!(
+        derive_nonce(&x, "ab", "c", &entropy),
+        derive_nonce(&x, "a", "bc", &entropy)
+    );
+}
+
+#[test]
+fn test_derive_nonce_known_answer() {
+    // pinned so the constant time and the num-bigint paths stay in agreement
+    let k = derive_nonce(
+        &BigInt::from(12345),
+        "alice",
+        "http://verifier:50051",
+        &[1u8; 32],
+    );
+    assert!(k
+        .to_str_radix(16)
+        .starts_with("162dd61d063f2868c65e91ae5ef0f284becaf62b"));
+    // x is reduced mod q before it is hashed
+    let x = PublicParams::q() + BigInt::from(12345);
+    assert_eq!(
+        derive_nonce(&x, "alice", "http://verifier:50051", &[1u8; 32]),
+        k
+    );
+}
+
+#[test]
+fn test_password_secret_known_answer() {
+    // checked against Python's hashlib.pbkdf2_hmac with the same expansion
+    let x = derive_secret_from_password("hunter2", "alice", 1000);
+    assert!(x
+        .to_str_radix(16)
+        .starts_with("4c21718905c4000b91f7e5ad74508332bf8f4ce40b46ea6cbd63f12e75"));
+    assert!(x < *PublicParams::q());
+    // the user id salts it
+    assert_ne!(derive_secret_from_password("hunter2", "bob", 1000), x);
+    assert_ne!(derive_secret_from_password("hunter3", "alice", 1000), x);
+}
+
+#[test]
+fn test_derived_nonce_answers_challenge() {
+    let x = BigInt::from(12345);
+    let k = derive_nonce(&x, "alice", "http://verifier:50051", &[0u8; 32]);
+    let c = BigInt::from(987654321);
+    let p = PublicParams::p();
+
+    let s = compute_auth_secret(c.clone(), k.clone(), x.clone());
+
+    // g^s * y1^c == g^k
+    let y1 = PublicParams::g().modpow(&x, p);
+    let r1 = PublicParams::g().modpow(&k, p);
+    assert_eq!((PublicParams::g().modpow(&s, p) * y1.modpow(&c, p)) % p, r1);
+    assert_eq!(gen_params(&k).0, r1.to_str_radix(16));
+}
+
+#[cfg(feature = "constant-time")]
+#[test]
+fn test_constant_time_backend_matches_num_bigint() {
+    use common_lib::ct;
+
+    let mut rng = StdRng::seed_from_u64(3);
+    let p = PublicParams::p();
+    let q = PublicParams::q();
+
+    for _ in 0..4 {
+        let k = random_in_range(&mut rng, &BigInt::zero(), q);
+        let x = random_in_range(&mut rng, &BigInt::zero(), q);
+        let c = random_in_range(&mut rng, &BigInt::zero(), q);
+
+        assert_eq!(
+            ct::pow_mod_p(PublicParams::g(), &k),
+            PublicParams::g().modpow(&k, p)
+        );
+        let mut expected = (&k - &c * &x) % q;
+        if expected < BigInt::zero() {
+            expected += q;
+        }
+        assert_eq!(ct::auth_secret(&c, &k, &x), expected);
+        assert_eq!(ct::reduce_mod_q(&(&x + q)), x);
+    }
+}
+
+#[test]
+fn test_auth_secret_reduces_public_challenge() {
+    let q = PublicParams::q();
+    let (c, k, x) = (BigInt::from(5), BigInt::from(3), BigInt::from(7));
+
+    // c is reduced mod q, including values from a misbehaving verifier
+    let expected = compute_auth_secret(c.clone(), k.clone(), x.clone());
+    assert_eq!(
+        compute_auth_secret(&c + q * BigInt::from(1u64 << 40), k.clone(), x.clone()),
+        expected
+    );
+    assert_eq!(compute_auth_secret(&c - q, k, x), expected);
+}
+
+#[test]
+fn test_known_verifiers_pins_on_first_use() {
+    let path = std::env::temp_dir().join(format!("known_verifiers_{}", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let known = KnownVerifiers::new(path.clone());
+    let key = BigInt::from(1234);
+
+    assert!(known.get("http://a:50051").unwrap().is_none());
+    assert!(known.check_or_pin("http://a:50051", &key).unwrap());
+    assert!(!known.check_or_pin("http://a:50051", &key).unwrap());
+    assert_eq!(known.get("http://a:50051").unwrap(), Some(key.clone()));
+
+    // a different key for a pinned server is refused, other servers are independent
+    assert!(matches!(
+        known.check_or_pin("http://a:50051", &BigInt::from(999)),
+        Err(PinError::Mismatch { .. })
+    ));
+    assert!(known
+        .check_or_pin("http://b:50051", &BigInt::from(999))
+        .unwrap());
+
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,218 @@
+#![cfg(feature = "net")]
+
+use common_lib::blind_auth_api::blind_auth_server::BlindAuth;
+use common_lib::blind_auth_api::{AuthChallengeRequest, AuthChallengeResponse};
+use common_lib::clock::{Clock, MockClock};
+use common_lib::gen_params;
+use common_lib::puzzle::{self, PuzzleConfig, PuzzleGuard, PUZZLE_TTL};
+use common_lib::rng::SeededRng;
+use common_lib::store::models::{User, UserStatus};
+use common_lib::store::store::DataStore;
+use common_lib::verifier::AuthServer;
+use num_bigint::BigInt;
+use num_traits::Num;
+use std::sync::Arc;
+use std::time::Duration;
+use tonic::{Code, Request, Status};
+
+fn config() -> PuzzleConfig {
+    PuzzleConfig {
+        peer_rate: 2,
+        global_rate: 100,
+        window: Duration::from_secs(10),
+        base_difficulty: 4,
+        max_difficulty: 6,
+    }
+}
+
+fn setup() -> (AuthServer, Arc<MockClock>) {
+    let clock = Arc::new(MockClock::default());
+    let auth_server = AuthServer::new(DataStore::with_clock(clock.clone())).with_puzzles(config());
+    let (y1, y2) = gen_params(&BigInt::from(12345));
+    auth_server.store.insert_user(User {
+        id: String::from("dummy"),
+        y1: BigInt::from_str_radix(&y1, 16).unwrap(),
+        y2: BigInt::from_str_radix(&y2, 16).unwrap(),
+        status: UserStatus::Active,
+        ..Default::default()
+    });
+    (auth_server, clock)
+}
+
+fn challenge_request(k: u64) -> AuthChallengeRequest {
+    let (r1, r2) = gen_params(&BigInt::from(k));
+    AuthChallengeRequest {
+        user: String::from("dummy"),
+        r1,
+        r2,
+        ..Default::default()
+    }
+}
+
+async fn request(
+    auth_server: &AuthServer,
+    req: AuthChallengeRequest,
+) -> Result<AuthChallengeResponse, Status> {
+    Ok(auth_server
+        .create_authentication_challenge(Request::new(req))
+        .await?
+        .into_inner())
+}
+
+#[tokio::test]
+async fn test_puzzle_only_above_rate() {
+    let (auth_server, _) = setup();
+
+    for k in 0..2 {
+        let response = request(&auth_server, challenge_request(100 + k))
+            .await
+            .unwrap();
+        assert!(response.puzzle.is_none());
+        assert!(!response.auth_id.is_empty());
+    }
+
+    let response = request(&auth_server, challenge_request(102)).await.unwrap();
+    assert!(response.auth_id.is_empty());
+    assert_eq!(response.puzzle.unwrap().difficulty, 4);
+}
+
+#[tokio::test]
+async fn test_solved_puzzle_issues_challenge() {
+    let (auth_server, _) = setup();
+    for k in 0..2 {
+        request(&auth_server, challenge_request(100 + k))
+            .await
+            .unwrap();
+    }
+
+    let puzzle = request(&auth_server, challenge_request(102))
+        .await
+        .unwrap()
+        .puzzle
+        .unwrap();
+    let solution = puzzle::solve("dummy", &puzzle);
+    let solved = AuthChallengeRequest {
+        puzzle: Some(puzzle),
+        puzzle_solution: solution,
+        ..challenge_request(102)
+    };
+    let response = request(&auth_server, solved.clone()).await.unwrap();
+    assert!(response.puzzle.is_none());
+    assert!(!response.auth_id.is_empty());
+
+    // a solution buys a single challenge
+    let err = request(
+        &auth_server,
+        AuthChallengeRequest {
+            r1: challenge_request(104).r1,
+            r2: challenge_request(104).r2,
+            ..solved
+        },
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(err.code(), Code::PermissionDenied);
+    assert_eq!(err.message(), "puzzle was already used");
+}
+
+#[tokio::test]
+async fn test_forged_puzzle_rejected() {
+    let (auth_server, _) = setup();
+    for k in 0..2 {
+        request(&auth_server, challenge_request(100 + k))
+            .await
+            .unwrap();
+    }
+    let puzzle = request(&auth_server, challenge_request(102))
+        .await
+        .unwrap()
+        .puzzle
+        .unwrap();
+
+    // lowering the difficulty breaks the mac
+    let mut easier = puzzle.clone();
+    easier.difficulty = 0;
+    let err = request(
+        &auth_server,
+        AuthChallengeRequest {
+            puzzle: Some(easier),
+            puzzle_solution: 0,
+            ..challenge_request(102)
+        },
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(err.message(), "puzzle solution is invalid");
+
+    // the puzzle is tied to the user it was issued for
+    let solution = (0..)
+        .find(|&solution| {
+            puzzle::is_solution("mallory", &puzzle, solution)
+                && !puzzle::is_solution("dummy", &puzzle, solution)
+        })
+        .unwrap();
+    let err = request(
+        &auth_server,
+        AuthChallengeRequest {
+            puzzle: Some(puzzle),
+            puzzle_solution: solution,
+            ..challenge_request(102)
+        },
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(err.message(), "puzzle solution is invalid");
+}
+
+#[tokio::test]
+async fn test_expired_puzzle_replaced() {
+    let (auth_server, clock) = setup();
+    for k in 0..2 {
+        request(&auth_server, challenge_request(100 + k))
+            .await
+            .unwrap();
+    }
+    let puzzle = request(&auth_server, challenge_request(102))
+        .await
+        .unwrap()
+        .puzzle
+        .unwrap();
+    let solution = puzzle::solve("dummy", &puzzle);
+
+    // keep the peer over its rate in the new window
+    clock.advance(PUZZLE_TTL);
+    for k in 0..2 {
+        request(&auth_server, challenge_request(200 + k))
+            .await
+            .unwrap();
+    }
+    let response = request(
+        &auth_server,
+        AuthChallengeRequest {
+            puzzle: Some(puzzle.clone()),
+            puzzle_solution: solution,
+            ..challenge_request(102)
+        },
+    )
+    .await
+    .unwrap();
+    let fresh = response.puzzle.unwrap();
+    assert_ne!(fresh.nonce, puzzle.nonce);
+}
+
+#[test]
+fn test_difficulty_grows_with_load() {
+    let clock = MockClock::default();
+    let guard = PuzzleGuard::new(config(), &SeededRng::new(1));
+    let difficulties: Vec<u32> = (0..16).map(|_| guard.record("peer", clock.now())).collect();
+    // 2 free requests, then base difficulty below 2x the rate, one more bit
+    // per doubling, capped at max_difficulty
+    assert_eq!(difficulties[..3], [0, 0, 4]);
+    assert_eq!(difficulties[3..7], [5, 5, 5, 5]);
+    assert!(difficulties[7..].iter().all(|&d| d == 6));
+
+    // other peers and later windows start over
+    assert_eq!(guard.record("other", clock.now()), 0);
+    clock.advance(Duration::from_secs(10));
+    assert_eq!(guard.record("peer", clock.now()), 0);
+}
@@ -0,0 +1,52 @@
+use common_lib::public_params::PublicParams;
+use common_lib::rng::SeededRng;
+use common_lib::schnorr::{verify, KeyPair, Signature};
+use num_bigint::BigInt;
+
+#[test]
+fn test_sign_verify_round_trip() {
+    let rng = SeededRng::new(1);
+    let key = KeyPair::generate(&rng);
+    let signature = key.sign(b"message", &rng);
+
+    assert!(verify(&key.public, b"message", &signature));
+}
+
+#[test]
+fn test_tampered_message_rejected() {
+    let rng = SeededRng::new(2);
+    let key = KeyPair::generate(&rng);
+    let signature = key.sign(b"message", &rng);
+
+    assert!(!verify(&key.public, b"messagf", &signature));
+}
+
+#[test]
+fn test_wrong_key_rejected() {
+    let rng = SeededRng::new(3);
+    let key = KeyPair::generate(&rng);
+    let other = KeyPair::generate(&rng);
+    let signature = key.sign(b"message", &rng);
+
+    assert!(!verify(&other.public, b"message", &signature));
+}
+
+#[test]
+fn test_out_of_range_signature_rejected() {
+    let rng = SeededRng::new(4);
+    let key = KeyPair::generate(&rng);
+    let signature = key.sign(b"message", &rng);
+
+    // s + q is the same exponent of g but must not be accepted as a second encoding
+    let malleated = Signature {
+        e: signature.e.clone(),
+        s: &signature.s + PublicParams::q(),
+    };
+    assert!(!verify(&key.public, b"message", &malleated));
+
+    let negative = Signature {
+        e: signature.e,
+        s: BigInt::from(-1),
+    };
+    assert!(!verify(&key.public, b"message", &negative));
+}
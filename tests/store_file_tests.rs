@@ -0,0 +1,118 @@
+#![cfg(feature = "net")]
+
+use common_lib::gen_params;
+use common_lib::store::file::{self, UserRecord};
+use common_lib::store::models::{User, UserStatus};
+use common_lib::store::store::{DataStore, USER_FILE_DELAY};
+use num_bigint::BigInt;
+use num_traits::Num;
+use std::fs;
+use std::path::PathBuf;
+use std::thread;
+
+// Fresh path under the temp dir, removed when dropped
+struct TempFile(PathBuf);
+
+impl TempFile {
+    fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!(
+            "blind-auth-{}-{}-{}.json",
+            name,
+            std::process::id(),
+            rand::random::<u64>()
+        ));
+        TempFile(path)
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+fn user(id: &str, x: u64) -> User {
+    let (y1, y2) = gen_params(&BigInt::from(x));
+    User {
+        id: id.to_string(),
+        y1: BigInt::from_str_radix(&y1, 16).unwrap(),
+        y2: BigInt::from_str_radix(&y2, 16).unwrap(),
+        status: UserStatus::Active,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_save_and_load() {
+    let path = TempFile::new("save");
+    assert!(file::load(&path.0).unwrap().is_empty());
+
+    let mut bob = user("bob", 2);
+    bob.status = UserStatus::Disabled;
+    file::save(&path.0, &[user("alice", 1), bob]).unwrap();
+
+    let users = file::load(&path.0).unwrap();
+    assert_eq!(users.len(), 2);
+    assert_eq!(users[0].y1, user("alice", 1).y1);
+    assert_eq!(users[1].status, UserStatus::Disabled);
+}
+
+#[test]
+fn test_invalid_records_are_rejected() {
+    let mut record = UserRecord::from(&user("alice", 1));
+    record.y1 = "1".to_string();
+    assert!(User::try_from(&record).is_err());
+
+    let path = TempFile::new("invalid");
+    fs::write(&path.0, file::to_json(&[record]).unwrap()).unwrap();
+    assert!(file::load(&path.0).is_err());
+
+    let json = r#"{"version": 2, "users": []}"#;
+    assert!(file::from_json(json).is_err());
+}
+
+#[tokio::test]
+async fn test_store_keeps_user_file_up_to_date() {
+    let path = TempFile::new("store");
+    let store = DataStore::new().with_user_file(path.0.clone()).unwrap();
+    store.insert_user(user("alice", 1));
+    store.insert_user(user("bob", 2));
+    store.set_user_status(&"alice".to_string(), UserStatus::Disabled);
+    store.remove_user(&"bob".to_string());
+    // none of them waited on the disk
+    assert!(!path.0.exists());
+    store.persist_users().await;
+
+    // a restarted verifier sees the same users
+    let store = DataStore::new().with_user_file(path.0.clone()).unwrap();
+    let alice = store.get_user(&"alice".to_string()).unwrap();
+    assert_eq!(alice.status, UserStatus::Disabled);
+    assert!(store.get_user(&"bob".to_string()).is_none());
+}
+
+#[tokio::test]
+async fn test_changes_are_written_later() {
+    let path = TempFile::new("deferred");
+    let store = DataStore::new().with_user_file(path.0.clone()).unwrap();
+    store.insert_user(user("alice", 1));
+    store.persist_users().await;
+    for now in 1..=100 {
+        store.update_user(&"alice".to_string(), |user| user.last_login_at = Some(now));
+    }
+    assert_eq!(
+        store.get_user(&"alice".to_string()).unwrap().last_login_at,
+        Some(100)
+    );
+    assert_eq!(file::load(&path.0).unwrap()[0].last_login_at, None);
+
+    // the writer thread saves the last of them
+    let mut saved = None;
+    for _ in 0..50 {
+        thread::sleep(USER_FILE_DELAY / 5);
+        saved = file::load(&path.0).unwrap()[0].last_login_at;
+        if saved.is_some() {
+            break;
+        }
+    }
+    assert_eq!(saved, Some(100));
+}
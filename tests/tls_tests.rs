@@ -0,0 +1,337 @@
+#![cfg(feature = "net")]
+
+use axum::extract::ConnectInfo;
+use axum::routing::get;
+use axum::Router;
+use common_lib::blind_auth_api::blind_auth_client::BlindAuthClient;
+use common_lib::blind_auth_api::blind_auth_server::{BlindAuth, BlindAuthServer};
+use common_lib::blind_auth_api::{AuthAnswerRequest, AuthChallengeRequest, AuthChallengeResponse};
+use common_lib::gen_params;
+use common_lib::prover::{compute_auth_secret, verify_challenge_signature, ChallengeCommitment};
+use common_lib::puzzle::PuzzleConfig;
+use common_lib::store::models::{User, UserStatus};
+use common_lib::store::store::DataStore;
+use common_lib::tls::{self, ChannelBinding};
+use common_lib::verifier::AuthServer;
+use num_bigint::BigInt;
+use num_traits::Num;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+use tokio_rustls::rustls::ServerName;
+use tokio_rustls::TlsConnector;
+use tokio_stream::StreamExt;
+use tonic::transport::{Channel, Endpoint, Server};
+use tonic::{Code, Request};
+
+struct Verifier {
+    store: DataStore,
+    verifier_key: BigInt,
+    url: String,
+    ca_pem: String,
+}
+
+// Starts a TLS verifier on a random port with a self signed localhost cert
+async fn start_verifier() -> Verifier {
+    let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+    let cert_pem = cert.serialize_pem().unwrap();
+    let key_pem = cert.serialize_private_key_pem();
+
+    let store = DataStore::new();
+    let x = BigInt::from(12345);
+    let (y1, y2) = gen_params(&x);
+    store.insert_user(User {
+        id: String::from("dummy"),
+        y1: BigInt::from_str_radix(&y1, 16).unwrap(),
+        y2: BigInt::from_str_radix(&y2, 16).unwrap(),
+        status: UserStatus::Active,
+        ..Default::default()
+    });
+    let auth_server = AuthServer::new(store.clone());
+    let verifier_key = auth_server.verifier_key().clone();
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let config = tls::server_config(cert_pem.as_bytes(), key_pem.as_bytes()).unwrap();
+    tokio::spawn(
+        Server::builder()
+            .add_service(BlindAuthServer::new(auth_server))
+            .serve_with_incoming(tls::incoming(listener, config, tls::DEFAULT_MAX_HANDSHAKES)),
+    );
+
+    Verifier {
+        store,
+        verifier_key,
+        url: format!("https://localhost:{}", port),
+        ca_pem: cert_pem,
+    }
+}
+
+async fn connect(verifier: &Verifier) -> (BlindAuthClient<Channel>, ChannelBinding) {
+    let endpoint = Endpoint::from_shared(verifier.url.clone()).unwrap();
+    let config = tls::client_config(verifier.ca_pem.as_bytes()).unwrap();
+    let (channel, binding) = tls::connect(endpoint, config).await.unwrap();
+    (BlindAuthClient::new(channel), binding)
+}
+
+async fn bound_challenge(
+    client: &mut BlindAuthClient<Channel>,
+    k: u64,
+) -> (BigInt, BigInt, AuthChallengeResponse) {
+    let (r1, r2) = gen_params(&BigInt::from(k));
+    let response = client
+        .create_authentication_challenge(AuthChallengeRequest {
+            user: String::from("dummy"),
+            r1: r1.clone(),
+            r2: r2.clone(),
+            channel_binding: true,
+            ..Default::default()
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    (
+        BigInt::from_str_radix(&r1, 16).unwrap(),
+        BigInt::from_str_radix(&r2, 16).unwrap(),
+        response,
+    )
+}
+
+fn commitment<'a>(
+    r1: &'a BigInt,
+    r2: &'a BigInt,
+    binding: &'a ChannelBinding,
+) -> ChallengeCommitment<'a> {
+    ChallengeCommitment {
+        user: "dummy",
+        r1,
+        r2,
+        session_public_key: None,
+        channel_binding: Some(binding),
+        audience: "",
+        scopes: &[],
+    }
+}
+
+#[tokio::test]
+async fn test_channel_bound_login() {
+    let verifier = start_verifier().await;
+    let (mut client, binding) = connect(&verifier).await;
+
+    let (r1, r2, response) = bound_challenge(&mut client, 42).await;
+    let commitment = commitment(&r1, &r2, &binding);
+    assert!(verify_challenge_signature(
+        &verifier.verifier_key,
+        &commitment,
+        &response
+    ));
+
+    let c = commitment.challenge(&response).unwrap();
+    let s = compute_auth_secret(c, BigInt::from(42), BigInt::from(12345));
+    let session_id = client
+        .verify_authentication(AuthAnswerRequest {
+            auth_id: response.auth_id,
+            s: s.to_str_radix(16),
+        })
+        .await
+        .unwrap()
+        .into_inner()
+        .session_id;
+
+    let session = verifier.store.get_session(&session_id).unwrap();
+    assert_eq!(session.channel_binding, Some(binding));
+}
+
+#[tokio::test]
+async fn test_relayed_transcript_rejected() {
+    let verifier = start_verifier().await;
+    let (mut relay, _) = connect(&verifier).await;
+    let (_, prover_binding) = connect(&verifier).await;
+
+    // a relay requests the challenge on its own connection, the prover sees a
+    // signature over a channel that is not its own and the c it would answer
+    // differs from the one the verifier checks
+    let (r1, r2, response) = bound_challenge(&mut relay, 42).await;
+    let commitment = commitment(&r1, &r2, &prover_binding);
+    assert!(!verify_challenge_signature(
+        &verifier.verifier_key,
+        &commitment,
+        &response
+    ));
+
+    let c = commitment.challenge(&response).unwrap();
+    let s = compute_auth_secret(c, BigInt::from(42), BigInt::from(12345));
+    let err = relay
+        .verify_authentication(AuthAnswerRequest {
+            auth_id: response.auth_id,
+            s: s.to_str_radix(16),
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), Code::PermissionDenied);
+}
+
+#[tokio::test]
+async fn test_answer_on_another_channel_rejected() {
+    let verifier = start_verifier().await;
+    let (mut first, binding) = connect(&verifier).await;
+    let (mut second, _) = connect(&verifier).await;
+
+    let (r1, r2, response) = bound_challenge(&mut first, 42).await;
+    let c = commitment(&r1, &r2, &binding).challenge(&response).unwrap();
+    let s = compute_auth_secret(c, BigInt::from(42), BigInt::from(12345));
+    let err = second
+        .verify_authentication(AuthAnswerRequest {
+            auth_id: response.auth_id,
+            s: s.to_str_radix(16),
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), Code::PermissionDenied);
+}
+
+#[tokio::test]
+async fn test_channel_binding_needs_tls() {
+    let auth_server = AuthServer::new(DataStore::new());
+    let (y1, y2) = gen_params(&BigInt::from(12345));
+    auth_server.store.insert_user(User {
+        id: String::from("dummy"),
+        y1: BigInt::from_str_radix(&y1, 16).unwrap(),
+        y2: BigInt::from_str_radix(&y2, 16).unwrap(),
+        status: UserStatus::Active,
+        ..Default::default()
+    });
+
+    let (r1, r2) = gen_params(&BigInt::from(42));
+    let err = auth_server
+        .create_authentication_challenge(Request::new(AuthChallengeRequest {
+            user: String::from("dummy"),
+            r1,
+            r2,
+            channel_binding: true,
+            ..Default::default()
+        }))
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), Code::FailedPrecondition);
+}
+
+#[tokio::test]
+async fn test_http_over_tls_sees_client_address() {
+    let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+    let cert_pem = cert.serialize_pem().unwrap();
+    let config = tls::http_server_config(
+        cert_pem.as_bytes(),
+        cert.serialize_private_key_pem().as_bytes(),
+    )
+    .unwrap();
+    let router = Router::new().route(
+        "/peer",
+        get(|ConnectInfo(addr): ConnectInfo<SocketAddr>| async move { addr.to_string() }),
+    );
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(tls::serve_http(
+        listener,
+        config,
+        router,
+        tls::DEFAULT_MAX_HANDSHAKES,
+    ));
+
+    let tcp = TcpStream::connect(addr).await.unwrap();
+    let local = tcp.local_addr().unwrap();
+    let connector = TlsConnector::from(tls::client_config(cert_pem.as_bytes()).unwrap());
+    let server_name = ServerName::try_from("localhost").unwrap();
+    let stream = connector.connect(server_name, tcp).await.unwrap();
+    let (mut sender, connection) = hyper::client::conn::Builder::new()
+        .http2_only(true)
+        .handshake(stream)
+        .await
+        .unwrap();
+    tokio::spawn(connection);
+
+    let request = hyper::Request::get(format!("https://localhost:{}/peer", addr.port()))
+        .body(hyper::Body::empty())
+        .unwrap();
+    let response = sender.send_request(request).await.unwrap();
+    assert_eq!(response.status(), hyper::StatusCode::OK);
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    assert_eq!(body, local.to_string());
+}
+
+#[tokio::test]
+async fn test_puzzles_count_tls_peers_by_address() {
+    let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+    let cert_pem = cert.serialize_pem().unwrap();
+    let config = tls::server_config(
+        cert_pem.as_bytes(),
+        cert.serialize_private_key_pem().as_bytes(),
+    )
+    .unwrap();
+    let auth_server = AuthServer::new(DataStore::new()).with_puzzles(PuzzleConfig {
+        peer_rate: 2,
+        ..Default::default()
+    });
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    tokio::spawn(
+        Server::builder()
+            .add_service(BlindAuthServer::new(auth_server))
+            .serve_with_incoming(tls::incoming(listener, config, tls::DEFAULT_MAX_HANDSHAKES)),
+    );
+    let endpoint = Endpoint::from_shared(format!("https://localhost:{}", port)).unwrap();
+    let config = tls::client_config(cert_pem.as_bytes()).unwrap();
+    let (channel, _) = tls::connect(endpoint, config).await.unwrap();
+    let mut client = BlindAuthClient::new(channel);
+
+    // a new user name for every request does not reset the count
+    let (r1, r2) = gen_params(&BigInt::from(42));
+    let mut puzzles = Vec::new();
+    for user in ["alice", "bob", "carol"] {
+        let response = client
+            .create_authentication_challenge(AuthChallengeRequest {
+                user: user.to_string(),
+                r1: r1.clone(),
+                r2: r2.clone(),
+                ..Default::default()
+            })
+            .await;
+        puzzles.push(matches!(response, Ok(response) if response.get_ref().puzzle.is_some()));
+    }
+    assert_eq!(puzzles, [false, false, true]);
+}
+
+#[tokio::test]
+async fn test_pending_handshakes_are_limited() {
+    let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+    let cert_pem = cert.serialize_pem().unwrap();
+    let config = tls::server_config(
+        cert_pem.as_bytes(),
+        cert.serialize_private_key_pem().as_bytes(),
+    )
+    .unwrap();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let mut incoming = tls::incoming(listener, config, 1);
+    let connector = TlsConnector::from(tls::client_config(cert_pem.as_bytes()).unwrap());
+    let handshake = |tcp| {
+        let connector = connector.clone();
+        tokio::spawn(async move {
+            let server_name = ServerName::try_from("localhost").unwrap();
+            connector.connect(server_name, tcp).await
+        })
+    };
+
+    // a client that never sends its hello holds the only permit
+    let stalled = TcpStream::connect(addr).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let client = handshake(TcpStream::connect(addr).await.unwrap());
+    let accepted = tokio::time::timeout(Duration::from_millis(500), incoming.next()).await;
+    assert!(accepted.is_err());
+
+    // its handshake fails once it hangs up, which lets the next one in
+    drop(stalled);
+    let accepted = tokio::time::timeout(Duration::from_secs(5), incoming.next()).await;
+    assert!(matches!(accepted, Ok(Some(Ok(_)))));
+    client.await.unwrap().unwrap();
+}
@@ -0,0 +1,156 @@
+use common_lib::blind_auth_api::{AuthAnswerRequest, AuthChallengeRequest, RegisterRequest};
+use common_lib::gen_params;
+use common_lib::public_params::PublicParams;
+use common_lib::validation::{
+    AnswerRequest, ChallengeRequest, Registration, ValidationError, MAX_AUDIENCE_LEN, MAX_SCOPES,
+    MAX_USER_ID_LEN,
+};
+use num_bigint::BigInt;
+
+#[test]
+fn test_valid_registration() {
+    let (y1, y2) = gen_params(&BigInt::from(7));
+    let request = RegisterRequest {
+        user: "alice.smith@example-1_2".to_string(),
+        y1,
+        y2,
+    };
+
+    let registration = Registration::try_from(&request).unwrap();
+
+    assert_eq!(registration.user, "alice.smith@example-1_2");
+    assert_eq!(registration.y1, BigInt::from(4).pow(7));
+}
+
+#[test]
+fn test_user_id_charset_and_length() {
+    let (y1, y2) = gen_params(&BigInt::from(7));
+    let mut request = RegisterRequest {
+        user: "bad user".to_string(),
+        y1,
+        y2,
+    };
+    assert_eq!(
+        Registration::try_from(&request).unwrap_err(),
+        ValidationError::InvalidCharacters("user")
+    );
+
+    request.user = "a".repeat(MAX_USER_ID_LEN + 1);
+    assert_eq!(
+        Registration::try_from(&request).unwrap_err(),
+        ValidationError::TooLong("user", MAX_USER_ID_LEN)
+    );
+}
+
+#[test]
+fn test_group_element_bounds() {
+    let (_, r2) = gen_params(&BigInt::from(7));
+    let p = PublicParams::p();
+
+    for r1 in [
+        "0",
+        "1",
+        &p.to_str_radix(16),
+        &(p + BigInt::from(4)).to_str_radix(16),
+    ] {
+        let request = AuthChallengeRequest {
+            user: "alice".to_string(),
+            r1: r1.to_string(),
+            r2: r2.clone(),
+            key_exchange: false,
+            session_public_key: String::new(),
+            channel_binding: false,
+            audience: String::new(),
+            scopes: vec![],
+            commit_id: String::new(),
+            puzzle: None,
+            puzzle_solution: 0,
+        };
+        assert_eq!(
+            ChallengeRequest::try_from(&request).unwrap_err(),
+            ValidationError::NotInGroup("r1")
+        );
+    }
+}
+
+#[test]
+fn test_group_element_outside_subgroup() {
+    // p = 3 (mod 4) so -1 is not a quadratic residue, and neither is -2 as 2 is
+    let (y1, _) = gen_params(&BigInt::from(7));
+    for y2 in [
+        PublicParams::p() - BigInt::from(1),
+        PublicParams::p() - BigInt::from(2),
+    ] {
+        let request = RegisterRequest {
+            user: "alice".to_string(),
+            y1: y1.clone(),
+            y2: y2.to_str_radix(16),
+        };
+        assert_eq!(
+            Registration::try_from(&request).unwrap_err(),
+            ValidationError::NotInGroup("y2")
+        );
+    }
+}
+
+#[test]
+fn test_scalar_range() {
+    let q = PublicParams::q();
+    let mut request = AuthAnswerRequest {
+        auth_id: "challengeid".to_string(),
+        s: (q - BigInt::from(1)).to_str_radix(16),
+    };
+    assert!(AnswerRequest::try_from(&request).is_ok());
+
+    request.s = "0".to_string();
+    assert!(AnswerRequest::try_from(&request).is_ok());
+
+    for s in [q.to_str_radix(16), "-1".to_string()] {
+        request.s = s;
+        assert_eq!(
+            AnswerRequest::try_from(&request).unwrap_err(),
+            ValidationError::OutOfRange("s")
+        );
+    }
+}
+
+#[test]
+fn test_audience_and_scopes() {
+    let (r1, r2) = gen_params(&BigInt::from(7));
+    let mut request = AuthChallengeRequest {
+        user: "alice".to_string(),
+        r1,
+        r2,
+        audience: "https://billing.example.com/api".to_string(),
+        scopes: vec!["invoices:read".to_string(), "invoices:write".to_string()],
+        ..Default::default()
+    };
+    let challenge = ChallengeRequest::try_from(&request).unwrap();
+    assert_eq!(challenge.audience, "https://billing.example.com/api");
+    assert_eq!(challenge.scopes, request.scopes);
+
+    request.audience = "a".repeat(MAX_AUDIENCE_LEN + 1);
+    assert_eq!(
+        ChallengeRequest::try_from(&request).unwrap_err(),
+        ValidationError::TooLong("audience", MAX_AUDIENCE_LEN)
+    );
+    request.audience = String::new();
+
+    request.scopes = vec!["invoices read".to_string()];
+    assert_eq!(
+        ChallengeRequest::try_from(&request).unwrap_err(),
+        ValidationError::InvalidCharacters("scopes")
+    );
+
+    request.scopes = vec!["admin".to_string(), "admin".to_string()];
+    assert_eq!(
+        ChallengeRequest::try_from(&request).unwrap_err(),
+        ValidationError::Duplicate("scopes")
+    );
+
+    request.scopes = (0..=MAX_SCOPES).map(|i| format!("scope{}", i)).collect();
+    assert_eq!(
+        ChallengeRequest::try_from(&request).unwrap_err(),
+        ValidationError::TooMany("scopes", MAX_SCOPES)
+    );
+}
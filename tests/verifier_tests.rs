@@ -1,23 +1,43 @@
+#![cfg(feature = "net")]
+
+use common_lib::account::AccountPolicy;
 use common_lib::blind_auth_api::blind_auth_server::BlindAuth;
-use common_lib::blind_auth_api::{AuthAnswerRequest, AuthChallengeRequest, RegisterRequest};
-use common_lib::store::models::{Challenge, User};
-use common_lib::store::store::DataStore;
-use common_lib::verifier::AuthServer;
+use common_lib::blind_auth_api::{
+    AuthAnswerBatchRequest, AuthAnswerRequest, AuthAnswerResponse, AuthChallengeRequest,
+    AuthChallengeResponse, CancelChallengeRequest, ChallengeCommitRequest, ChallengeCommitResponse,
+    ChallengeStatusRequest, PublicParamsRequest, RegisterRequest, RotateKeyRequest,
+    ValidateSessionRequest,
+};
+use common_lib::clock::{unix_seconds, Clock, MockClock};
+use common_lib::gen_params;
+use common_lib::pool::CryptoPool;
+use common_lib::prover::{
+    compute_auth_secret, derive_session_key, verify_challenge_opening, verify_challenge_signature,
+    ChallengeCommitment,
+};
+use common_lib::public_params::PublicParams;
+use common_lib::rng::{OsRngProvider, SeededRng};
+use common_lib::store::models::{Challenge, Session, User, UserStatus};
+use common_lib::store::store::{DataStore, CHALLENGE_TTL, MAX_PENDING_CHALLENGES, SESSION_TTL};
+use common_lib::verifier::{verify_batch, AuthServer};
 use num_bigint::BigInt;
-use num_traits::Num;
+use num_traits::{Num, One};
+use std::sync::Arc;
+use std::time::Duration;
 use tonic::Request;
 
 #[tokio::test]
 async fn test_register() {
     // Initialize the AuthServer and DataStore
     let store = DataStore::new();
-    let auth_server = AuthServer { store };
+    let auth_server = AuthServer::new(store);
 
     // Create a RegisterRequest for testing
+    let (y1, y2) = gen_params(&BigInt::from(12345));
     let register_request = RegisterRequest {
         user: String::from("testuser"),
-        y1: String::from("2d"),
-        y2: String::from("1f"),
+        y1,
+        y2,
     };
 
     // Call the register method
@@ -26,7 +46,7 @@ async fn test_register() {
     // Assert that the registration was successful
     assert!(response.is_ok());
     let response = response.unwrap();
-    assert_eq!(response.get_ref().success, true);
+    assert!(response.get_ref().success);
 
     // Assert that the user is stored in the DataStore
     let stored_user = auth_server.store.get_user(&String::from("testuser"));
@@ -37,21 +57,32 @@ async fn test_register() {
 async fn test_create_authentication_challenge() {
     // Initialize the AuthServer and DataStore
     let store = DataStore::new();
-    let auth_server = AuthServer { store };
+    let auth_server = AuthServer::new(store);
 
     // Insert a user into the DataStore
     let user = User {
         id: String::from("dummy"),
         y1: BigInt::from(1),
         y2: BigInt::from(3),
+        status: UserStatus::Active,
+        ..Default::default()
     };
     auth_server.store.insert_user(user.clone());
 
     // Create an AuthChallengeRequest for testing
+    let (r1, r2) = gen_params(&BigInt::from(42));
     let challenge_request = AuthChallengeRequest {
         user: user.id.clone(),
-        r1: BigInt::from(1).to_str_radix(16),
-        r2: BigInt::from(1).to_str_radix(16),
+        r1,
+        r2,
+        key_exchange: false,
+        session_public_key: String::new(),
+        channel_binding: false,
+        audience: String::new(),
+        scopes: vec![],
+        commit_id: String::new(),
+        puzzle: None,
+        puzzle_solution: 0,
     };
 
     // Call the create_authentication_challenge method
@@ -72,7 +103,7 @@ async fn test_create_authentication_challenge() {
 async fn test_verify_authentication_successful() {
     // Initialize the AuthServer and DataStore
     let store = DataStore::new();
-    let auth_server = AuthServer { store };
+    let auth_server = AuthServer::new(store);
 
     // dummy values that should pass verification.
     let r1 = "67419400b47b3283039ae352a461b106487b9ec5657c7765c5520360ccfa365b34564f7f88dbea6378cbc94da0c9ca47a5d5e78f161a7d95db47a5a46b573ea04164d3707a52400e3671e17cea29f966cb1b2ce02ea1552e3b770e97cef5941634c73c73e2ab34ec52b21dce745363f4650b14e20f190ca4708813a77625c8534470035de73d528620440a5323fd27ecc436df28b9765e0c2d82343fa7f018f8f70984d8267444ec75559c16d1750ac08de1517f5336ee99940180ebb9f3bfae84a101d93530b633c68791c70bddb90f99601a2805ec47bcc94b4c8ab55b916e918160050811fc783affccaf7a89d63579a415761ef1e39dbf8a10c4a8d6e99f";
@@ -85,6 +116,8 @@ async fn test_verify_authentication_successful() {
         id: String::from("dummy"),
         y1: BigInt::from_str_radix("40", 16).unwrap(),
         y2: BigInt::from_str_radix("2d9", 16).unwrap(),
+        status: UserStatus::Active,
+        ..Default::default()
     };
     let challenge = Challenge {
         user_id: String::from("dummy"),
@@ -92,6 +125,13 @@ async fn test_verify_authentication_successful() {
         r2: BigInt::from_str_radix(r2, 16).unwrap(),
         r1: BigInt::from_str_radix(r1, 16).unwrap(),
         id: String::from("challengeid"),
+        ephemeral: None,
+        session_public_key: None,
+        channel_binding: None,
+        audience: String::new(),
+        scopes: vec![],
+        issued_at: 0,
+        expires_at: u64::MAX,
     };
     auth_server.store.insert_user(user.clone());
     auth_server.store.insert_challenge(challenge.clone());
@@ -115,13 +155,15 @@ async fn test_verify_authentication_successful() {
 async fn test_verify_authentication_failed() {
     // Initialize the AuthServer and DataStore
     let store = DataStore::new();
-    let auth_server = AuthServer { store };
+    let auth_server = AuthServer::new(store);
 
     // Insert a user and challenge into the DataStore
     let user = User {
         id: String::from("dummy"),
         y1: BigInt::from(1),
         y2: BigInt::from(3),
+        status: UserStatus::Active,
+        ..Default::default()
     };
     auth_server.store.insert_user(user.clone());
     let challenge = Challenge {
@@ -130,6 +172,13 @@ async fn test_verify_authentication_failed() {
         r2: BigInt::from(3),
         r1: BigInt::from(4),
         id: String::from("challengeid"),
+        ephemeral: None,
+        session_public_key: None,
+        channel_binding: None,
+        audience: String::new(),
+        scopes: vec![],
+        issued_at: 0,
+        expires_at: u64::MAX,
     };
     auth_server.store.insert_challenge(challenge.clone());
 
@@ -155,7 +204,7 @@ async fn test_verify_authentication_failed() {
 #[tokio::test]
 async fn test_empty_user_register() {
     let store = DataStore::new();
-    let auth_server = AuthServer { store };
+    let auth_server = AuthServer::new(store);
 
     let request = RegisterRequest {
         user: "".to_string(),
@@ -171,7 +220,7 @@ async fn test_empty_user_register() {
 #[tokio::test]
 async fn test_invalid_y1_register() {
     let store = DataStore::new();
-    let auth_server = AuthServer { store };
+    let auth_server = AuthServer::new(store);
 
     let request = RegisterRequest {
         user: "test_user".to_string(),
@@ -187,7 +236,7 @@ async fn test_invalid_y1_register() {
 #[tokio::test]
 async fn test_invalid_y2_register() {
     let store = DataStore::new();
-    let auth_server = AuthServer { store };
+    let auth_server = AuthServer::new(store);
 
     let request = RegisterRequest {
         user: "test_user".to_string(),
@@ -203,12 +252,20 @@ async fn test_invalid_y2_register() {
 #[tokio::test]
 async fn test_empty_user_auth_challenge() {
     let store = DataStore::new();
-    let auth_server = AuthServer { store };
+    let auth_server = AuthServer::new(store);
 
     let request = AuthChallengeRequest {
         user: "".to_string(),
         r1: "1".to_string(),
         r2: "2".to_string(),
+        key_exchange: false,
+        session_public_key: String::new(),
+        channel_binding: false,
+        audience: String::new(),
+        scopes: vec![],
+        commit_id: String::new(),
+        puzzle: None,
+        puzzle_solution: 0,
     };
 
     let result = auth_server
@@ -221,12 +278,20 @@ async fn test_empty_user_auth_challenge() {
 #[tokio::test]
 async fn test_invalid_r1_auth_challenge() {
     let store = DataStore::new();
-    let auth_server = AuthServer { store };
+    let auth_server = AuthServer::new(store);
 
     let request = AuthChallengeRequest {
         user: "test_user".to_string(),
         r1: "invalid_r1".to_string(),
         r2: "2".to_string(),
+        key_exchange: false,
+        session_public_key: String::new(),
+        channel_binding: false,
+        audience: String::new(),
+        scopes: vec![],
+        commit_id: String::new(),
+        puzzle: None,
+        puzzle_solution: 0,
     };
 
     let result = auth_server
@@ -239,12 +304,20 @@ async fn test_invalid_r1_auth_challenge() {
 #[tokio::test]
 async fn test_invalid_r2_auth_challenge() {
     let store = DataStore::new();
-    let auth_server = AuthServer { store };
+    let auth_server = AuthServer::new(store);
 
     let request = AuthChallengeRequest {
         user: "test_user".to_string(),
         r1: "1".to_string(),
         r2: "invalid_r2".to_string(),
+        key_exchange: false,
+        session_public_key: String::new(),
+        channel_binding: false,
+        audience: String::new(),
+        scopes: vec![],
+        commit_id: String::new(),
+        puzzle: None,
+        puzzle_solution: 0,
     };
 
     let result = auth_server
@@ -257,7 +330,7 @@ async fn test_invalid_r2_auth_challenge() {
 #[tokio::test]
 async fn test_empty_auth_id_verify_auth() {
     let store = DataStore::new();
-    let auth_server = AuthServer { store };
+    let auth_server = AuthServer::new(store);
 
     let request = AuthAnswerRequest {
         auth_id: "".to_string(),
@@ -274,13 +347,15 @@ async fn test_empty_auth_id_verify_auth() {
 #[tokio::test]
 async fn test_invalid_s_verify_auth() {
     let store = DataStore::new();
-    let auth_server = AuthServer { store };
+    let auth_server = AuthServer::new(store);
 
     // Insert a user and challenge into the DataStore
     let user = User {
         id: String::from("dummy"),
         y1: BigInt::from(1),
         y2: BigInt::from(3),
+        status: UserStatus::Active,
+        ..Default::default()
     };
     auth_server.store.insert_user(user.clone());
     let challenge = Challenge {
@@ -289,6 +364,13 @@ async fn test_invalid_s_verify_auth() {
         r2: BigInt::from(3),
         r1: BigInt::from(4),
         id: String::from("challengeid"),
+        ephemeral: None,
+        session_public_key: None,
+        channel_binding: None,
+        audience: String::new(),
+        scopes: vec![],
+        issued_at: 0,
+        expires_at: u64::MAX,
     };
     auth_server.store.insert_challenge(challenge.clone());
 
@@ -307,11 +389,11 @@ async fn test_invalid_s_verify_auth() {
 #[tokio::test]
 async fn test_invalid_challenge_id() {
     let store = DataStore::new();
-    let auth_server = AuthServer { store };
+    let auth_server = AuthServer::new(store);
 
     let request = AuthAnswerRequest {
         auth_id: "invalid_challenge_id".to_string(),
-        s: "not_important_s".to_string(),
+        s: "1".to_string(),
     };
 
     let result = auth_server
@@ -320,3 +402,1173 @@ async fn test_invalid_challenge_id() {
 
     assert_eq!(result.unwrap_err().code(), tonic::Code::NotFound);
 }
+
+#[tokio::test]
+async fn test_reused_commitment_rejected() {
+    let store = DataStore::new();
+    let auth_server = AuthServer::new(store);
+
+    for id in ["dummy", "other"] {
+        auth_server.store.insert_user(User {
+            id: String::from(id),
+            y1: BigInt::from(1),
+            y2: BigInt::from(3),
+            status: UserStatus::Active,
+            ..Default::default()
+        });
+    }
+
+    let (r1, r2) = gen_params(&BigInt::from(42));
+    let request = |user: &str| AuthChallengeRequest {
+        user: user.to_string(),
+        r1: r1.clone(),
+        r2: r2.clone(),
+        key_exchange: false,
+        session_public_key: String::new(),
+        channel_binding: false,
+        audience: String::new(),
+        scopes: vec![],
+        commit_id: String::new(),
+        puzzle: None,
+        puzzle_solution: 0,
+    };
+
+    let first = auth_server
+        .create_authentication_challenge(Request::new(request("dummy")))
+        .await;
+    assert!(first.is_ok());
+
+    // same k for a second challenge would leak x
+    let second = auth_server
+        .create_authentication_challenge(Request::new(request("dummy")))
+        .await;
+    assert_eq!(second.unwrap_err().code(), tonic::Code::AlreadyExists);
+
+    // commitments are tracked per user
+    let other = auth_server
+        .create_authentication_challenge(Request::new(request("other")))
+        .await;
+    assert!(other.is_ok());
+}
+
+#[tokio::test]
+async fn test_challenge_signed_by_verifier_key() {
+    let auth_server = AuthServer::new(DataStore::new());
+    register_dummy(&auth_server);
+
+    let params = auth_server
+        .get_public_params(Request::new(PublicParamsRequest {}))
+        .await
+        .unwrap()
+        .into_inner();
+    let verifier_key = BigInt::from_str_radix(&params.verifier_key, 16).unwrap();
+    assert_eq!(&verifier_key, auth_server.verifier_key());
+
+    let (r1_hex, r2_hex) = gen_params(&BigInt::from(42));
+    let response = auth_server
+        .create_authentication_challenge(Request::new(AuthChallengeRequest {
+            user: String::from("dummy"),
+            r1: r1_hex.clone(),
+            r2: r2_hex.clone(),
+            key_exchange: false,
+            session_public_key: String::new(),
+            channel_binding: false,
+            audience: String::new(),
+            scopes: vec![],
+            commit_id: String::new(),
+            puzzle: None,
+            puzzle_solution: 0,
+        }))
+        .await
+        .unwrap()
+        .into_inner();
+    let r1 = BigInt::from_str_radix(&r1_hex, 16).unwrap();
+    let r2 = BigInt::from_str_radix(&r2_hex, 16).unwrap();
+
+    assert!(verify_challenge_signature(
+        &verifier_key,
+        &commitment("dummy", &r1, &r2),
+        &response
+    ));
+    // the signature binds the user and the commitments
+    assert!(!verify_challenge_signature(
+        &verifier_key,
+        &commitment("other", &r1, &r2),
+        &response
+    ));
+    assert!(!verify_challenge_signature(
+        &verifier_key,
+        &commitment("dummy", &r2, &r1),
+        &response
+    ));
+
+    // an impostor with another key cannot produce it
+    let impostor = AuthServer::new(DataStore::new());
+    assert!(!verify_challenge_signature(
+        impostor.verifier_key(),
+        &commitment("dummy", &r1, &r2),
+        &response
+    ));
+
+    let mut unsigned = response;
+    unsigned.verifier_signature = None;
+    assert!(!verify_challenge_signature(
+        &verifier_key,
+        &commitment("dummy", &r1, &r2),
+        &unsigned
+    ));
+}
+
+#[tokio::test]
+async fn test_key_exchange_derives_shared_session_key() {
+    let auth_server = AuthServer::new(DataStore::new());
+    register_dummy(&auth_server);
+
+    let k = BigInt::from(42);
+    let (r1_hex, r2_hex) = gen_params(&k);
+    let response = auth_server
+        .create_authentication_challenge(Request::new(AuthChallengeRequest {
+            user: String::from("dummy"),
+            r1: r1_hex.clone(),
+            r2: r2_hex.clone(),
+            key_exchange: true,
+            session_public_key: String::new(),
+            channel_binding: false,
+            audience: String::new(),
+            scopes: vec![],
+            commit_id: String::new(),
+            puzzle: None,
+            puzzle_solution: 0,
+        }))
+        .await
+        .unwrap()
+        .into_inner();
+    assert!(!response.ephemeral.is_empty());
+
+    // the ephemeral key is covered by the verifier signature
+    let r1 = BigInt::from_str_radix(&r1_hex, 16).unwrap();
+    let r2 = BigInt::from_str_radix(&r2_hex, 16).unwrap();
+    assert!(verify_challenge_signature(
+        auth_server.verifier_key(),
+        &commitment("dummy", &r1, &r2),
+        &response
+    ));
+    let mut swapped = response.clone();
+    swapped.ephemeral = gen_params(&BigInt::from(7)).0;
+    assert!(!verify_challenge_signature(
+        auth_server.verifier_key(),
+        &commitment("dummy", &r1, &r2),
+        &swapped
+    ));
+
+    let prover_key = derive_session_key(&k, &commitment("dummy", &r1, &r2), &response)
+        .unwrap()
+        .unwrap();
+    let c = BigInt::from_str_radix(&response.c, 16).unwrap();
+    let answer = auth_server
+        .verify_authentication(Request::new(AuthAnswerRequest {
+            auth_id: response.auth_id,
+            s: compute_auth_secret(c, k, BigInt::from(12345)).to_str_radix(16),
+        }))
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(answer.key_id, prover_key.id);
+
+    // a MAC by the prover verifies under the key the verifier stored
+    let verifier_key = auth_server
+        .store
+        .get_session(&answer.session_id)
+        .unwrap()
+        .key
+        .unwrap();
+    let tag = prover_key.mac("/app/Transfer", 1000, "n1", b"body");
+    assert!(verifier_key.verify("/app/Transfer", 1000, "n1", b"body", &tag));
+}
+
+#[tokio::test]
+async fn test_no_key_exchange_by_default() {
+    let auth_server = AuthServer::new(DataStore::new());
+    let session_id = login(&auth_server, 42).await.unwrap();
+    let session = auth_server.store.get_session(&session_id).unwrap();
+    assert!(session.key.is_none());
+}
+
+// Lets `dummy` ask for scopes at login
+fn allow_scopes(auth_server: &AuthServer, scopes: &[&str]) {
+    auth_server
+        .store
+        .update_user(&String::from("dummy"), |user| {
+            user.scopes = scopes.iter().map(|scope| scope.to_string()).collect()
+        })
+        .unwrap();
+}
+
+// Requests a challenge for `dummy` with k for audience and scopes
+async fn scoped_challenge(
+    auth_server: &AuthServer,
+    k: u64,
+    audience: &str,
+    scopes: &[&str],
+) -> AuthChallengeResponse {
+    let (r1, r2) = gen_params(&BigInt::from(k));
+    auth_server
+        .create_authentication_challenge(Request::new(AuthChallengeRequest {
+            user: String::from("dummy"),
+            r1,
+            r2,
+            key_exchange: false,
+            session_public_key: String::new(),
+            channel_binding: false,
+            audience: audience.to_string(),
+            scopes: scopes.iter().map(|scope| scope.to_string()).collect(),
+            commit_id: String::new(),
+            puzzle: None,
+            puzzle_solution: 0,
+        }))
+        .await
+        .unwrap()
+        .into_inner()
+}
+
+#[tokio::test]
+async fn test_scoped_session_carries_context() {
+    let clock = Arc::new(MockClock::default());
+    let auth_server = AuthServer::new(DataStore::with_clock(clock.clone()));
+    register_dummy(&auth_server);
+    allow_scopes(&auth_server, &["invoices:read"]);
+
+    let response = scoped_challenge(&auth_server, 42, "billing", &["invoices:read"]).await;
+    let issued_at = unix_seconds(clock.now());
+    assert_eq!(response.issued_at, issued_at);
+    assert_eq!(response.expires_at, issued_at + CHALLENGE_TTL.as_secs());
+
+    let (r1, r2) = gen_params(&BigInt::from(42));
+    let r1 = BigInt::from_str_radix(&r1, 16).unwrap();
+    let r2 = BigInt::from_str_radix(&r2, 16).unwrap();
+    let scopes = vec![String::from("invoices:read")];
+    let commitment = ChallengeCommitment {
+        audience: "billing",
+        scopes: &scopes,
+        ..commitment("dummy", &r1, &r2)
+    };
+    assert!(verify_challenge_signature(
+        auth_server.verifier_key(),
+        &commitment,
+        &response
+    ));
+
+    // the plain c no longer answers a scoped challenge
+    let c = commitment.challenge(&response).unwrap();
+    assert_ne!(c, BigInt::from_str_radix(&response.c, 16).unwrap());
+
+    let s = compute_auth_secret(c, BigInt::from(42), BigInt::from(12345));
+    let answer = auth_server
+        .verify_authentication(Request::new(AuthAnswerRequest {
+            auth_id: response.auth_id,
+            s: s.to_str_radix(16),
+        }))
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(answer.audience, "billing");
+    assert_eq!(answer.scopes, scopes);
+
+    let session = auth_server.store.get_session(&answer.session_id).unwrap();
+    assert_eq!(session.audience, "billing");
+    assert_eq!(session.scopes, scopes);
+}
+
+#[tokio::test]
+async fn test_proof_for_other_context_rejected() {
+    let auth_server = AuthServer::new(DataStore::new());
+    register_dummy(&auth_server);
+    allow_scopes(&auth_server, &["invoices:read", "users:write"]);
+
+    // a low privilege app relays the prover's commitments but asks for an
+    // admin session
+    let response = scoped_challenge(&auth_server, 42, "admin", &["users:write"]).await;
+
+    let (r1, r2) = gen_params(&BigInt::from(42));
+    let r1 = BigInt::from_str_radix(&r1, 16).unwrap();
+    let r2 = BigInt::from_str_radix(&r2, 16).unwrap();
+    let scopes = vec![String::from("invoices:read")];
+    let commitment = ChallengeCommitment {
+        audience: "billing",
+        scopes: &scopes,
+        ..commitment("dummy", &r1, &r2)
+    };
+    assert!(!verify_challenge_signature(
+        auth_server.verifier_key(),
+        &commitment,
+        &response
+    ));
+
+    // even a prover that skips the signature check answers for its own
+    // context, which does not verify for the admin one
+    let c = commitment.challenge(&response).unwrap();
+    let s = compute_auth_secret(c, BigInt::from(42), BigInt::from(12345));
+    let err = auth_server
+        .verify_authentication(Request::new(AuthAnswerRequest {
+            auth_id: response.auth_id,
+            s: s.to_str_radix(16),
+        }))
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), tonic::Code::PermissionDenied);
+}
+
+async fn commit(auth_server: &AuthServer, user: &str) -> ChallengeCommitResponse {
+    auth_server
+        .commit_challenge(Request::new(ChallengeCommitRequest {
+            user: user.to_string(),
+        }))
+        .await
+        .unwrap()
+        .into_inner()
+}
+
+// Requests a challenge for `dummy` with k using the committed c
+async fn strict_challenge(
+    auth_server: &AuthServer,
+    k: u64,
+    commit_id: &str,
+) -> Result<AuthChallengeResponse, tonic::Status> {
+    let (r1, r2) = gen_params(&BigInt::from(k));
+    Ok(auth_server
+        .create_authentication_challenge(Request::new(AuthChallengeRequest {
+            user: String::from("dummy"),
+            r1,
+            r2,
+            commit_id: commit_id.to_string(),
+            ..Default::default()
+        }))
+        .await?
+        .into_inner())
+}
+
+#[tokio::test]
+async fn test_strict_mode_login() {
+    let auth_server = AuthServer::new(DataStore::new());
+    register_dummy(&auth_server);
+
+    let committed = commit(&auth_server, "dummy").await;
+    let response = strict_challenge(&auth_server, 42, &committed.commit_id)
+        .await
+        .unwrap();
+    assert_eq!(response.issued_at, committed.issued_at);
+    assert_eq!(response.expires_at, committed.expires_at);
+    assert!(verify_challenge_opening("dummy", &committed, &response));
+
+    let c = BigInt::from_str_radix(&response.c, 16).unwrap();
+    let s = compute_auth_secret(c, BigInt::from(42), BigInt::from(12345));
+    let answer = auth_server
+        .verify_authentication(Request::new(AuthAnswerRequest {
+            auth_id: response.auth_id,
+            s: s.to_str_radix(16),
+        }))
+        .await;
+    assert!(answer.is_ok());
+}
+
+#[tokio::test]
+async fn test_changed_challenge_does_not_open_commitment() {
+    let auth_server = AuthServer::new(DataStore::new());
+    register_dummy(&auth_server);
+
+    let committed = commit(&auth_server, "dummy").await;
+    let response = strict_challenge(&auth_server, 42, &committed.commit_id)
+        .await
+        .unwrap();
+
+    // a verifier picking c after seeing r1, r2 can not open the commitment
+    let mut changed = response.clone();
+    changed.c = (BigInt::from_str_radix(&response.c, 16).unwrap() + BigInt::one()).to_str_radix(16);
+    assert!(!verify_challenge_opening("dummy", &committed, &changed));
+
+    let mut changed = response.clone();
+    changed.expires_at += 1;
+    assert!(!verify_challenge_opening("dummy", &committed, &changed));
+
+    assert!(!verify_challenge_opening("mallory", &committed, &response));
+}
+
+#[tokio::test]
+async fn test_committed_challenge_used_once() {
+    let auth_server = AuthServer::new(DataStore::new());
+    register_dummy(&auth_server);
+    auth_server.store.insert_user(User {
+        id: String::from("mallory"),
+        y1: BigInt::from(1),
+        y2: BigInt::from(3),
+        status: UserStatus::Active,
+        ..Default::default()
+    });
+
+    let committed = commit(&auth_server, "dummy").await;
+    assert!(strict_challenge(&auth_server, 42, &committed.commit_id)
+        .await
+        .is_ok());
+    let err = strict_challenge(&auth_server, 43, &committed.commit_id)
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), tonic::Code::NotFound);
+
+    // a commitment made for another user is not accepted either, and is
+    // still there for its own user afterwards
+    let committed = commit(&auth_server, "mallory").await;
+    let err = strict_challenge(&auth_server, 44, &committed.commit_id)
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), tonic::Code::NotFound);
+    let (r1, r2) = gen_params(&BigInt::from(45));
+    assert!(auth_server
+        .create_authentication_challenge(Request::new(AuthChallengeRequest {
+            user: String::from("mallory"),
+            r1,
+            r2,
+            commit_id: committed.commit_id,
+            ..Default::default()
+        }))
+        .await
+        .is_ok());
+}
+
+fn commitment<'a>(user: &'a str, r1: &'a BigInt, r2: &'a BigInt) -> ChallengeCommitment<'a> {
+    ChallengeCommitment {
+        user,
+        r1,
+        r2,
+        session_public_key: None,
+        channel_binding: None,
+        audience: "",
+        scopes: &[],
+    }
+}
+
+// Registers user x=12345 and runs a full login with k, returns the session id
+async fn login(auth_server: &AuthServer, k: u64) -> Result<String, tonic::Status> {
+    let x = BigInt::from(12345);
+    if auth_server.store.get_user(&String::from("dummy")).is_none() {
+        let (y1, y2) = gen_params(&x);
+        auth_server
+            .register(Request::new(RegisterRequest {
+                user: String::from("dummy"),
+                y1,
+                y2,
+            }))
+            .await?;
+    }
+
+    let (r1, r2) = gen_params(&BigInt::from(k));
+    let challenge = auth_server
+        .create_authentication_challenge(Request::new(AuthChallengeRequest {
+            user: String::from("dummy"),
+            r1,
+            r2,
+            key_exchange: false,
+            session_public_key: String::new(),
+            channel_binding: false,
+            audience: String::new(),
+            scopes: vec![],
+            commit_id: String::new(),
+            puzzle: None,
+            puzzle_solution: 0,
+        }))
+        .await?
+        .into_inner();
+
+    let c = BigInt::from_str_radix(&challenge.c, 16).unwrap();
+    let s = compute_auth_secret(c, BigInt::from(k), x);
+    let answer = auth_server
+        .verify_authentication(Request::new(AuthAnswerRequest {
+            auth_id: challenge.auth_id,
+            s: s.to_str_radix(16),
+        }))
+        .await?;
+    Ok(answer.into_inner().session_id)
+}
+
+#[tokio::test]
+async fn test_seeded_rng_reproduces_transcript() {
+    let mut transcripts = Vec::new();
+    for _ in 0..2 {
+        let auth_server = AuthServer::with_rng(DataStore::new(), Arc::new(SeededRng::new(7)));
+        auth_server.store.insert_user(User {
+            id: String::from("dummy"),
+            y1: BigInt::from(1),
+            y2: BigInt::from(3),
+            status: UserStatus::Active,
+            ..Default::default()
+        });
+        let (r1, r2) = gen_params(&BigInt::from(42));
+        let response = auth_server
+            .create_authentication_challenge(Request::new(AuthChallengeRequest {
+                user: String::from("dummy"),
+                r1,
+                r2,
+                key_exchange: false,
+                session_public_key: String::new(),
+                channel_binding: false,
+                audience: String::new(),
+                scopes: vec![],
+                commit_id: String::new(),
+                puzzle: None,
+                puzzle_solution: 0,
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        transcripts.push((response.auth_id, response.c));
+    }
+
+    assert_eq!(transcripts[0], transcripts[1]);
+}
+
+#[tokio::test]
+async fn test_challenge_expiry() {
+    let clock = Arc::new(MockClock::default());
+    let auth_server = AuthServer::new(DataStore::with_clock(clock.clone()));
+    auth_server.store.insert_user(User {
+        id: String::from("dummy"),
+        y1: BigInt::from(1),
+        y2: BigInt::from(3),
+        status: UserStatus::Active,
+        ..Default::default()
+    });
+
+    let (r1, r2) = gen_params(&BigInt::from(42));
+    let response = auth_server
+        .create_authentication_challenge(Request::new(AuthChallengeRequest {
+            user: String::from("dummy"),
+            r1,
+            r2,
+            key_exchange: false,
+            session_public_key: String::new(),
+            channel_binding: false,
+            audience: String::new(),
+            scopes: vec![],
+            commit_id: String::new(),
+            puzzle: None,
+            puzzle_solution: 0,
+        }))
+        .await
+        .unwrap()
+        .into_inner();
+
+    clock.advance(CHALLENGE_TTL - Duration::from_secs(1));
+    assert!(auth_server.store.get_challenge(&response.auth_id).is_some());
+
+    clock.advance(Duration::from_secs(1));
+    assert!(auth_server.store.get_challenge(&response.auth_id).is_none());
+
+    let result = auth_server
+        .verify_authentication(Request::new(AuthAnswerRequest {
+            auth_id: response.auth_id,
+            s: String::from("1"),
+        }))
+        .await;
+    assert_eq!(result.unwrap_err().code(), tonic::Code::NotFound);
+}
+
+#[tokio::test]
+async fn test_session_expiry() {
+    let clock = Arc::new(MockClock::default());
+    let auth_server = AuthServer::new(DataStore::with_clock(clock.clone()));
+
+    let session_id = login(&auth_server, 42).await.unwrap();
+    assert!(auth_server.store.get_session(&session_id).is_some());
+
+    clock.advance(SESSION_TTL - Duration::from_secs(1));
+    assert!(auth_server.store.get_session(&session_id).is_some());
+
+    clock.advance(Duration::from_secs(1));
+    assert!(auth_server.store.get_session(&session_id).is_none());
+}
+
+// Creates a challenge for `dummy` (x=12345) with nonce k and returns the
+// auth_id and a correct response s
+async fn answer_challenge(auth_server: &AuthServer, k: u64) -> (String, BigInt) {
+    let (r1, r2) = gen_params(&BigInt::from(k));
+    let challenge = auth_server
+        .create_authentication_challenge(Request::new(AuthChallengeRequest {
+            user: String::from("dummy"),
+            r1,
+            r2,
+            key_exchange: false,
+            session_public_key: String::new(),
+            channel_binding: false,
+            audience: String::new(),
+            scopes: vec![],
+            commit_id: String::new(),
+            puzzle: None,
+            puzzle_solution: 0,
+        }))
+        .await
+        .unwrap()
+        .into_inner();
+    let c = BigInt::from_str_radix(&challenge.c, 16).unwrap();
+    (
+        challenge.auth_id,
+        compute_auth_secret(c, BigInt::from(k), BigInt::from(12345)),
+    )
+}
+
+#[tokio::test]
+async fn test_challenge_status_and_cancel() {
+    let clock = Arc::new(MockClock::default());
+    let auth_server = AuthServer::new(DataStore::with_clock(clock.clone()));
+    register_dummy(&auth_server);
+
+    let (auth_id, s) = answer_challenge(&auth_server, 42).await;
+    clock.advance(Duration::from_secs(3));
+    let status = auth_server
+        .get_challenge_status(Request::new(ChallengeStatusRequest {
+            auth_id: auth_id.clone(),
+        }))
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(status.server_time, unix_seconds(clock.now()));
+    assert_eq!(
+        status.expires_at,
+        status.server_time - 3 + CHALLENGE_TTL.as_secs()
+    );
+
+    auth_server
+        .cancel_challenge(Request::new(CancelChallengeRequest {
+            auth_id: auth_id.clone(),
+        }))
+        .await
+        .unwrap();
+    let err = auth_server
+        .get_challenge_status(Request::new(ChallengeStatusRequest {
+            auth_id: auth_id.clone(),
+        }))
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), tonic::Code::NotFound);
+
+    let err = auth_server
+        .verify_authentication(Request::new(AuthAnswerRequest {
+            auth_id,
+            s: s.to_str_radix(16),
+        }))
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), tonic::Code::NotFound);
+}
+
+#[tokio::test]
+async fn test_answered_challenge_used_up() {
+    let clock = Arc::new(MockClock::default());
+    let auth_server = AuthServer::new(DataStore::with_clock(clock.clone()));
+    register_dummy(&auth_server);
+
+    let (auth_id, s) = answer_challenge(&auth_server, 42).await;
+    let answer = AuthAnswerRequest {
+        auth_id,
+        s: s.to_str_radix(16),
+    };
+    let response = auth_server
+        .verify_authentication(Request::new(answer.clone()))
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(
+        response.expires_at,
+        unix_seconds(clock.now()) + SESSION_TTL.as_secs()
+    );
+
+    // replaying the answer does not start a second session
+    let err = auth_server
+        .verify_authentication(Request::new(answer))
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), tonic::Code::NotFound);
+}
+
+#[tokio::test]
+async fn test_concurrent_answers_start_one_session() {
+    let auth_server = AuthServer::new(DataStore::new());
+    register_dummy(&auth_server);
+
+    let (auth_id, s) = answer_challenge(&auth_server, 42).await;
+    let answer = AuthAnswerRequest {
+        auth_id,
+        s: s.to_str_radix(16),
+    };
+    // both are in flight before either finishes verifying
+    let (first, second) = tokio::join!(
+        auth_server.verify_authentication(Request::new(answer.clone())),
+        auth_server.verify_authentication(Request::new(answer)),
+    );
+    assert!(first.is_ok());
+    assert_eq!(second.unwrap_err().code(), tonic::Code::NotFound);
+}
+
+#[tokio::test]
+async fn test_user_disabled_during_verification_gets_no_session() {
+    // one worker, kept busy until the user is disabled
+    let pool = CryptoPool::new(1, 8);
+    let auth_server = AuthServer::new(DataStore::new()).with_crypto_pool(pool.clone());
+    register_dummy(&auth_server);
+    let (auth_id, s) = answer_challenge(&auth_server, 42).await;
+    let (release, blocked) = std::sync::mpsc::channel::<()>();
+
+    let (_, answer, _) = tokio::join!(
+        pool.run(move || blocked.recv()),
+        auth_server.verify_authentication(Request::new(AuthAnswerRequest {
+            auth_id: auth_id.clone(),
+            s: s.to_str_radix(16),
+        })),
+        async {
+            // the answer passed the status check and waits for the pool
+            while auth_server.store.get_challenge(&auth_id).is_some() {
+                tokio::task::yield_now().await;
+            }
+            auth_server
+                .store
+                .set_user_status(&String::from("dummy"), UserStatus::Disabled);
+            release.send(()).unwrap();
+        },
+    );
+    let err = answer.unwrap_err();
+    assert_eq!(err.code(), tonic::Code::PermissionDenied);
+    assert_eq!(err.message(), "user is disabled");
+    assert!(auth_server.store.user_sessions("dummy").is_empty());
+    assert_eq!(
+        auth_server
+            .store
+            .get_user(&String::from("dummy"))
+            .unwrap()
+            .last_login_at,
+        None
+    );
+}
+
+#[tokio::test]
+async fn test_pending_challenges_capped_per_user() {
+    let clock = Arc::new(MockClock::default());
+    let auth_server = AuthServer::new(DataStore::with_clock(clock.clone()));
+    register_dummy(&auth_server);
+
+    // commitments are cheap to make and count towards the cap as well
+    let (auth_id, _) = answer_challenge(&auth_server, 100).await;
+    for _ in 1..MAX_PENDING_CHALLENGES {
+        commit(&auth_server, "dummy").await;
+    }
+    let err = auth_server
+        .commit_challenge(Request::new(ChallengeCommitRequest {
+            user: String::from("dummy"),
+        }))
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), tonic::Code::ResourceExhausted);
+
+    // cancelling one frees a slot
+    auth_server
+        .cancel_challenge(Request::new(CancelChallengeRequest { auth_id }))
+        .await
+        .unwrap();
+    commit(&auth_server, "dummy").await;
+
+    // so does letting them expire
+    let (r1, r2) = gen_params(&BigInt::from(200));
+    let request = AuthChallengeRequest {
+        user: String::from("dummy"),
+        r1,
+        r2,
+        ..Default::default()
+    };
+    let err = auth_server
+        .create_authentication_challenge(Request::new(request.clone()))
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), tonic::Code::ResourceExhausted);
+
+    clock.advance(CHALLENGE_TTL);
+    let (r1, r2) = gen_params(&BigInt::from(201));
+    assert!(auth_server
+        .create_authentication_challenge(Request::new(AuthChallengeRequest { r1, r2, ..request }))
+        .await
+        .is_ok());
+}
+
+fn register_dummy(auth_server: &AuthServer) {
+    auth_server.store.insert_user(User {
+        id: String::from("dummy"),
+        y1: PublicParams::g().modpow(&BigInt::from(12345), PublicParams::p()),
+        y2: PublicParams::h().modpow(&BigInt::from(12345), PublicParams::p()),
+        status: UserStatus::Active,
+        ..Default::default()
+    });
+}
+
+#[tokio::test]
+async fn test_verify_batch() {
+    let auth_server = AuthServer::new(DataStore::new());
+    register_dummy(&auth_server);
+
+    let mut items = Vec::new();
+    for k in 100..105 {
+        let (auth_id, s) = answer_challenge(&auth_server, k).await;
+        let challenge = auth_server.store.get_challenge(&auth_id).unwrap();
+        let user = auth_server.store.get_user(&challenge.user_id).unwrap();
+        items.push((user, challenge, s));
+    }
+    assert_eq!(verify_batch(&items, &OsRngProvider), vec![true; 5]);
+
+    // a single bad proof is pinpointed by the fallback
+    items[3].2 += BigInt::one();
+    assert_eq!(
+        verify_batch(&items, &OsRngProvider),
+        vec![true, true, true, false, true]
+    );
+}
+
+#[tokio::test]
+async fn test_verify_authentication_batch() {
+    let auth_server = AuthServer::new(DataStore::new());
+    register_dummy(&auth_server);
+
+    let mut answers = Vec::new();
+    for k in 200..203 {
+        let (auth_id, s) = answer_challenge(&auth_server, k).await;
+        answers.push(AuthAnswerRequest {
+            auth_id,
+            s: s.to_str_radix(16),
+        });
+    }
+    let (auth_id, s) = answer_challenge(&auth_server, 203).await;
+    answers.push(AuthAnswerRequest {
+        auth_id,
+        s: (s + BigInt::one()).to_str_radix(16),
+    });
+    answers.push(AuthAnswerRequest {
+        auth_id: String::from("unknown"),
+        s: String::from("1"),
+    });
+
+    let results = auth_server
+        .verify_authentication_batch(Request::new(AuthAnswerBatchRequest { answers }))
+        .await
+        .unwrap()
+        .into_inner()
+        .results;
+
+    let success: Vec<bool> = results.iter().map(|r| r.success).collect();
+    assert_eq!(success, vec![true, true, true, false, false]);
+    for result in &results[..3] {
+        assert!(auth_server.store.get_session(&result.session_id).is_some());
+    }
+    assert_eq!(results[3].error, "auth challenge failed");
+    assert_eq!(results[4].error, "challenge not found");
+}
+
+#[tokio::test]
+async fn test_batch_with_repeated_answer_starts_one_session() {
+    let auth_server = AuthServer::new(DataStore::new());
+    register_dummy(&auth_server);
+
+    let (auth_id, s) = answer_challenge(&auth_server, 300).await;
+    let answer = AuthAnswerRequest {
+        auth_id,
+        s: s.to_str_radix(16),
+    };
+    let results = auth_server
+        .verify_authentication_batch(Request::new(AuthAnswerBatchRequest {
+            answers: vec![answer.clone(), answer],
+        }))
+        .await
+        .unwrap()
+        .into_inner()
+        .results;
+
+    assert!(results[0].success);
+    assert!(!results[1].success);
+    assert!(results[1].session_id.is_empty());
+    assert_eq!(
+        results[1].error,
+        "auth_id appears more than once in the batch"
+    );
+    assert_eq!(auth_server.store.user_sessions("dummy").len(), 1);
+}
+
+#[tokio::test]
+async fn test_lockout_after_failed_logins() {
+    let clock = Arc::new(MockClock::default());
+    let auth_server =
+        AuthServer::new(DataStore::with_clock(clock.clone())).with_account_policy(AccountPolicy {
+            max_failed_logins: 2,
+            lockout: Duration::from_secs(60),
+            ..Default::default()
+        });
+    register_dummy(&auth_server);
+
+    for k in [42, 43] {
+        let (auth_id, s) = answer_challenge(&auth_server, k).await;
+        let status = auth_server
+            .verify_authentication(Request::new(AuthAnswerRequest {
+                auth_id,
+                s: (s + BigInt::one()).to_str_radix(16),
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(status.message(), "auth challenge failed");
+    }
+
+    let (r1, r2) = gen_params(&BigInt::from(44));
+    let request = AuthChallengeRequest {
+        user: String::from("dummy"),
+        r1,
+        r2,
+        ..Default::default()
+    };
+    let status = auth_server
+        .create_authentication_challenge(Request::new(request))
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), tonic::Code::PermissionDenied);
+    assert!(status.message().starts_with("user is locked until"));
+
+    // the lock runs out on its own and a good login clears it
+    clock.advance(Duration::from_secs(60));
+    answer(&auth_server, 45).await;
+    let user = auth_server.store.get_user(&String::from("dummy")).unwrap();
+    assert_eq!(user.status, UserStatus::Active);
+    assert_eq!(user.failed_logins, 0);
+    assert_eq!(user.last_login_at, Some(unix_seconds(clock.now())));
+}
+
+// Answers a challenge for `dummy` (x=12345) with nonce k
+async fn answer(auth_server: &AuthServer, k: u64) -> AuthAnswerResponse {
+    let (auth_id, s) = answer_challenge(auth_server, k).await;
+    auth_server
+        .verify_authentication(Request::new(AuthAnswerRequest {
+            auth_id,
+            s: s.to_str_radix(16),
+        }))
+        .await
+        .unwrap()
+        .into_inner()
+}
+
+#[tokio::test]
+async fn test_key_rotation_and_expiry() {
+    let day = Duration::from_secs(24 * 60 * 60);
+    let clock = Arc::new(MockClock::default());
+    let auth_server =
+        AuthServer::new(DataStore::with_clock(clock.clone())).with_account_policy(AccountPolicy {
+            key_rotation_age: Some(day * 10),
+            key_lifetime: Some(day * 20),
+            ..Default::default()
+        });
+    let (y1, y2) = gen_params(&BigInt::from(12345));
+    let register = RegisterRequest {
+        user: String::from("dummy"),
+        y1,
+        y2,
+    };
+    auth_server
+        .register(Request::new(register.clone()))
+        .await
+        .unwrap();
+    let user = auth_server.store.get_user(&String::from("dummy")).unwrap();
+    assert_eq!(user.created_at, unix_seconds(clock.now()));
+    assert_eq!(
+        user.key_expires_at,
+        Some(unix_seconds(clock.now()) + (day * 20).as_secs())
+    );
+
+    assert!(!answer(&auth_server, 42).await.key_rotation_required);
+    clock.advance(day * 10);
+    let login = answer(&auth_server, 43).await;
+    assert!(login.key_rotation_required);
+
+    // registering again is refused, the key is rotated on the session
+    let status = auth_server
+        .register(Request::new(register.clone()))
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), tonic::Code::AlreadyExists);
+    auth_server
+        .rotate_key(Request::new(RotateKeyRequest {
+            session_id: login.session_id,
+            y1: register.y1.clone(),
+            y2: register.y2.clone(),
+        }))
+        .await
+        .unwrap();
+    let rotated = auth_server.store.get_user(&String::from("dummy")).unwrap();
+    assert_eq!(rotated.created_at, user.created_at);
+    assert_eq!(rotated.key_created_at, unix_seconds(clock.now()));
+    assert!(!answer(&auth_server, 44).await.key_rotation_required);
+
+    clock.advance(day * 20);
+    let (r1, r2) = gen_params(&BigInt::from(45));
+    let status = auth_server
+        .create_authentication_challenge(Request::new(AuthChallengeRequest {
+            user: String::from("dummy"),
+            r1,
+            r2,
+            ..Default::default()
+        }))
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), tonic::Code::FailedPrecondition);
+    assert_eq!(
+        status.message(),
+        "key has expired, rotate it on a live session or ask an admin to delete the user"
+    );
+}
+
+#[tokio::test]
+async fn test_rotate_key_needs_unbound_session() {
+    let auth_server = AuthServer::new(DataStore::new());
+    register_dummy(&auth_server);
+    let (y1, y2) = gen_params(&BigInt::from(54321));
+    let rotate = |session_id: &str| RotateKeyRequest {
+        session_id: session_id.to_string(),
+        y1: y1.clone(),
+        y2: y2.clone(),
+    };
+
+    let status = auth_server
+        .rotate_key(Request::new(rotate("missing")))
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), tonic::Code::NotFound);
+
+    auth_server.store.insert_session(Session {
+        id: String::from("bound"),
+        user_id: String::from("dummy"),
+        key: None,
+        public_key: Some(BigInt::from(7)),
+        channel_binding: None,
+        audience: String::new(),
+        scopes: vec![],
+        roles: vec![],
+        attributes: Default::default(),
+    });
+    let status = auth_server
+        .rotate_key(Request::new(rotate("bound")))
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), tonic::Code::FailedPrecondition);
+
+    let session_id = answer(&auth_server, 42).await.session_id;
+    auth_server
+        .store
+        .set_user_status(&String::from("dummy"), UserStatus::Disabled);
+    let status = auth_server
+        .rotate_key(Request::new(rotate(&session_id)))
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), tonic::Code::PermissionDenied);
+
+    auth_server
+        .store
+        .set_user_status(&String::from("dummy"), UserStatus::Active);
+    auth_server
+        .rotate_key(Request::new(rotate(&session_id)))
+        .await
+        .unwrap();
+    let user = auth_server.store.get_user(&String::from("dummy")).unwrap();
+    assert_eq!(user.y1.to_str_radix(16), y1);
+}
+
+#[tokio::test]
+async fn test_pending_user_needs_approval() {
+    let auth_server = AuthServer::new(DataStore::new()).with_account_policy(AccountPolicy {
+        require_approval: true,
+        ..Default::default()
+    });
+    let status = login(&auth_server, 42).await.unwrap_err();
+    assert_eq!(status.code(), tonic::Code::FailedPrecondition);
+    assert_eq!(status.message(), "user is pending approval");
+
+    auth_server
+        .store
+        .set_user_status(&String::from("dummy"), UserStatus::Active);
+    answer(&auth_server, 43).await;
+}
+
+#[tokio::test]
+async fn test_scope_not_allowed_rejected() {
+    let auth_server = AuthServer::new(DataStore::new());
+    register_dummy(&auth_server);
+    allow_scopes(&auth_server, &["invoices:read"]);
+
+    let (r1, r2) = gen_params(&BigInt::from(42));
+    let err = auth_server
+        .create_authentication_challenge(Request::new(AuthChallengeRequest {
+            user: String::from("dummy"),
+            r1,
+            r2,
+            key_exchange: false,
+            session_public_key: String::new(),
+            channel_binding: false,
+            audience: String::from("billing"),
+            scopes: vec![
+                String::from("invoices:read"),
+                String::from("invoices:write"),
+            ],
+            commit_id: String::new(),
+            puzzle: None,
+            puzzle_solution: 0,
+        }))
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), tonic::Code::PermissionDenied);
+    assert_eq!(
+        err.message(),
+        "scope invoices:write is not allowed for user"
+    );
+}
+
+#[tokio::test]
+async fn test_validate_session() {
+    let clock = Arc::new(MockClock::default());
+    let auth_server = AuthServer::new(DataStore::with_clock(clock.clone()));
+    register_dummy(&auth_server);
+    auth_server
+        .store
+        .update_user(&String::from("dummy"), |user| {
+            user.roles = vec![String::from("admin")];
+            user.attributes
+                .insert(String::from("team"), String::from("payments"));
+        })
+        .unwrap();
+
+    let session_id = answer(&auth_server, 42).await.session_id;
+    // roles changed after login are not seen by the session
+    auth_server
+        .store
+        .update_user(&String::from("dummy"), |user| user.roles.clear())
+        .unwrap();
+
+    let session = auth_server
+        .validate_session(Request::new(ValidateSessionRequest {
+            session_id: session_id.clone(),
+        }))
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(session.user, "dummy");
+    assert_eq!(session.roles, vec![String::from("admin")]);
+    assert_eq!(session.attributes["team"], "payments");
+    assert_eq!(
+        session.expires_at,
+        unix_seconds(clock.now()) + SESSION_TTL.as_secs()
+    );
+    assert!(!session.channel_bound);
+    assert!(!session.key_exchange);
+
+    auth_server.store.remove_session(&session_id);
+    let err = auth_server
+        .validate_session(Request::new(ValidateSessionRequest { session_id }))
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), tonic::Code::NotFound);
+}
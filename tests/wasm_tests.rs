@@ -0,0 +1,109 @@
+// Runs under Node with
+// wasm-pack test --node -- --no-default-features --features constant-time,wasm
+#![cfg(all(feature = "wasm", target_arch = "wasm32"))]
+
+use common_lib::blind_auth_api::{
+    AuthAnswerRequest, AuthChallengeRequest, AuthChallengeResponse, RegisterRequest,
+};
+use common_lib::prover::{derive_secret_from_password, PASSWORD_ITERATIONS};
+use common_lib::public_params::PublicParams;
+use common_lib::rng::OsRngProvider;
+use common_lib::schnorr::KeyPair;
+use common_lib::transcript::{ChallengeContext, ChallengeTranscript};
+use common_lib::wasm::Prover;
+use num_bigint::BigInt;
+use num_traits::Num;
+use wasm_bindgen_test::wasm_bindgen_test;
+
+fn hex(value: &str) -> BigInt {
+    BigInt::from_str_radix(value, 16).unwrap()
+}
+
+// Challenge response the verifier holding key would send for request
+fn challenge(key: &KeyPair, request: &str, c: &BigInt) -> String {
+    let request: AuthChallengeRequest = serde_json::from_str(request).unwrap();
+    let (r1, r2) = (hex(&request.r1), hex(&request.r2));
+    let transcript = ChallengeTranscript {
+        user: &request.user,
+        auth_id: "a1",
+        r1: &r1,
+        r2: &r2,
+        c,
+        ephemeral: None,
+        session_public_key: None,
+        context: ChallengeContext {
+            audience: &request.audience,
+            scopes: &request.scopes,
+            issued_at: 0,
+            expires_at: u64::MAX,
+            channel_binding: None,
+        },
+    }
+    .hash();
+    let response = AuthChallengeResponse {
+        auth_id: "a1".to_string(),
+        c: c.to_str_radix(16),
+        verifier_signature: Some(key.sign(&transcript, &OsRngProvider).into()),
+        expires_at: u64::MAX,
+        ..Default::default()
+    };
+    serde_json::to_string(&response).unwrap()
+}
+
+#[wasm_bindgen_test]
+fn test_prover_answers_signed_challenge() {
+    let key = KeyPair::generate(&OsRngProvider);
+    let mut prover =
+        Prover::new("alice".to_string(), "3039", &key.public.to_str_radix(16)).unwrap();
+
+    let registration: RegisterRequest = serde_json::from_str(&prover.register_request()).unwrap();
+    let y1 = hex(&registration.y1);
+    assert_eq!(registration.user, "alice");
+
+    let request = prover.challenge_request(String::new(), vec![]);
+    let r1: AuthChallengeRequest = serde_json::from_str(&request).unwrap();
+    let r1 = hex(&r1.r1);
+    let c = BigInt::from(987654321);
+    let answer = prover
+        .answer_request(&challenge(&key, &request, &c))
+        .unwrap();
+    let answer: AuthAnswerRequest = serde_json::from_str(&answer).unwrap();
+    assert_eq!(answer.auth_id, "a1");
+
+    // r1 = g^s * y1^c (mod p)
+    let p = PublicParams::p();
+    let s = hex(&answer.s);
+    let expected = (PublicParams::g().modpow(&s, p) * y1.modpow(&c, p)) % p;
+    assert_eq!(expected, r1);
+}
+
+#[wasm_bindgen_test]
+fn test_prover_rejects_unsigned_challenge() {
+    let key = KeyPair::generate(&OsRngProvider);
+    let other = KeyPair::generate(&OsRngProvider);
+    let mut prover =
+        Prover::new("alice".to_string(), "3039", &key.public.to_str_radix(16)).unwrap();
+
+    let request =
+        prover.challenge_request("billing".to_string(), vec!["invoices:read".to_string()]);
+    let forged = challenge(&other, &request, &BigInt::from(5));
+    assert!(prover.answer_request(&forged).is_err());
+    // the nonce is gone with the rejected answer
+    let signed = challenge(&key, &request, &BigInt::from(5));
+    assert!(prover.answer_request(&signed).is_err());
+}
+
+#[wasm_bindgen_test]
+fn test_prover_from_password() {
+    let key = KeyPair::generate(&OsRngProvider);
+    let verifier_key = key.public.to_str_radix(16);
+    let prover = Prover::from_password("alice".to_string(), "hunter2", &verifier_key).unwrap();
+
+    // the same password registers the same key every time
+    let registration: RegisterRequest = serde_json::from_str(&prover.register_request()).unwrap();
+    let x = derive_secret_from_password("hunter2", "alice", PASSWORD_ITERATIONS);
+    let (y1, _) = common_lib::gen_params(&x);
+    assert_eq!(registration.y1, y1);
+
+    assert!(Prover::from_password("alice".to_string(), "", &verifier_key).is_err());
+}